@@ -0,0 +1,58 @@
+-- Local mirror of the Stripe objects from stripe_retypes.rs
+-- Each row keeps the queried fields as columns and the full record as json in `data`.
+
+CREATE TABLE IF NOT EXISTS prices (
+    id TEXT PRIMARY KEY NOT NULL,
+    product_id TEXT,
+    active INTEGER NOT NULL,
+    unit_amount INTEGER,
+    data TEXT NOT NULL -- DbPrice
+);
+
+CREATE TABLE IF NOT EXISTS products (
+    id TEXT PRIMARY KEY NOT NULL,
+    active INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    default_price_id TEXT,
+    updated INTEGER,
+    data TEXT NOT NULL -- DbProduct without default_price
+);
+
+CREATE TABLE IF NOT EXISTS customers (
+    id TEXT PRIMARY KEY NOT NULL,
+    email TEXT,
+    name TEXT,
+    created INTEGER,
+    data TEXT NOT NULL -- DbCustomer
+);
+
+CREATE TABLE IF NOT EXISTS checkout_sessions (
+    id TEXT PRIMARY KEY NOT NULL,
+    status TEXT,
+    payment_status TEXT NOT NULL,
+    customer_id TEXT,
+    customer_email TEXT,
+    amount_total INTEGER,
+    created INTEGER,
+    expires_at INTEGER,
+    data TEXT NOT NULL -- DbCheckoutSession without line_items
+);
+
+CREATE INDEX IF NOT EXISTS checkout_sessions_status ON checkout_sessions(status);
+
+CREATE TABLE IF NOT EXISTS checkout_session_items (
+    checkout_session_id TEXT NOT NULL REFERENCES checkout_sessions(id) ON DELETE CASCADE,
+    id TEXT NOT NULL,
+    position INTEGER NOT NULL,
+    price_id TEXT,
+    quantity INTEGER,
+    amount_total INTEGER NOT NULL,
+    data TEXT NOT NULL, -- DbCheckoutSessionItem
+    PRIMARY KEY (checkout_session_id, id)
+);
+
+-- Values of StripeData that aren't lists, like the shipping rate ids
+CREATE TABLE IF NOT EXISTS stripe_settings (
+    key TEXT PRIMARY KEY NOT NULL,
+    value TEXT NOT NULL
+);
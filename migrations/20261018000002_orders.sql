@@ -0,0 +1,15 @@
+-- Orders made from completed and paid checkout sessions, see orders.rs
+-- `id` gives the order number, `data` keeps the full Order with its items.
+
+CREATE TABLE IF NOT EXISTS orders (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    order_number TEXT UNIQUE,
+    checkout_session_id TEXT UNIQUE NOT NULL,
+    customer_email TEXT,
+    payment_status TEXT NOT NULL,
+    amount_total INTEGER NOT NULL,
+    created_at INTEGER NOT NULL,
+    data TEXT NOT NULL -- Order
+);
+
+CREATE INDEX IF NOT EXISTS orders_created_at ON orders(created_at);
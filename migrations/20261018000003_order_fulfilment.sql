@@ -0,0 +1,22 @@
+-- Fulfilment lifecycle of orders, see FulfilmentStatus in orders.rs
+
+ALTER TABLE orders ADD COLUMN fulfilment_status TEXT NOT NULL DEFAULT 'Paid';
+
+CREATE INDEX IF NOT EXISTS orders_fulfilment_status ON orders(fulfilment_status);
+
+-- Every status change of an order with who made it and when
+CREATE TABLE IF NOT EXISTS order_fulfilment_transitions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    order_id INTEGER NOT NULL REFERENCES orders(id) ON DELETE CASCADE,
+    from_status TEXT,
+    to_status TEXT NOT NULL,
+    actor TEXT NOT NULL,
+    note TEXT,
+    at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS order_fulfilment_transitions_order ON order_fulfilment_transitions(order_id);
+
+-- Orders made before this migration start their history as Paid
+INSERT INTO order_fulfilment_transitions (order_id, from_status, to_status, actor, note, at)
+SELECT id, NULL, 'Paid', 'stripe', NULL, created_at FROM orders;
@@ -0,0 +1,24 @@
+-- Stock levels of products with limited batches.
+-- Products without a row here aren't tracked and never sell out.
+
+CREATE TABLE IF NOT EXISTS inventory (
+    product_id TEXT PRIMARY KEY NOT NULL,
+    stock INTEGER NOT NULL, -- units on hand, reservations not subtracted
+    updated_at INTEGER NOT NULL
+);
+
+-- Stock held by checkout sessions.
+-- `reservation_key` is a pending key until the checkout session exists, then the checkout session id.
+-- Status is Reserved while the session is open, Released when it expired and Committed when it was paid.
+CREATE TABLE IF NOT EXISTS stock_reservations (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    reservation_key TEXT NOT NULL,
+    product_id TEXT NOT NULL,
+    quantity INTEGER NOT NULL,
+    status TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS stock_reservations_key ON stock_reservations(reservation_key);
+CREATE INDEX IF NOT EXISTS stock_reservations_product_status ON stock_reservations(product_id, status);
@@ -0,0 +1,18 @@
+-- Admin accounts and their login sessions, see auth.rs
+
+CREATE TABLE IF NOT EXISTS admins (
+    username TEXT PRIMARY KEY NOT NULL,
+    password_hash TEXT NOT NULL, -- argon2 PHC string
+    created_at INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL
+);
+
+-- Only the sha256 of the session token is stored, the token itself lives in the admin's cookie
+CREATE TABLE IF NOT EXISTS admin_sessions (
+    token_hash TEXT PRIMARY KEY NOT NULL,
+    username TEXT NOT NULL REFERENCES admins(username) ON DELETE CASCADE,
+    created_at INTEGER NOT NULL,
+    expires_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS admin_sessions_expires_at ON admin_sessions(expires_at);
@@ -0,0 +1,8 @@
+-- What Stripe had for each product at the last products_config.json refresh,
+-- the common ancestor of the three-way merge in products_config.rs
+
+CREATE TABLE IF NOT EXISTS products_config_base (
+    stripe_id TEXT PRIMARY KEY NOT NULL,
+    data TEXT NOT NULL, -- CfgProduct JSON
+    updated_at INTEGER NOT NULL
+);
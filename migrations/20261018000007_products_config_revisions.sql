@@ -0,0 +1,10 @@
+-- Every version of products_config.json written, so a bad refresh can be rolled back
+
+CREATE TABLE IF NOT EXISTS products_config_revisions (
+    revision INTEGER PRIMARY KEY AUTOINCREMENT,
+    created_at INTEGER NOT NULL,
+    actor TEXT NOT NULL, -- admin username, "startup" or "cli"
+    note TEXT NOT NULL,
+    products_count INTEGER NOT NULL,
+    data TEXT NOT NULL -- CfgProducts JSON
+);
@@ -35,19 +35,20 @@ use leptos_use::storage::*;
 use leptos_use::*;
 use log::*;
 
+mod admin;
 mod cart;
 mod chrome;
 mod pages;
 mod products;
 
+pub use admin::*;
 pub use cart::*;
 pub use chrome::*;
 pub use pages::*;
 pub use products::*;
 
 
-pub type AppStateDataRes = Resource<(), Result<AppState, ServerFnError>>;
-pub type StripeDataRes = Resource<(), Result<StripeData, ServerFnError>>;
+pub type CatalogRes = Resource<(), Result<Catalog, ServerFnError>>;
 // pub type CfgProductsRes = Resource<(), Result<CfgProducts, ServerFnError>>;
 // pub type CheckoutSessionRes = Resource<i64, Result<DbCheckoutSession, ServerFnError>>;
 pub type CheckoutSessionIdRes = String;
@@ -58,13 +59,8 @@ pub fn App() -> impl IntoView {
     // Provides context that manages stylesheets, titles, meta tags, etc.
     provide_meta_context();
 
-    let stripe_data: StripeDataRes =
-        create_resource(|| (), move |_| async { stripe_stater().await });
-    provide_context(stripe_data);
-
-    let app_state: AppStateDataRes =
-        create_resource(|| (), move |_| async { appstate_stater().await });
-    provide_context(app_state);
+    let catalog: CatalogRes = create_resource(|| (), move |_| async { fetch_catalog().await });
+    provide_context(catalog);
 
     let (current_page, set_current_page) = create_signal(CurrentPage::None);
     provide_context(current_page);
@@ -281,6 +277,24 @@ pub fn Routerer() -> impl IntoView {
                     }
                 }
             }/>
+            <Route path="/admin" view={
+                move || {
+                    const CURRENTPAGE: CurrentPage = CurrentPage::Admin;
+
+                    let setter = expect_context::<WriteSignal<CurrentPage>>();
+                    setter.update(|page: &mut CurrentPage| *page = CURRENTPAGE);
+                    view! {
+                        <Pager page=AdminPage currentpage=CURRENTPAGE/>
+                    }
+                }
+            }>
+                <Route path="" view=AdminOrdersPage/>
+                <Route path="products" view=AdminProductsPage/>
+                <Route path="customers" view=AdminCustomersPage/>
+                <Route path="checkout-sessions" view=AdminCheckoutSessionsPage/>
+                <Route path="sync" view=AdminSyncPage/>
+                <Route path="revisions" view=AdminRevisionsPage/>
+            </Route>
             <Route path="/cancel" view={
                 move || {
                     const CURRENTPAGE: CurrentPage = CurrentPage::None;
@@ -311,6 +325,7 @@ pub enum CurrentPage {
     ShoppingCart,
     VideoInstructions,
     VideoBlogs,
+    Admin,
 }
 
 #[component]
@@ -342,6 +357,7 @@ where
                             CurrentPage::VideoInstructions => {"pager-content-video-instructions"},
                             CurrentPage::VideoBlogs => {"pager-content-video-blogs"},
                             CurrentPage::ProductItemDetailsPage => {"pager-content-product-item-details"},
+                            CurrentPage::Admin => {"pager-content-admin"},
                         }
                     >{page()}</div>
                 </div>
@@ -0,0 +1,629 @@
+use super::CatalogRes;
+use crate::orders::{FulfilmentStatus, Order};
+use crate::stripe_retypes::*;
+use crate::*;
+use leptos::*;
+use leptos_router::*;
+
+/// Username of the logged in admin, None when not logged in
+pub type AdminRes = Resource<(), Result<Option<String>, ServerFnError>>;
+
+fn format_amount(amount: i64) -> String {
+    format!("${:.2}", amount as f64 / 100.0)
+}
+
+/// Formats seconds since the Unix epoch like "2024-10-18 09:30 UTC"
+fn format_time(at: i64) -> String {
+    // Civil date from days since 1970-01-01, see http://howardhinnant.github.io/date_algorithms.html
+    let (days, seconds) = (at.div_euclid(86400), at.rem_euclid(86400));
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60
+    )
+}
+
+fn format_optional_time(at: Option<i64>) -> String {
+    at.map(format_time).unwrap_or_default()
+}
+
+fn admin_error(err: ServerFnError) -> View {
+    view! { <p class="admin-error">{err.to_string()}</p> }.into_view()
+}
+
+/// Shell of the /admin pages. Shows the login form until an admin is logged in, the admin pages in its Outlet after.
+#[component]
+pub fn AdminPage() -> impl IntoView {
+    let admin: AdminRes = create_resource(|| (), |_| async { admin_whoami().await });
+    provide_context(admin);
+
+    let logout = create_action(move |_: &()| async move {
+        if let Err(err) = admin_logout().await {
+            leptos::logging::log!("Couldn't log out: {}", err);
+        }
+        admin.refetch();
+    });
+
+    view! {
+        <Suspense fallback=move || view! {"Loading..."}>
+            {move || match admin.get() {
+                None => view! { <p>"Loading..."</p> }.into_view(),
+                Some(Ok(Some(username))) => view! {
+                    <div class="admin">
+                        <nav class="admin-nav">
+                            <a href="/admin">"Orders"</a>
+                            <a href="/admin/products">"Products"</a>
+                            <a href="/admin/customers">"Customers"</a>
+                            <a href="/admin/checkout-sessions">"Checkout Sessions"</a>
+                            <a href="/admin/sync">"Sync"</a>
+                            <a href="/admin/revisions">"Catalog Revisions"</a>
+                            <span class="admin-username">{username}</span>
+                            <button on:click=move |_| logout.dispatch(())>"Log out"</button>
+                        </nav>
+                        <Outlet/>
+                    </div>
+                }.into_view(),
+                Some(Ok(None)) => view! { <AdminLogin/> }.into_view(),
+                Some(Err(err)) => admin_error(err),
+            }}
+        </Suspense>
+    }
+}
+
+#[component]
+pub fn AdminLogin() -> impl IntoView {
+    let admin = expect_context::<AdminRes>();
+
+    let (username, set_username) = create_signal(String::new());
+    let (password, set_password) = create_signal(String::new());
+
+    let login = create_action(move |(username, password): &(String, String)| {
+        let (username, password) = (username.clone(), password.clone());
+        async move {
+            let logged_in = admin_login(username, password).await;
+            if matches!(logged_in, Ok(true)) {
+                admin.refetch();
+            }
+            logged_in
+        }
+    });
+
+    let message = move || match login.value().get() {
+        Some(Ok(false)) => "Wrong username or password.".to_string(),
+        Some(Err(err)) => err.to_string(),
+        _ => String::new(),
+    };
+
+    view! {
+        <form class="admin-login" on:submit=move |ev: ev::SubmitEvent| {
+            ev.prevent_default();
+            login.dispatch((username.get(), password.get()));
+        }>
+            <h3>"Admin Login"</h3>
+            <input type="text" placeholder="Username" autocomplete="username"
+                prop:value=username
+                on:input=move |ev| set_username.set(event_target_value(&ev))
+            />
+            <input type="password" placeholder="Password" autocomplete="current-password"
+                prop:value=password
+                on:input=move |ev| set_password.set(event_target_value(&ev))
+            />
+            <button type="submit" disabled=move || login.pending().get()>"Log in"</button>
+            <p class="admin-error">{message}</p>
+        </form>
+    }
+}
+
+#[component]
+pub fn AdminOrdersPage() -> impl IntoView {
+    let orders = create_resource(|| (), |_| async { admin_orders().await });
+
+    view! {
+        <h3>"Orders"</h3>
+        <Transition fallback=move || view! {"Loading orders..."}>
+            {move || orders.get().map(|orders| match orders {
+                Ok(orders) if orders.is_empty() => view! { <p>"No orders yet."</p> }.into_view(),
+                Ok(orders) => view! {
+                    <table class="admin-table">
+                        <tr>
+                            <th>"Order"</th>
+                            <th>"Placed"</th>
+                            <th>"Customer"</th>
+                            <th>"Items"</th>
+                            <th>"Total"</th>
+                            <th>"Status"</th>
+                            <th>"Fulfilment"</th>
+                        </tr>
+                        {orders.into_iter().map(|order| view! { <AdminOrderRow order=order/> }).collect_view()}
+                    </table>
+                }.into_view(),
+                Err(err) => admin_error(err),
+            })}
+        </Transition>
+    }
+}
+
+/// An order with buttons moving it to the statuses it can go to next
+#[component]
+pub fn AdminOrderRow(order: Order) -> impl IntoView {
+    let (order, set_order) = create_signal(order);
+
+    let (note, set_note) = create_signal(String::new());
+    let (carrier, set_carrier) = create_signal(String::new());
+    let (tracking_number, set_tracking_number) = create_signal(String::new());
+
+    let update = create_action(move |to: &FulfilmentStatus| {
+        let to = *to;
+        let order_number = order.get_untracked().order_number;
+        let non_empty = |value: String| Some(value).filter(|value| !value.trim().is_empty());
+        let note = non_empty(note.get_untracked());
+        let carrier = non_empty(carrier.get_untracked());
+        let tracking_number = non_empty(tracking_number.get_untracked());
+        async move {
+            let updated =
+                update_order_fulfilment(order_number, to, note, carrier, tracking_number).await?;
+            set_order.set(updated);
+            set_note.set(String::new());
+            Ok::<_, ServerFnError>(())
+        }
+    });
+
+    view! {
+        <tr>
+            <td>{move || order.get().order_number}</td>
+            <td>{move || format_optional_time(order.get().placed_at)}</td>
+            <td>
+                {move || order.get().customer_name.unwrap_or_default()}
+                <br/>
+                {move || order.get().customer_email.unwrap_or_default()}
+            </td>
+            <td>
+                {move || order.get().items.into_iter().map(|item| view! {
+                    <div>{item.name}" x"{item.quantity}</div>
+                }).collect_view()}
+            </td>
+            <td>{move || format_amount(order.get().amount_total)}</td>
+            <td>
+                {move || order.get().fulfilment_status.label()}
+                {move || order.get().shipping_address.and_then(|shipping| shipping.carrier).map(|carrier| view! {
+                    <div>"Carrier: "{carrier}</div>
+                })}
+            </td>
+            <td class="admin-fulfilment">
+                <Show when=move || !order.get().fulfilment_status.next_statuses().is_empty() fallback=|| view! {}>
+                    <input type="text" placeholder="Note" prop:value=note on:input=move |ev| set_note.set(event_target_value(&ev))/>
+                    <Show when=move || order.get().fulfilment_status.can_transition_to(FulfilmentStatus::OutForDelivery) fallback=|| view! {}>
+                        <input type="text" placeholder="Carrier" prop:value=carrier on:input=move |ev| set_carrier.set(event_target_value(&ev))/>
+                        <input type="text" placeholder="Tracking number" prop:value=tracking_number on:input=move |ev| set_tracking_number.set(event_target_value(&ev))/>
+                    </Show>
+                    {move || order.get().fulfilment_status.next_statuses().iter().map(|&to| view! {
+                        <button disabled=move || update.pending().get() on:click=move |_| update.dispatch(to)>
+                            {to.label()}
+                        </button>
+                    }).collect_view()}
+                </Show>
+                {move || match update.value().get() {
+                    Some(Err(err)) => admin_error(err),
+                    _ => view! {}.into_view(),
+                }}
+            </td>
+        </tr>
+    }
+}
+
+#[component]
+pub fn AdminProductsPage() -> impl IntoView {
+    let catalog = expect_context::<CatalogRes>();
+
+    view! {
+        <h3>"Products"</h3>
+        <p>"Setting stock changes the units on hand, available is what is left after open checkouts. Leave it empty to stop tracking stock of a product."</p>
+        <Transition fallback=move || view! {"Loading products..."}>
+            {move || catalog.get().map(|catalog| match catalog {
+                Ok(catalog) => view! {
+                    <table class="admin-table">
+                        <tr>
+                            <th>"Product"</th>
+                            <th>"Category"</th>
+                            <th>"Price"</th>
+                            <th>"Available"</th>
+                            <th>"Stock"</th>
+                        </tr>
+                        {catalog.products.0.into_iter().map(|product| {
+                            let available = catalog.available_stock.get(&product.stripe_id).copied();
+                            view! { <AdminProductRow product=product available=available/> }
+                        }).collect_view()}
+                    </table>
+                }.into_view(),
+                Err(err) => admin_error(err),
+            })}
+        </Transition>
+    }
+}
+
+#[component]
+pub fn AdminProductRow(product: CfgProduct, available: Option<i64>) -> impl IntoView {
+    let catalog = expect_context::<CatalogRes>();
+
+    let product_id = product.stripe_id.clone();
+    let (stock, set_stock) = create_signal(String::new());
+
+    let set = create_action(move |stock: &String| {
+        let product_id = product_id.clone();
+        let stock = stock.trim().to_string();
+        async move {
+            let stock = match stock.is_empty() {
+                true => None,
+                false => match stock.parse::<i64>() {
+                    Ok(stock) => Some(stock),
+                    Err(_) => {
+                        return Err(ServerFnError::ServerError(
+                            "Stock has to be a whole number".into(),
+                        ))
+                    }
+                },
+            };
+            set_product_stock(product_id, stock).await?;
+            catalog.refetch();
+            Ok::<_, ServerFnError>(())
+        }
+    });
+
+    view! {
+        <tr>
+            <td>{product.name.clone()}</td>
+            <td>{product.metadata.as_ref().and_then(|metadata| metadata.get("category").cloned()).unwrap_or_default()}</td>
+            <td>{product.price.as_ref().and_then(|price| price.unit_amount).map(format_amount).unwrap_or_default()}</td>
+            <td>{available.map_or("Not tracked".to_string(), |available| available.to_string())}</td>
+            <td>
+                <input type="number" min="0" prop:value=stock on:input=move |ev| set_stock.set(event_target_value(&ev))/>
+                <button disabled=move || set.pending().get() on:click=move |_| set.dispatch(stock.get())>"Set"</button>
+                {move || match set.value().get() {
+                    Some(Err(err)) => admin_error(err),
+                    _ => view! {}.into_view(),
+                }}
+            </td>
+        </tr>
+    }
+}
+
+#[component]
+pub fn AdminCustomersPage() -> impl IntoView {
+    let customers = create_resource(|| (), |_| async { admin_customers().await });
+
+    view! {
+        <h3>"Customers"</h3>
+        <Transition fallback=move || view! {"Loading customers..."}>
+            {move || customers.get().map(|customers| match customers {
+                Ok(customers) => view! {
+                    <table class="admin-table">
+                        <tr>
+                            <th>"Name"</th>
+                            <th>"Email"</th>
+                            <th>"Phone"</th>
+                            <th>"Created"</th>
+                            <th>"Id"</th>
+                        </tr>
+                        {customers.into_iter().map(|customer| view! {
+                            <tr>
+                                <td>{customer.name.unwrap_or_default()}</td>
+                                <td>{customer.email.unwrap_or_default()}</td>
+                                <td>{customer.phone.unwrap_or_default()}</td>
+                                <td>{format_optional_time(customer.created)}</td>
+                                <td>{customer.id}</td>
+                            </tr>
+                        }).collect_view()}
+                    </table>
+                }.into_view(),
+                Err(err) => admin_error(err),
+            })}
+        </Transition>
+    }
+}
+
+#[component]
+pub fn AdminCheckoutSessionsPage() -> impl IntoView {
+    let checkout_sessions = create_resource(|| (), |_| async { admin_checkout_sessions().await });
+
+    // None shows sessions of every status
+    let (status, set_status) = create_signal(None::<DbCheckoutSessionStatus>);
+    let filters = [
+        ("All", None),
+        ("Open", Some(DbCheckoutSessionStatus::Open)),
+        ("Complete", Some(DbCheckoutSessionStatus::Complete)),
+        ("Expired", Some(DbCheckoutSessionStatus::Expired)),
+    ];
+
+    view! {
+        <h3>"Checkout Sessions"</h3>
+        <div class="admin-filters">
+            {filters.into_iter().map(|(label, filter)| {
+                let is_current = {
+                    let filter = filter.clone();
+                    move || status.get() == filter
+                };
+                view! {
+                    <button class:current=is_current on:click=move |_| set_status.set(filter.clone())>
+                        {label}
+                    </button>
+                }
+            }).collect_view()}
+        </div>
+        <Transition fallback=move || view! {"Loading checkout sessions..."}>
+            {move || checkout_sessions.get().map(|checkout_sessions| match checkout_sessions {
+                Ok(checkout_sessions) => view! {
+                    <table class="admin-table">
+                        <tr>
+                            <th>"Created"</th>
+                            <th>"Status"</th>
+                            <th>"Payment"</th>
+                            <th>"Customer"</th>
+                            <th>"Total"</th>
+                            <th>"Expires"</th>
+                            <th>"Id"</th>
+                        </tr>
+                        {checkout_sessions.into_iter()
+                            .filter(|checkout_session| status.get().is_none_or(|status| checkout_session.status == Some(status)))
+                            .map(|checkout_session| view! {
+                                <tr>
+                                    <td>{format_optional_time(checkout_session.created)}</td>
+                                    <td>{checkout_session.status.map(|status| format!("{:?}", status)).unwrap_or_default()}</td>
+                                    <td>{format!("{:?}", checkout_session.payment_status)}</td>
+                                    <td>
+                                        {checkout_session.customer_details
+                                            .and_then(|customer_details| customer_details.email)
+                                            .or(checkout_session.customer_email)
+                                            .unwrap_or_default()}
+                                    </td>
+                                    <td>{checkout_session.amount_total.map(format_amount).unwrap_or_default()}</td>
+                                    <td>{format_optional_time(checkout_session.expires_at)}</td>
+                                    <td>{checkout_session.id}</td>
+                                </tr>
+                            })
+                            .collect_view()}
+                    </table>
+                }.into_view(),
+                Err(err) => admin_error(err),
+            })}
+        </Transition>
+    }
+}
+
+#[component]
+pub fn AdminSyncPage() -> impl IntoView {
+    let catalog = expect_context::<CatalogRes>();
+    let sync_status = create_resource(|| (), |_| async { admin_sync_status().await });
+
+    let sync = create_action(move |_: &()| async move {
+        let synced = stripe_sync().await.map(|_| ());
+        sync_status.refetch();
+        catalog.refetch();
+        synced
+    });
+    // Report of the last products refresh or preview
+    let report = create_rw_signal(None::<String>);
+    let refresh = create_action(move |rewrite: &bool| {
+        let rewrite = *rewrite;
+        async move {
+            let refreshed = refresh_local_product_info(rewrite).await;
+            if let Ok(diff) = &refreshed {
+                report.set(Some(diff.clone()));
+            }
+            sync_status.refetch();
+            catalog.refetch();
+            refreshed.map(|_| ())
+        }
+    });
+    let preview = create_action(move |rewrite: &bool| {
+        let rewrite = *rewrite;
+        async move {
+            let previewed = preview_local_product_info(rewrite).await;
+            if let Ok(diff) = &previewed {
+                report.set(Some(format!("Preview, nothing was written yet:\n{}", diff)));
+            }
+            previewed.map(|_| ())
+        }
+    });
+    // Pushing is only offered after a preview showed what it changes in Stripe, and pushes just that
+    let push_planned = create_rw_signal(None);
+    let preview_push = create_action(move |_: &()| async move {
+        let previewed = preview_push_products().await;
+        if let Ok(plan) = &previewed {
+            report.set(Some(format!("Preview, nothing was pushed yet:\n{}", plan)));
+            push_planned.set((!plan.is_empty()).then(|| plan.clone()));
+        }
+        previewed.map(|_| ())
+    });
+    let push = create_action(move |plan: &crate::products_config::push::PushPlan| {
+        let plan = plan.clone();
+        async move {
+            push_planned.set(None);
+            let pushed = push_products(plan).await;
+            if let Ok(push_report) = &pushed {
+                report.set(Some(push_report.clone()));
+            }
+            sync_status.refetch();
+            catalog.refetch();
+            pushed.map(|_| ())
+        }
+    });
+    let is_pending = move || {
+        sync.pending().get()
+            || refresh.pending().get()
+            || preview.pending().get()
+            || preview_push.pending().get()
+            || push.pending().get()
+    };
+
+    view! {
+        <h3>"Sync"</h3>
+        <div class="admin-filters">
+            <button disabled=is_pending on:click=move |_| sync.dispatch(())>"Sync with Stripe"</button>
+            <button disabled=is_pending on:click=move |_| preview.dispatch(false)>"Preview merge"</button>
+            <button disabled=is_pending on:click=move |_| refresh.dispatch(false)>"Merge products from Stripe"</button>
+            <button disabled=is_pending on:click=move |_| preview.dispatch(true)>"Preview rewrite"</button>
+            <button disabled=is_pending on:click=move |_| refresh.dispatch(true)>"Rewrite products from Stripe"</button>
+            <button disabled=is_pending on:click=move |_| preview_push.dispatch(())>"Preview push to Stripe"</button>
+            {move || push_planned.get().map(|plan| view! {
+                <button disabled=is_pending on:click=move |_| push.dispatch(plan.clone())>"Push previewed changes to Stripe"</button>
+            })}
+        </div>
+        {move || match (sync.value().get(), refresh.value().get(), preview.value().get()) {
+            (Some(Err(err)), _, _) | (_, Some(Err(err)), _) | (_, _, Some(Err(err))) => admin_error(err),
+            _ => view! {}.into_view(),
+        }}
+        {move || match (preview_push.value().get(), push.value().get()) {
+            (Some(Err(err)), _) | (_, Some(Err(err))) => admin_error(err),
+            _ => view! {}.into_view(),
+        }}
+        {move || report.get().map(|report| view! { <pre class="admin-report">{report}</pre> })}
+        <Transition fallback=move || view! {"Loading sync status..."}>
+            {move || sync_status.get().map(|sync_status| match sync_status {
+                Ok(sync_status) => view! {
+                    <p>
+                        {match sync_status.stripe_status {
+                            StripeStatus::Live => "Stripe is reachable.",
+                            StripeStatus::Degraded => "Stripe can't be reached, the shop runs on stored data and checkout is unavailable.",
+                        }}
+                    </p>
+                    <table class="admin-table">
+                        <tr>
+                            <th>"Started"</th>
+                            <th>"Kind"</th>
+                            <th>"By"</th>
+                            <th>"Took"</th>
+                            <th>"Result"</th>
+                        </tr>
+                        {sync_status.sync_reports.into_iter().map(|sync_report| view! {
+                            <tr class:admin-error=sync_report.error.is_some()>
+                                <td>{format_time(sync_report.started_at)}</td>
+                                <td>
+                                    {match sync_report.kind {
+                                        SyncKind::StripeSync => "Stripe sync",
+                                        SyncKind::ProductsRefresh => "Products refresh",
+                                        SyncKind::CatalogReload => "Catalog reload",
+                                        SyncKind::ProductsPush => "Products push",
+                                    }}
+                                </td>
+                                <td>{sync_report.actor}</td>
+                                <td>{format!("{}s", sync_report.finished_at - sync_report.started_at)}</td>
+                                <td>{sync_report.error.or(sync_report.summary).unwrap_or_default()}</td>
+                            </tr>
+                        }).collect_view()}
+                    </table>
+                }.into_view(),
+                Err(err) => admin_error(err),
+            })}
+        </Transition>
+    }
+}
+
+#[component]
+pub fn AdminRevisionsPage() -> impl IntoView {
+    let catalog = expect_context::<CatalogRes>();
+    let revisions = create_resource(|| (), |_| async { products_config_revisions().await });
+
+    // Report of the last comparison or rollback
+    let report = create_rw_signal(None::<String>);
+    let diff = create_action(move |(from, to): &(i64, i64)| {
+        let (from, to) = (*from, *to);
+        async move {
+            let diffed = diff_products_config_revisions(from, to).await;
+            if let Ok(diff) = &diffed {
+                report.set(Some(format!(
+                    "Changes from #{} to #{}:\n{}",
+                    from, to, diff
+                )));
+            }
+            diffed.map(|_| ())
+        }
+    });
+    let rollback = create_action(move |revision: &i64| {
+        let revision = *revision;
+        async move {
+            let rolled_back = rollback_products_config(revision).await;
+            if let Ok(new_revision) = &rolled_back {
+                report.set(Some(format!(
+                    "Rolled back to #{} as revision #{}.",
+                    revision, new_revision
+                )));
+            }
+            revisions.refetch();
+            catalog.refetch();
+            rolled_back.map(|_| ())
+        }
+    });
+    let is_pending = move || diff.pending().get() || rollback.pending().get();
+
+    view! {
+        <h3>"Catalog Revisions"</h3>
+        {move || match (diff.value().get(), rollback.value().get()) {
+            (Some(Err(err)), _) | (_, Some(Err(err))) => admin_error(err),
+            _ => view! {}.into_view(),
+        }}
+        {move || report.get().map(|report| view! { <pre class="admin-report">{report}</pre> })}
+        <Transition fallback=move || view! {"Loading revisions..."}>
+            {move || revisions.get().map(|revisions| match revisions {
+                Ok(revisions) => {
+                    // Newest first, so the previous revision of each is the next one in the list
+                    let previous: Vec<Option<i64>> = revisions
+                        .iter()
+                        .skip(1)
+                        .map(|revision| Some(revision.revision))
+                        .chain([None])
+                        .collect();
+                    view! {
+                        <table class="admin-table">
+                            <tr>
+                                <th>"#"</th>
+                                <th>"Created"</th>
+                                <th>"By"</th>
+                                <th>"Products"</th>
+                                <th>"Note"</th>
+                                <th></th>
+                            </tr>
+                            {revisions.into_iter().zip(previous).enumerate().map(|(index, (revision, previous))| {
+                                let number = revision.revision;
+                                view! {
+                                    <tr>
+                                        <td>{number}</td>
+                                        <td>{format_time(revision.created_at)}</td>
+                                        <td>{revision.actor}</td>
+                                        <td>{revision.products_count}</td>
+                                        <td>{revision.note}</td>
+                                        <td>
+                                            {previous.map(|previous| view! {
+                                                <button disabled=is_pending on:click=move |_| diff.dispatch((previous, number))>
+                                                    "Changes"
+                                                </button>
+                                            })}
+                                            {(index > 0).then(|| view! {
+                                                <button disabled=is_pending on:click=move |_| rollback.dispatch(number)>
+                                                    "Roll back to this"
+                                                </button>
+                                            })}
+                                        </td>
+                                    </tr>
+                                }
+                            }).collect_view()}
+                        </table>
+                    }.into_view()
+                }
+                Err(err) => admin_error(err),
+            })}
+        </Transition>
+    }
+}
@@ -1,28 +1,61 @@
-use super::{AppStateDataRes, CheckoutSessionIdRes, CheckoutSessionUpdateRes, StripeDataRes};
+use super::{CatalogRes, CheckoutSessionIdRes, CheckoutSessionUpdateRes};
 use crate::*;
 use leptos::*;
 
 #[component]
 pub fn SuccessCheckout() -> impl IntoView {
-    let checkout_sessionid = expect_context::<Signal<CheckoutSessionIdRes>>();
-    provide_context(checkout_sessionid);
+    // Stripe redirects here with ?session_id={CHECKOUT_SESSION_ID}
+    let query = leptos_router::use_query_map();
+    let session_id = move || query.with(|query| query.get("session_id").cloned());
+
+    let order = create_resource(session_id, |session_id| async move {
+        match session_id {
+            Some(session_id) => checkout_order(session_id).await,
+            None => Ok(None),
+        }
+    });
+
+    let format_amount = |amount: i64| format!("${:.2}", amount as f64 / 100.0);
 
     view! {
-        <p>
-            "Checkout session with id"
-            {checkout_sessionid()}
-            " completed successfully!"
-        </p>
-        <p>
-            "You should find details of your order in your email soon."
-        </p>
+        <Suspense fallback=move || view! {"Loading order..."}>
+            {move || match order.get() {
+                None => view! { <p>"Loading..."</p> }.into_view(),
+                Some(Ok(Some(order))) => view! {
+                    <div class="order-summary">
+                        <h3>"Thank you! Your order " {order.order_number.clone()} " is confirmed."</h3>
+                        <p>"Status: "{order.fulfilment_status.label()}</p>
+                        <ul class="order-items-ul">
+                            {order.items.iter().map(|item| view! {
+                                <li>
+                                    {item.name.clone()}", quantity: "{item.quantity}
+                                    " "{format_amount(item.amount_total)}
+                                </li>
+                            }).collect::<Vec<_>>()}
+                        </ul>
+                        <p>"Shipping: "{format_amount(order.amount_shipping)}</p>
+                        <p>"Total: "{format_amount(order.amount_total)}" "{order.currency.to_uppercase()}</p>
+                        <p>"You should find details of your order in your email soon."</p>
+                    </div>
+                }.into_view(),
+                Some(Ok(None)) => view! {
+                    <p>"We haven't received your payment yet. Your order will show up here once it is confirmed."</p>
+                }.into_view(),
+                Some(Err(err)) => {
+                    leptos::logging::log!("Couldn't load order: {}", err);
+                    view! {
+                        <p>"Couldn't load your order. You should find details of your order in your email soon."</p>
+                    }.into_view()
+                }
+            }}
+        </Suspense>
     }
 }
 
 #[component]
 pub fn CancelCheckout() -> impl IntoView {
-    let stripe_data = expect_context::<StripeDataRes>();
-    provide_context(stripe_data);
+    let catalog = expect_context::<CatalogRes>();
+    provide_context(catalog);
 
     let shopping_cart = expect_context::<Signal<ShoppingCart>>();
     provide_context(shopping_cart);
@@ -105,11 +138,8 @@ pub fn CancelCheckout() -> impl IntoView {
 
 #[component]
 pub fn ShoppingCartPage() -> impl IntoView {
-    let stripe_data = expect_context::<StripeDataRes>();
-    provide_context(stripe_data);
-
-    let app_state = expect_context::<AppStateDataRes>();
-    provide_context(app_state);
+    let catalog = expect_context::<CatalogRes>();
+    provide_context(catalog);
 
     let shopping_cart = expect_context::<Signal<ShoppingCart>>();
     provide_context(shopping_cart);
@@ -130,6 +160,14 @@ pub fn ShoppingCartPage() -> impl IntoView {
     let checkout_sessionid = expect_context::<Signal<CheckoutSessionIdRes>>();
     provide_context(checkout_sessionid);
 
+    // Checkout is off while the server runs in degraded mode without Stripe
+    let is_checkout_available = move || {
+        catalog
+            .get()
+            .and_then(|catalog| catalog.ok())
+            .is_none_or(|catalog| catalog.checkout_available)
+    };
+
     // let checkout_session = expect_context::<CheckoutSessionRes>();
     // provide_context(checkout_session);
 
@@ -179,29 +217,44 @@ pub fn ShoppingCartPage() -> impl IntoView {
                                 let (product_id, _) = create_signal(product_id.clone());
                                 provide_context(product_id);
 
-                                let product_name = if let Some(product) = stripe_data
+                                let product = catalog
                                     .get()
                                     .unwrap()
                                     .unwrap()
                                     .products
+                                    .0
                                     .into_iter()
-                                    .find(|x| x.id == product_id.get())
-                                {
-                                    create_signal(product.name).0
+                                    .find(|x| x.stripe_id == product_id.get());
+                                let unit_price = product
+                                    .as_ref()
+                                    .and_then(|product| product.unit_price())
+                                    .map(|unit_price| unit_price.to_string());
+                                let product_name = if let Some(product) = product {
+                                    create_signal(product.display_name()).0
                                 } else {
                                     set_shopping_cart.update(|s| s.remove_single_product(&product_id.get()));
                                     create_signal("Product".to_string()).0
                                 };
 
                                 provide_context(product_name);
+
+                                let cart_limit = move || {
+                                    catalog
+                                        .get()
+                                        .and_then(|catalog| catalog.ok())
+                                        .map_or(20, |catalog| catalog.cart_limit(&product_id.get(), 20))
+                                };
                                 view! {
                                     <li>
                                         <p>
                                             {product_name.get()}", quantity: "{quantity}
                                         </p>
+                                        <p class="shopping-cart-unit-price">
+                                            {unit_price}
+                                        </p>
                                         <div>
                                             <Show
-                                                when=move || {quantity < 20}
+                                                when=move || {quantity < cart_limit()}
                                                 fallback=move || view! {
                                                     <button class="plus_one_product_amount">
                                                     "MAX"
@@ -210,7 +263,7 @@ pub fn ShoppingCartPage() -> impl IntoView {
                                             >
                                                 <button class="plus_one_product_amount" on:click=move |_| {
                                                     set_shopping_cart.update(|s| {
-                                                        s.add_single_product(&product_id.get(), 20);
+                                                        s.add_single_product(&product_id.get(), cart_limit());
                                                     });
                                                 }>
                                                 "+"
@@ -235,12 +288,15 @@ pub fn ShoppingCartPage() -> impl IntoView {
                     }
                 </ul>
                 <div class="shopping-cart-ceckout-section">
-                    <button class="checkout-button" on:click=move |_| {
+                    <Show when=move || !is_checkout_available() fallback=|| view!{}>
+                        <p class="checkout-unavailable">
+                            "Checkout is temporarily unavailable. Please try again later."
+                        </p>
+                    </Show>
+                    <button class="checkout-button" disabled=move || !is_checkout_available() on:click=move |_| {
                             let checkout_sessionid_before = checkout_sessionid.get();
 
                             spawn_local(async move {
-                                stripe_sync().await;
-
                                 new_checkout_session(shopping_cart.get().0, checkout_sessionid.get()).await;
                             });
 
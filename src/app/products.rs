@@ -1,4 +1,4 @@
-use super::AppStateDataRes;
+use super::CatalogRes;
 use crate::*;
 use leptos::*;
 use leptos_use::*;
@@ -57,12 +57,43 @@ pub fn CfgProductItem(product: CfgProduct) -> impl IntoView {
     let set_shopping_cart = expect_context::<WriteSignal<ShoppingCart>>();
     provide_context(set_shopping_cart);
 
+    let catalog = expect_context::<CatalogRes>();
+    let is_sold_out = move || {
+        catalog
+            .get()
+            .and_then(|catalog| catalog.ok())
+            .is_some_and(|catalog| catalog.is_sold_out(&product.get().stripe_id))
+    };
+    let cart_limit = move || {
+        catalog
+            .get()
+            .and_then(|catalog| catalog.ok())
+            .map_or(20, |catalog| {
+                catalog.cart_limit(&product.get().stripe_id, 20)
+            })
+    };
+    let thumbnail_size = move || {
+        let product = product.get();
+        product
+            .thumbnail()
+            .and_then(|thumbnail| product.local_image_size(thumbnail))
+            .cloned()
+    };
+    // Variants share one card, the options are chosen on the product page
+    let variants = move || {
+        catalog
+            .get()
+            .and_then(|catalog| catalog.ok())
+            .map_or(vec![product.get()], |catalog| {
+                catalog.products.variants_of(&product.get())
+            })
+    };
+    let has_options = move || variants().len() > 1;
+    let page_href = move || format!("/shop/products/:{:#}", product.get().page_slug());
+
     view! {
         <div class="product-item-container">
-            <a href=move || {
-                let product_name = product.get().name.to_lowercase().replace(" ", "-");
-                format!("/shop/products/:{:#}", product_name)
-            }>
+            <a href=page_href>
                 <Show
                     when=move || {product.get().images.is_some_and(|x| !x.is_empty())}
                     fallback=move || {view!{
@@ -70,73 +101,134 @@ pub fn CfgProductItem(product: CfgProduct) -> impl IntoView {
                         </div>
                     }}
                 >
-                    <img class="product-item-image" src=move || {
-                        let image_path = product
-                            .get()
-                            .local_images
-                            .as_ref()
-                            .expect("No local images for CfgProduct!")
-                            .iter()
-                            .find(|image_path| image_path.file_name().map_or(false, |name| name == "thumbnail.webp"))
-                            .map(|image_pathbuf| image_pathbuf.to_string_lossy().to_string()) // Convert to owned String
-                            .unwrap_or_else(|| {
-                                error!(
-                                    "Couldn't find thumbnail.webp for product! {}",
-                                    product.get().item_number.expect("No item_number of CfgProduct!")
-                                );
-                                "no_image_for_cfgproduct.webp".to_string() // Return owned String
-                            });
-                        image_path
-                    }/>
+                    <img
+                        class="product-item-image"
+                        src=move || {
+                            let image_path = product
+                                .get()
+                                .thumbnail()
+                                .map(|image_pathbuf| image_pathbuf.to_string_lossy().to_string()) // Convert to owned String
+                                .unwrap_or_else(|| {
+                                    error!(
+                                        "Couldn't find thumbnail.webp for product! {:?}",
+                                        product.get().item_number
+                                    );
+                                    "no_image_for_cfgproduct.webp".to_string() // Return owned String
+                                });
+                            image_path
+                        }
+                        // sizes of the image pipeline, see images.rs
+                        srcset=move || thumbnail_size().map(|local_image| local_image.srcset())
+                        sizes="15rem"
+                        width=move || thumbnail_size().map(|local_image| local_image.width.to_string())
+                        height=move || thumbnail_size().map(|local_image| local_image.height.to_string())
+                        alt=move || {
+                            let product = product.get();
+                            product
+                                .thumbnail()
+                                .map_or(product.name.clone(), |thumbnail| product.image_alt(thumbnail))
+                        }
+                    />
                 </Show>
                 <div class="product-info">
                     <strong class="product-item-name">
-                        {product.get().name}
+                        {move || match product.get().variant {
+                            Some(variant) if has_options() => variant.parent,
+                            _ => product.get().name,
+                        }}
                     </strong>
+                    <span class="product-item-unit-price">
+                        {move || product.get().unit_price().map(|unit_price| unit_price.to_string())}
+                    </span>
                 </div>
             </a>
-            <button class="product-item-addtocart-button" on:click=move |_| {
-                set_shopping_cart.update(|s| {
-                    s.add_single_product(&product.get().stripe_id, 20);
-                });
-            }>
-            "Add To Cart $"{product.get().price.unwrap().unit_amount.unwrap() / 100}
-            </button>
+            <Show
+                when=move || !has_options()
+                fallback=move || {
+                    let lowest_price = variants()
+                        .iter()
+                        .filter_map(|variant| variant.price.as_ref()?.unit_amount)
+                        .min()
+                        .unwrap_or_default();
+                    view! {
+                        <a class="product-item-addtocart-button" href=page_href>
+                            {format!("Choose Options from ${}", lowest_price / 100)}
+                        </a>
+                    }
+                }
+            >
+                <button class="product-item-addtocart-button" disabled=is_sold_out on:click=move |_| {
+                    set_shopping_cart.update(|s| {
+                        s.add_single_product(&product.get().stripe_id, cart_limit());
+                    });
+                }>
+                {move || if is_sold_out() {
+                    "Sold Out".to_string()
+                } else {
+                    format!("Add To Cart ${}", product.get().price.unwrap().unit_amount.unwrap() / 100)
+                }}
+                </button>
+            </Show>
         </div>
     }
 }
 
 #[component]
 pub fn CfgProductItemsList(items_category: String) -> impl IntoView {
-    let app_state = expect_context::<AppStateDataRes>();
-    provide_context(app_state);
+    let catalog = expect_context::<CatalogRes>();
+    provide_context(catalog);
     let (items_category, set_items_category) = create_signal(items_category);
     provide_context(items_category);
+    let (sort_by_unit_price, set_sort_by_unit_price) = create_signal(false);
 
     view! {
+        <label class="product-list-sort">
+            "Sort by "
+            <select on:change=move |ev| set_sort_by_unit_price.set(event_target_value(&ev) == "unit_price")>
+                <option value="featured" selected=move || !sort_by_unit_price.get()>"Featured"</option>
+                <option value="unit_price" selected=move || sort_by_unit_price.get()>"Unit price, low to high"</option>
+            </select>
+        </label>
         <Suspense fallback=move || view! {"Loading data..."}>
             {
-                move || match app_state.get() {
+                move || match catalog.get() {
                     None => view! { <p>"Loading..."</p>}.into_view(),
-                    Some(app_state) => {
-                        let products_config: CfgProducts = app_state.clone()
-                            .expect("Resource AppState is not here on 'get()")
-                            .products_config.expect("Resource StripeData is not here on 'get()'");
+                    Some(catalog) => {
+                        let products_config: CfgProducts = catalog
+                            .expect("Resource Catalog is not here on 'get()")
+                            .products;
                         let items_category = expect_context::<ReadSignal<String>>();
                         provide_context(items_category);
 
 
+                        // One card per page, the first variant stands for its parent
+                        let mut listed_pages = std::collections::HashSet::new();
+                        let mut products: Vec<CfgProduct> = products_config.0.into_iter()
+                            .filter(|product| {
+                                product.metadata
+                                    .as_ref()
+                                    .and_then(|metadata| metadata.get("category"))
+                                    .map(|category| category == &items_category.get())
+                                    .unwrap_or(false)
+                            })
+                            .filter(|product| listed_pages.insert(product.page_slug()))
+                            .collect();
+                        if sort_by_unit_price.get() {
+                            // Products without a unit price go last, in config order
+                            products.sort_by(|a, b| {
+                                match (a.unit_price(), b.unit_price()) {
+                                    (Some(a), Some(b)) => a.sort_key().partial_cmp(&b.sort_key()).unwrap_or(std::cmp::Ordering::Equal),
+                                    (Some(_), None) => std::cmp::Ordering::Less,
+                                    (None, Some(_)) => std::cmp::Ordering::Greater,
+                                    (None, None) => std::cmp::Ordering::Equal,
+                                }
+                            });
+                        }
+
                         view! {
                             <ul class="product-list-ul">
                                 {
-                                    products_config.0.into_iter()
-                                    .filter(|product| {
-                                        product.metadata
-                                            .as_ref()
-                                            .and_then(|metadata| metadata.get("category"))
-                                            .map(|category| category == &items_category.get())
-                                            .unwrap_or(false)
-                                    })
+                                    products.into_iter()
                                     .map(|product| {
                                         view! {
                                             <li class="product-list-item">
@@ -159,8 +251,8 @@ pub fn CfgProductItemsList(items_category: String) -> impl IntoView {
 #[component]
 pub fn CfgProductItemDetailsPage(product_name: String) -> impl IntoView {
     // let stripe_data = expect_context::<StripeDataRes>();
-    let app_state = expect_context::<AppStateDataRes>();
-    provide_context(app_state);
+    let catalog = expect_context::<CatalogRes>();
+    provide_context(catalog);
 
     let (product_name, _) = create_signal(product_name);
     provide_context(product_name);
@@ -172,28 +264,29 @@ pub fn CfgProductItemDetailsPage(product_name: String) -> impl IntoView {
 
     view! {
         <Suspense fallback=move || view! {"loading data"}>
-            {move || match app_state.get() {
+            {move || match catalog.get() {
                 None => view! { <p>"Loading..."</p> }.into_view(),
-                Some(app_state) => {
+                Some(catalog) => {
                     // let stripe_data: StripeData = stripe_data.expect("Resource StripeData is not here on 'get()'");
-                    let products_config: CfgProducts = app_state.clone()
-                        .expect("Resource AppState is not here on 'get()")
-                        .products_config.expect("Resource StripeData is not here on 'get()'");
+                    let products_config: CfgProducts = catalog
+                        .expect("Resource Catalog is not here on 'get()")
+                        .products;
 
                     let product_name = expect_context::<ReadSignal<String>>();
                     provide_context(product_name);
 
 
-                    match products_config.0.into_iter()
-                    .find(|product| {
-                        let cmp1 = product.name.to_lowercase().replace(" ", "-");
-                        let cmp2 = &product_name.get()[1..];
-
-                        cmp1 == cmp2
-                    }) {
+                    // Pages of a variant or of its parent both show the variant selector
+                    match products_config.find_by_slug(&product_name.get()[1..]) {
+                        Some(product) if product.variant.is_some() => {
+                            let variants = products_config.variants_of(product);
+                            view!{
+                                <CfgProductVariantsDetails variants=variants selected=product.clone()/>
+                            }.into_view()
+                        },
                         Some(product) => {
                             view!{
-                                <CfgProductItemDetailsContent product=product/>
+                                <CfgProductItemDetailsContent product=product.clone()/>
                             }.into_view()
                         },
                         None => view!{
@@ -206,12 +299,101 @@ pub fn CfgProductItemDetailsPage(product_name: String) -> impl IntoView {
     }
 }
 
+/// A parent product with a select per option axis, showing the variant the options pick
 #[component]
-pub fn ImageListDisplay(images: Vec<std::path::PathBuf>, class: String) -> impl IntoView {
+pub fn CfgProductVariantsDetails(variants: Vec<CfgProduct>, selected: CfgProduct) -> impl IntoView {
+    let axes = crate::products_config::variant_axes(&variants);
+    let (variants, _) = create_signal(variants);
+    let (selected, set_selected) = create_signal(selected);
+
+    // The variant with the chosen value and the other options kept, or else any with the chosen value
+    let choose_option = move |axis: String, value: String| {
+        let mut options = selected
+            .get()
+            .variant
+            .map(|variant| variant.options)
+            .unwrap_or_default();
+        options.insert(axis.clone(), value.clone());
+        let variants = variants.get();
+        let has_value = |product: &&CfgProduct| {
+            product
+                .variant
+                .as_ref()
+                .is_some_and(|variant| variant.options.get(&axis) == Some(&value))
+        };
+        let chosen = variants
+            .iter()
+            .find(|product| {
+                product
+                    .variant
+                    .as_ref()
+                    .is_some_and(|variant| variant.options == options)
+            })
+            .or_else(|| variants.iter().find(has_value));
+        if let Some(chosen) = chosen {
+            set_selected.set(chosen.clone());
+        }
+    };
+
+    view! {
+        <div class="product-variants">
+            <h2 class="product-variants-parent">
+                {move || selected.get().variant.map(|variant| variant.parent)}
+            </h2>
+            {axes
+                .into_iter()
+                .map(|(axis, values)| {
+                    let selected_axis = axis.clone();
+                    let is_selected = move |value: &str| {
+                        selected
+                            .get()
+                            .variant
+                            .is_some_and(|variant| variant.options.get(&selected_axis).map(String::as_str) == Some(value))
+                    };
+                    let changed_axis = axis.clone();
+                    view! {
+                        <label class="product-variants-option">
+                            {axis.replace("_", " ")}
+                            <select on:change=move |ev| choose_option(changed_axis.clone(), event_target_value(&ev))>
+                                {values
+                                    .into_iter()
+                                    .map(|value| {
+                                        let option_value = value.clone();
+                                        let is_selected = is_selected.clone();
+                                        view! {
+                                            <option value=value.clone() selected=move || is_selected(&option_value)>
+                                                {value}
+                                            </option>
+                                        }
+                                    })
+                                    .collect::<Vec<_>>()}
+                            </select>
+                        </label>
+                    }
+                })
+                .collect::<Vec<_>>()}
+            {move || view! { <CfgProductItemDetailsContent product=selected.get()/> }}
+        </div>
+    }
+}
+
+#[component]
+pub fn ImageListDisplay(
+    images: Vec<std::path::PathBuf>,
+    class: String,
+    /// Sizes of the images from the image pipeline, for srcset
+    #[prop(optional)]
+    sizes: Vec<crate::products_config::LocalImage>,
+    /// Alt text of each image, in the order of images
+    #[prop(optional)]
+    alts: Vec<String>,
+) -> impl IntoView {
     let (img_class, _) = create_signal(class);
     provide_context(img_class);
     let (images_signal, _) = create_signal(images.clone());
     provide_context(images_signal);
+    let (sizes_signal, _) = create_signal(sizes);
+    let (alts_signal, _) = create_signal(alts);
 
     let (current_index, set_current_index) = create_signal(0usize);
     let current_image = move || {
@@ -221,6 +403,13 @@ pub fn ImageListDisplay(images: Vec<std::path::PathBuf>, class: String) -> impl
             .cloned()
             .unwrap_or_default()
     };
+    let current_size = move || {
+        let current_image = current_image();
+        sizes_signal
+            .get()
+            .into_iter()
+            .find(|local_image| local_image.path == current_image)
+    };
 
     let (img_opacity, set_img_opacity) = create_signal(1.0);
 
@@ -248,6 +437,11 @@ pub fn ImageListDisplay(images: Vec<std::path::PathBuf>, class: String) -> impl
                 <img
                     class={img_class.get()}
                     src={move || current_image().display().to_string()}
+                    srcset={move || current_size().map(|local_image| local_image.srcset())}
+                    sizes="100vw"
+                    width={move || current_size().map(|local_image| local_image.width.to_string())}
+                    height={move || current_size().map(|local_image| local_image.height.to_string())}
+                    alt={move || alts_signal.get().get(current_index.get()).cloned()}
                     style={move || format!("opacity: {}; transition: 0.1s ease-in-out;", img_opacity.get())}
                 />
             </Show>
@@ -293,6 +487,22 @@ pub fn CfgProductItemDetailsContent(product: CfgProduct) -> impl IntoView {
     let set_shopping_cart = expect_context::<WriteSignal<ShoppingCart>>();
     provide_context(set_shopping_cart);
 
+    let catalog = expect_context::<CatalogRes>();
+    let is_sold_out = move || {
+        catalog
+            .get()
+            .and_then(|catalog| catalog.ok())
+            .is_some_and(|catalog| catalog.is_sold_out(&product.get().stripe_id))
+    };
+    let cart_limit = move || {
+        catalog
+            .get()
+            .and_then(|catalog| catalog.ok())
+            .map_or(20, |catalog| {
+                catalog.cart_limit(&product.get().stripe_id, 20)
+            })
+    };
+
     view! {
         <div class="product-item-container">
             <Show
@@ -304,7 +514,19 @@ pub fn CfgProductItemDetailsContent(product: CfgProduct) -> impl IntoView {
                 }}
             >
                 // <img class="product-item-image" src={product.get().local_images.unwrap().first().unwrap_or(&std::path::PathBuf::from("no_local_image_found_error")).to_owned().display().to_string()}/>
-                <ImageListDisplay images={product.get().local_images.expect("None local_images")} class="product-item-image".to_string() />
+                <ImageListDisplay
+                    images={product.get().gallery_images()}
+                    class="product-item-image".to_string()
+                    sizes={product.get().local_image_sizes.unwrap_or_default()}
+                    alts={
+                        let product = product.get();
+                        product
+                            .gallery_images()
+                            .iter()
+                            .map(|path| product.image_alt(path))
+                            .collect()
+                    }
+                />
             </Show>
             <div class="product-info">
                 <strong class="product-item-name">
@@ -313,23 +535,101 @@ pub fn CfgProductItemDetailsContent(product: CfgProduct) -> impl IntoView {
                 <p class="product-item-description">
                     {product.get().description.unwrap_or("No Description.".to_string())}
                 </p>
+                <ProductSpecTable product=product.get()/>
             </div>
-            <button class="product-item-addtocart-button" on:click=move |_| {
+            <p class="product-item-unit-price">
+                {move || product.get().unit_price().map(|unit_price| unit_price.to_string())}
+            </p>
+            <button class="product-item-addtocart-button" disabled=is_sold_out on:click=move |_| {
                 set_shopping_cart.update(|s| {
-                    s.add_single_product(&product.get().stripe_id, 20);
+                    s.add_single_product(&product.get().stripe_id, cart_limit());
                 });
             }>
-            "Add To Cart $"{product.get().price.unwrap().unit_amount.unwrap() / 100}
+            {move || if is_sold_out() {
+                "Sold Out".to_string()
+            } else {
+                format!("Add To Cart ${}", product.get().price.unwrap().unit_amount.unwrap() / 100)
+            }}
             </button>
         </div>
     }
 }
 
+/// Weight, storage, allergens and nutrition of a product, rows it has no value for are left out
+#[component]
+pub fn ProductSpecTable(product: CfgProduct) -> impl IntoView {
+    let attributes = product.attributes.unwrap_or_default();
+    let rows: Vec<(&str, String)> = [
+        (
+            "Net weight",
+            attributes
+                .net_weight_g
+                .map(crate::products_config::format_weight),
+        ),
+        (
+            "Pieces per pack",
+            attributes.pieces_per_pack.map(|pieces| pieces.to_string()),
+        ),
+        ("Sold per", product.unit_label),
+        (
+            "Storage",
+            attributes.storage.map(|storage| storage.to_string()),
+        ),
+        (
+            "Shelf life",
+            attributes
+                .shelf_life_days
+                .map(|days| format!("{} days", days)),
+        ),
+        (
+            "Allergens",
+            (!attributes.allergens.is_empty()).then(|| attributes.allergens.join(", ")),
+        ),
+    ]
+    .into_iter()
+    .filter_map(|(label, value)| Some((label, value?)))
+    .collect();
+    let nutrition = attributes.nutrition;
+
+    view! {
+        {(!rows.is_empty()).then(|| view! {
+            <table class="product-spec-table">
+                {rows
+                    .into_iter()
+                    .map(|(label, value)| view! {
+                        <tr>
+                            <th>{label}</th>
+                            <td>{value}</td>
+                        </tr>
+                    })
+                    .collect::<Vec<_>>()}
+            </table>
+        })}
+        {(!nutrition.is_empty()).then(|| view! {
+            <table class="product-nutrition-table">
+                <tr>
+                    <th>"Nutrition"</th>
+                    <th>"Per 100 g"</th>
+                </tr>
+                {nutrition
+                    .into_iter()
+                    .map(|row| view! {
+                        <tr>
+                            <td>{row.nutrient}</td>
+                            <td>{row.per_100g}</td>
+                        </tr>
+                    })
+                    .collect::<Vec<_>>()}
+            </table>
+        })}
+    }
+}
+
 #[component]
 pub fn CfgProductItemShoppingCartCounter(product: CfgProduct) -> impl IntoView {
     let (product, _) = create_signal(product);
-    let app_state = expect_context::<AppStateDataRes>();
-    provide_context(app_state);
+    let catalog = expect_context::<CatalogRes>();
+    provide_context(catalog);
     let shopping_cart = expect_context::<Signal<ShoppingCart>>();
     provide_context(shopping_cart);
 
@@ -341,11 +641,10 @@ pub fn CfgProductItemShoppingCartCounter(product: CfgProduct) -> impl IntoView {
                     .0
                     .keys()
                     .find(|stripe_id| product.get().stripe_id == stripe_id.to_owned().to_owned()).unwrap().clone()
-                     == app_state.get()
-                        .expect("No AppState!")
-                        .expect("No AppState!")
-                        .products_config
-                        .expect("No products_config in AppState!")
+                     == catalog.get()
+                        .expect("No Catalog!")
+                        .expect("No Catalog!")
+                        .products
                         .0
                         .iter()
                         .find(|appstate_cfg_product| appstate_cfg_product.stripe_id == product.get().stripe_id).unwrap().stripe_id
@@ -0,0 +1,201 @@
+#![cfg(feature = "ssr")]
+
+//! Admin authentication.
+//! Admins log in with a username and password hashed by argon2, and get a random session token in an HttpOnly cookie.
+//! `AdminSession` is an axum extractor, server functions use `require_admin()` to get it.
+
+use crate::db::{self, DbPool};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+};
+use axum_extra::extract::CookieJar;
+use leptos::ServerFnError;
+use log::*;
+use rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha256};
+
+pub const SESSION_COOKIE: &str = "farmtasker_admin_session";
+
+/// Admin sessions last 12 hours
+pub const SESSION_LIFETIME: i64 = 12 * 60 * 60;
+
+/// A logged in admin, extracted from the session cookie of the request
+#[derive(Debug, Clone)]
+pub struct AdminSession {
+    pub username: String,
+    pub expires_at: i64,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AuthError {
+    #[error("Not logged in as admin")]
+    Unauthorized,
+    #[error("DbPool not found in request extensions")]
+    MissingDb,
+    #[error(transparent)]
+    Db(#[from] sqlx::Error),
+}
+
+impl IntoResponse for AuthError {
+    fn into_response(self) -> Response {
+        match self {
+            AuthError::Unauthorized => StatusCode::UNAUTHORIZED.into_response(),
+            err => {
+                error!("{}", err);
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+}
+
+pub fn hash_password(password: &str) -> Result<String, ServerFnError> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|err| ServerFnError::ServerError(format!("Couldn't hash password: {}", err)))
+}
+
+fn verify_password(password: &str, password_hash: &str) -> bool {
+    match PasswordHash::new(password_hash) {
+        Ok(parsed) => Argon2::default()
+            .verify_password(password.as_bytes(), &parsed)
+            .is_ok(),
+        Err(err) => {
+            error!("Stored admin password hash is invalid: {}", err);
+            false
+        }
+    }
+}
+
+/// Only the hash of a session token is stored, so a leaked database doesn't leak sessions
+fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn new_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Secure cookies need https, which the local dev server doesn't have
+fn session_cookie(value: &str, max_age: i64) -> String {
+    let secure = if std::env::var("DEVPORT").is_ok() {
+        ""
+    } else {
+        "; Secure"
+    };
+    format!(
+        "{}={}; Path=/; HttpOnly; SameSite=Strict; Max-Age={}{}",
+        SESSION_COOKIE, value, max_age, secure
+    )
+}
+
+/// Checks the credentials and starts a session.
+/// Returns the session and the Set-Cookie header value that carries its token.
+pub async fn login(
+    pool: &DbPool,
+    username: &str,
+    password: &str,
+) -> Result<Option<(AdminSession, String)>, ServerFnError> {
+    let password_hash = db::admins::get_password_hash(pool, username).await?;
+
+    let is_valid = match &password_hash {
+        Some(password_hash) => verify_password(password, password_hash),
+        None => {
+            // Hash anyway so unknown usernames take as long as wrong passwords
+            let _ = hash_password(password);
+            false
+        }
+    };
+    if !is_valid {
+        warn!("Failed admin login for {:?}.", username);
+        return Ok(None);
+    }
+
+    let token = new_token();
+    let expires_at = db::unix_now() + SESSION_LIFETIME;
+    db::admins::create_session(pool, &hash_token(&token), username, expires_at).await?;
+    if let Err(err) = db::admins::delete_expired_sessions(pool).await {
+        error!("Couldn't delete expired admin sessions: {}", err);
+    }
+    info!("Admin {} logged in.", username);
+
+    Ok(Some((
+        AdminSession {
+            username: username.to_string(),
+            expires_at,
+        },
+        session_cookie(&token, SESSION_LIFETIME),
+    )))
+}
+
+/// Ends the session of `token`, returns the Set-Cookie header value that clears the cookie
+pub async fn logout(pool: &DbPool, token: &str) -> Result<String, ServerFnError> {
+    db::admins::delete_session(pool, &hash_token(token)).await?;
+    Ok(session_cookie("", 0))
+}
+
+pub fn session_token(jar: &CookieJar) -> Option<String> {
+    jar.get(SESSION_COOKIE)
+        .map(|cookie| cookie.value().to_string())
+        .filter(|token| !token.is_empty())
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for AdminSession {
+    type Rejection = AuthError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let token = session_token(&CookieJar::from_headers(&parts.headers))
+            .ok_or(AuthError::Unauthorized)?;
+        let pool = parts
+            .extensions
+            .get::<DbPool>()
+            .cloned()
+            .ok_or(AuthError::MissingDb)?;
+
+        match db::admins::get_session(&pool, &hash_token(&token)).await? {
+            Some((username, expires_at)) => Ok(AdminSession {
+                username,
+                expires_at,
+            }),
+            None => Err(AuthError::Unauthorized),
+        }
+    }
+}
+
+/// Gets the admin of the current request, or fails the server function when not logged in
+pub async fn require_admin() -> Result<AdminSession, ServerFnError> {
+    leptos_axum::extract::<AdminSession>().await
+}
+
+/// Creates the first admin from ADMIN_USERNAME and ADMIN_PASSWORD when there is no admin yet
+pub async fn bootstrap_admin(pool: &DbPool) -> Result<(), ServerFnError> {
+    if db::admins::count_admins(pool).await? > 0 {
+        return Ok(());
+    }
+
+    match (
+        std::env::var("ADMIN_USERNAME"),
+        std::env::var("ADMIN_PASSWORD"),
+    ) {
+        (Ok(username), Ok(password)) if !username.is_empty() && !password.is_empty() => {
+            db::admins::upsert_admin(pool, &username, &hash_password(&password)?).await?;
+            info!("Created admin {} from ADMIN_USERNAME.", username);
+        }
+        _ => warn!(
+            "No admin exists yet. Set ADMIN_USERNAME and ADMIN_PASSWORD to create one, admin endpoints are closed until then."
+        ),
+    }
+    Ok(())
+}
@@ -0,0 +1,655 @@
+//! Operator CLI for catalog and order tasks, working on the same database and products_config.json as the server.
+//! The server doesn't have to be running. Run without a subcommand to pick a task interactively.
+
+#[cfg(not(feature = "ssr"))]
+fn main() {}
+
+#[cfg(feature = "ssr")]
+mod cli {
+    use clap::{Parser, Subcommand, ValueEnum};
+    use farmtasker_au::db::{self, DbPool};
+    use farmtasker_au::orders::{FulfilmentStatus, Order};
+    use farmtasker_au::*;
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    pub type CliResult<T> = Result<T, leptos::ServerFnError>;
+
+    /// Actor of the products config revisions written from here
+    const CLI_ACTOR: &str = "cli";
+
+    #[derive(Parser)]
+    #[command(
+        name = "farmtasker-admin",
+        about = "Catalog and order tasks of the Farmtasker shop",
+        long_about = "Catalog and order tasks of the Farmtasker shop.\nUses DATABASE_URL, LEPTOS_SITE_ROOT, LEPTOS_ASSETS_DIR, STRIPE_KEY and PAYMENT_BACKEND like the server does.\nRun without a subcommand to pick a task interactively."
+    )]
+    pub struct Cli {
+        #[command(subcommand)]
+        pub command: Option<Command>,
+    }
+
+    #[derive(Subcommand)]
+    pub enum Command {
+        /// Fetches products, customers and checkout sessions from Stripe into the database
+        Sync,
+        /// Merges the changes made in Stripe into products_config.json and prints them
+        Refresh {
+            /// Only print what would change, nothing is written
+            #[arg(long)]
+            dry_run: bool,
+        },
+        /// Rewrites products_config.json from Stripe, dropping local changes
+        Reset {
+            /// Don't ask for confirmation
+            #[arg(long)]
+            yes: bool,
+            /// Only print what would change, nothing is written
+            #[arg(long)]
+            dry_run: bool,
+        },
+        /// Pushes local edits of products_config.json to Stripe, new prices replace changed ones
+        Push {
+            /// Don't ask for confirmation
+            #[arg(long)]
+            yes: bool,
+            /// Only print what would change in Stripe, nothing is pushed
+            #[arg(long)]
+            dry_run: bool,
+        },
+        /// Lists the products of products_config.json with item numbers, prices and stock
+        Products,
+        /// Exports products_config.json as CSV, one row per product
+        ExportProducts {
+            /// File to write to, stdout if missing
+            #[arg(short, long)]
+            output: Option<PathBuf>,
+        },
+        /// Imports a CSV made by export-products back into products_config.json
+        ImportProducts {
+            input: PathBuf,
+            /// Don't ask for confirmation
+            #[arg(long)]
+            yes: bool,
+            /// Only print what would change, nothing is written
+            #[arg(long)]
+            dry_run: bool,
+        },
+        /// Checks products_config.json and its local images, fails when there are errors
+        Validate,
+        /// Converts new source images of products_assets to webp, refresh lists them in products_config.json
+        Images,
+        /// Lists, compares and rolls back revisions of products_config.json
+        Revisions {
+            #[command(subcommand)]
+            command: RevisionsCommand,
+        },
+        /// Shows a checkout session and its order as JSON
+        Session {
+            checkout_session_id: String,
+            /// Fetch the session from the payment backend instead of the database
+            #[arg(long)]
+            remote: bool,
+        },
+        /// Exports orders, newest first
+        ExportOrders {
+            #[arg(long, value_enum, default_value_t = ExportFormat::Csv)]
+            format: ExportFormat,
+            /// Only orders in this fulfilment status, like "paid" or "out-for-delivery"
+            #[arg(long, value_parser = parse_fulfilment_status)]
+            status: Option<FulfilmentStatus>,
+            /// File to write to, stdout if missing
+            #[arg(short, long)]
+            output: Option<PathBuf>,
+        },
+        /// Manages admin logins of the dashboard
+        Admins {
+            #[command(subcommand)]
+            command: AdminsCommand,
+        },
+    }
+
+    #[derive(Subcommand)]
+    pub enum AdminsCommand {
+        List,
+        /// Adds an admin or changes their password, the password is asked for
+        Set {
+            username: String,
+        },
+        /// Removes an admin and ends their sessions
+        Remove {
+            username: String,
+        },
+    }
+
+    #[derive(Subcommand)]
+    pub enum RevisionsCommand {
+        List,
+        /// Prints the changes from one revision to another
+        Diff {
+            from: i64,
+            to: i64,
+        },
+        /// Writes an earlier revision back as the latest one
+        Rollback {
+            revision: i64,
+            /// Don't ask for confirmation
+            #[arg(long)]
+            yes: bool,
+        },
+    }
+
+    #[derive(ValueEnum, Clone, Copy)]
+    pub enum ExportFormat {
+        Csv,
+        Json,
+    }
+
+    /// Accepts "out-for-delivery", "OutForDelivery" and "Out for delivery" alike
+    fn parse_fulfilment_status(value: &str) -> Result<FulfilmentStatus, String> {
+        let simplify = |value: &str| {
+            value
+                .chars()
+                .filter(|c| c.is_alphanumeric())
+                .collect::<String>()
+                .to_lowercase()
+        };
+        FulfilmentStatus::ALL
+            .into_iter()
+            .find(|status| simplify(status.label()) == simplify(value))
+            .ok_or_else(|| format!("Unknown fulfilment status {:?}", value))
+    }
+
+    /// Asks which task to run, for when no subcommand was given
+    pub fn prompt_command() -> CliResult<Command> {
+        const TASKS: [&str; 16] = [
+            "Sync from Stripe",
+            "Preview refresh of products_config.json",
+            "Refresh products_config.json",
+            "Reset products_config.json",
+            "Preview push to Stripe",
+            "Push products_config.json to Stripe",
+            "List products",
+            "Export products as CSV",
+            "Import products from CSV",
+            "Validate products_config.json",
+            "Process product images",
+            "List products_config.json revisions",
+            "Inspect checkout session",
+            "Export orders",
+            "List admins",
+            "Set admin password",
+        ];
+
+        let command = match inquire::Select::new("What do you want to do?", TASKS.to_vec())
+            .prompt()?
+        {
+            "Sync from Stripe" => Command::Sync,
+            "Preview refresh of products_config.json" => Command::Refresh { dry_run: true },
+            "Refresh products_config.json" => Command::Refresh { dry_run: false },
+            "Reset products_config.json" => Command::Reset {
+                yes: false,
+                dry_run: false,
+            },
+            "Preview push to Stripe" => Command::Push {
+                yes: false,
+                dry_run: true,
+            },
+            "Push products_config.json to Stripe" => Command::Push {
+                yes: false,
+                dry_run: false,
+            },
+            "List products" => Command::Products,
+            "Export products as CSV" => {
+                let output = inquire::Text::new("Write to file (empty for stdout):").prompt()?;
+                Command::ExportProducts {
+                    output: (!output.trim().is_empty()).then(|| PathBuf::from(output.trim())),
+                }
+            }
+            "Import products from CSV" => Command::ImportProducts {
+                input: PathBuf::from(inquire::Text::new("CSV file:").prompt()?.trim()),
+                yes: false,
+                dry_run: false,
+            },
+            "Validate products_config.json" => Command::Validate,
+            "Process product images" => Command::Images,
+            "List products_config.json revisions" => Command::Revisions {
+                command: RevisionsCommand::List,
+            },
+            "Inspect checkout session" => Command::Session {
+                checkout_session_id: inquire::Text::new("Checkout session id:").prompt()?,
+                remote: inquire::Confirm::new("Fetch it from the payment backend?")
+                    .with_default(false)
+                    .prompt()?,
+            },
+            "Export orders" => {
+                let format = match inquire::Select::new("Format:", vec!["csv", "json"]).prompt()? {
+                    "json" => ExportFormat::Json,
+                    _ => ExportFormat::Csv,
+                };
+                let output = inquire::Text::new("Write to file (empty for stdout):").prompt()?;
+                Command::ExportOrders {
+                    format,
+                    status: None,
+                    output: (!output.trim().is_empty()).then(|| PathBuf::from(output.trim())),
+                }
+            }
+            "List admins" => Command::Admins {
+                command: AdminsCommand::List,
+            },
+            _ => Command::Admins {
+                command: AdminsCommand::Set {
+                    username: inquire::Text::new("Username:").prompt()?,
+                },
+            },
+        };
+        Ok(command)
+    }
+
+    pub async fn run(command: Command, db: &DbPool) -> CliResult<()> {
+        match command {
+            Command::Sync => {
+                // Nothing is served from this state, it only satisfies sync_stripe_data
+                let shared_appstate = SharedAppState::new(AppState {
+                    stripe_data: None,
+                    products_config: None,
+                    stripe_status: StripeStatus::Live,
+                    sync_reports: Vec::new(),
+                });
+                let report = sync_stripe_data(&shared_appstate, db).await?;
+                println!("{}", report["count"]);
+                eprintln!("A running server picks this up on its next sync.");
+            }
+            Command::Refresh { dry_run: true } | Command::Reset { dry_run: true, .. } => {
+                let rewrite = matches!(command, Command::Reset { .. });
+                let (_, _, diff) = preview_products_config(db, rewrite).await?;
+                print!("{}", diff);
+                eprintln!("Dry run, nothing was written.");
+            }
+            Command::Refresh { dry_run: false } => {
+                let diff = refresh_products_config(db, CLI_ACTOR, false).await?;
+                print!("{}", diff);
+            }
+            Command::Reset { yes, .. } => {
+                let confirmed = yes
+                    || inquire::Confirm::new(
+                        "Rewrite products_config.json from Stripe? Local changes are lost.",
+                    )
+                    .with_default(false)
+                    .prompt()?;
+                if confirmed {
+                    let diff = refresh_products_config(db, CLI_ACTOR, true).await?;
+                    print!("{}", diff);
+                }
+            }
+            Command::Push { yes, dry_run } => {
+                let plan = preview_push_products_config().await?;
+                print!("{}", plan);
+                if dry_run {
+                    eprintln!("Dry run, nothing was pushed.");
+                } else if plan.is_empty() {
+                    eprintln!("Stripe already has the local values, nothing to push.");
+                } else {
+                    let confirmed = yes
+                        || inquire::Confirm::new("Push these changes to Stripe?")
+                            .with_default(false)
+                            .prompt()?;
+                    if confirmed {
+                        let report = push_products_config(db, CLI_ACTOR, &plan).await?;
+                        print!("{}", report);
+                        if !report.failed.is_empty() {
+                            return Err(leptos::ServerFnError::ServerError(
+                                "Some products couldn't be pushed".into(),
+                            ));
+                        }
+                    }
+                }
+            }
+            Command::Products => list_products(db).await?,
+            Command::ExportProducts { output } => export_products(output).await?,
+            Command::ImportProducts {
+                input,
+                yes,
+                dry_run,
+            } => import_products(db, &input, yes, dry_run).await?,
+            Command::Validate => validate_products().await?,
+            Command::Images => {
+                let report = process_product_images().await?;
+                print!("{}", report);
+                if !report.failed.is_empty() {
+                    return Err(leptos::ServerFnError::ServerError(
+                        "Some product images couldn't be processed".into(),
+                    ));
+                }
+            }
+            Command::Revisions { command } => manage_revisions(db, command).await?,
+            Command::Session {
+                checkout_session_id,
+                remote,
+            } => inspect_checkout_session(db, &checkout_session_id, remote).await?,
+            Command::ExportOrders {
+                format,
+                status,
+                output,
+            } => export_orders(db, format, status, output).await?,
+            Command::Admins { command } => manage_admins(db, command).await?,
+        }
+        Ok(())
+    }
+
+    fn format_amount(amount: Option<i64>) -> String {
+        amount.map_or(String::new(), |amount| {
+            format!("{:.2}", amount as f64 / 100.0)
+        })
+    }
+
+    async fn list_products(db: &DbPool) -> CliResult<()> {
+        let mut products = read_products_config().await?.0;
+        products.sort_by_key(|product| product.item_number.unwrap_or(i64::MAX));
+        let available_stock = db::inventory::get_available_stock(db).await?;
+
+        println!(
+            "{:>5}  {:<40}  {:>8}  {:>9}  STRIPE ID",
+            "#", "NAME", "PRICE", "AVAILABLE"
+        );
+        for product in products {
+            println!(
+                "{:>5}  {:<40}  {:>8}  {:>9}  {}",
+                product
+                    .item_number
+                    .map_or("-".to_string(), |item_number| item_number.to_string()),
+                product.name,
+                format_amount(product.price.as_ref().and_then(|price| price.unit_amount)),
+                available_stock
+                    .get(&product.stripe_id)
+                    .map_or("-".to_string(), |available| available.to_string()),
+                product.stripe_id
+            );
+        }
+        Ok(())
+    }
+
+    async fn export_products(output: Option<PathBuf>) -> CliResult<()> {
+        let products_config = read_products_config().await?;
+        let writer: Box<dyn Write> = match &output {
+            Some(path) => Box::new(std::fs::File::create(path)?),
+            None => Box::new(std::io::stdout()),
+        };
+        products_config::spreadsheet::export_products_csv(&products_config, writer)?;
+
+        if let Some(path) = output {
+            eprintln!(
+                "Exported {} products to {}.",
+                products_config.0.len(),
+                path.display()
+            );
+        }
+        Ok(())
+    }
+
+    async fn import_products(
+        db: &DbPool,
+        input: &std::path::Path,
+        yes: bool,
+        dry_run: bool,
+    ) -> CliResult<()> {
+        let products_config = read_products_config().await?;
+        let (imported, report) = products_config::spreadsheet::import_products_csv(
+            &products_config,
+            std::fs::File::open(input)?,
+        )?;
+        print!("{}", report);
+
+        if !report.is_importable() {
+            return Err(leptos::ServerFnError::ServerError(
+                "The CSV has errors, nothing was imported".into(),
+            ));
+        }
+        if dry_run {
+            eprintln!("Dry run, nothing was written.");
+            return Ok(());
+        }
+        if report.diff.is_empty() {
+            eprintln!("Nothing to import, products_config.json already has these values.");
+            return Ok(());
+        }
+
+        let confirmed = yes
+            || inquire::Confirm::new("Write these changes to products_config.json?")
+                .with_default(false)
+                .prompt()?;
+        if confirmed {
+            let note = format!("import from {}: {}", input.display(), report.diff.summary());
+            let revision = save_products_config(db, imported, CLI_ACTOR, &note).await?;
+            println!("Imported as revision #{}.", revision);
+            eprintln!("Changed names and descriptions reach Stripe with `push`.");
+        }
+        Ok(())
+    }
+
+    async fn validate_products() -> CliResult<()> {
+        let site_root = std::env::var("LEPTOS_SITE_ROOT").unwrap_or_else(|_| "site".to_string());
+        let report = products_config::validation::validate_products_config(
+            &read_products_config().await?,
+            Some(std::path::Path::new(&site_root)),
+        );
+        print!("{}", report);
+
+        match report.has_errors() {
+            true => Err(leptos::ServerFnError::ServerError(
+                "products_config.json has errors, the products with them aren't sold".into(),
+            )),
+            false => Ok(()),
+        }
+    }
+
+    async fn manage_revisions(db: &DbPool, command: RevisionsCommand) -> CliResult<()> {
+        match command {
+            RevisionsCommand::List => {
+                println!(
+                    "{:>5}  {:<20}  {:<12}  {:>8}  NOTE",
+                    "#", "CREATED", "BY", "PRODUCTS"
+                );
+                for revision in db::products_config::get_revisions(db).await? {
+                    println!(
+                        "{:>5}  {:<20}  {:<12}  {:>8}  {}",
+                        revision.revision,
+                        revision.created_at,
+                        revision.actor,
+                        revision.products_count,
+                        revision.note
+                    );
+                }
+            }
+            RevisionsCommand::Diff { from, to } => {
+                print!("{}", diff_revisions(db, from, to).await?)
+            }
+            RevisionsCommand::Rollback { revision, yes } => {
+                let confirmed = yes
+                    || inquire::Confirm::new(&format!(
+                        "Roll products_config.json back to revision #{}?",
+                        revision
+                    ))
+                    .with_default(false)
+                    .prompt()?;
+                if confirmed {
+                    let new_revision = rollback_to_revision(db, revision, CLI_ACTOR).await?;
+                    println!(
+                        "Rolled back to revision #{} as revision #{}.",
+                        revision, new_revision
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn inspect_checkout_session(
+        db: &DbPool,
+        checkout_session_id: &str,
+        remote: bool,
+    ) -> CliResult<()> {
+        let checkout_session = match remote {
+            true => Some(
+                payments::payment_backend()?
+                    .retrieve_checkout_session(checkout_session_id)
+                    .await?,
+            ),
+            false => db::stripe::get_checkout_session(db, checkout_session_id).await?,
+        };
+        let Some(checkout_session) = checkout_session else {
+            return Err(leptos::ServerFnError::ServerError(format!(
+                "No checkout session {} in the database, try --remote",
+                checkout_session_id
+            )));
+        };
+        let order = db::orders::get_order_by_checkout_session(db, checkout_session_id).await?;
+
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&serde_json::json!({
+                "checkout_session": checkout_session,
+                "order": order,
+            }))?
+        );
+        Ok(())
+    }
+
+    async fn export_orders(
+        db: &DbPool,
+        format: ExportFormat,
+        status: Option<FulfilmentStatus>,
+        output: Option<PathBuf>,
+    ) -> CliResult<()> {
+        let orders: Vec<Order> = db::orders::get_orders(db)
+            .await?
+            .into_iter()
+            .filter(|order| status.is_none_or(|status| order.fulfilment_status == status))
+            .collect();
+
+        let writer: Box<dyn Write> = match &output {
+            Some(path) => Box::new(std::fs::File::create(path)?),
+            None => Box::new(std::io::stdout()),
+        };
+
+        match format {
+            ExportFormat::Json => serde_json::to_writer_pretty(writer, &orders)?,
+            ExportFormat::Csv => {
+                let mut csv = csv::Writer::from_writer(writer);
+                csv.write_record([
+                    "order_number",
+                    "placed_at",
+                    "fulfilment_status",
+                    "customer_name",
+                    "customer_email",
+                    "customer_phone",
+                    "shipping_address",
+                    "items",
+                    "amount_subtotal",
+                    "amount_shipping",
+                    "amount_total",
+                    "currency",
+                ])?;
+                for order in &orders {
+                    let shipping_address = order
+                        .shipping_address
+                        .as_ref()
+                        .and_then(|shipping| shipping.address.clone())
+                        .map(|address| {
+                            [
+                                address.line1,
+                                address.line2,
+                                address.city,
+                                address.state,
+                                address.postal_code,
+                            ]
+                            .into_iter()
+                            .flatten()
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                        })
+                        .unwrap_or_default();
+                    let items = order
+                        .items
+                        .iter()
+                        .map(|item| format!("{} x{}", item.name, item.quantity))
+                        .collect::<Vec<_>>()
+                        .join("; ");
+
+                    csv.write_record([
+                        order.order_number.clone(),
+                        order.placed_at.unwrap_or(order.created_at).to_string(),
+                        order.fulfilment_status.label().to_string(),
+                        order.customer_name.clone().unwrap_or_default(),
+                        order.customer_email.clone().unwrap_or_default(),
+                        order.customer_phone.clone().unwrap_or_default(),
+                        shipping_address,
+                        items,
+                        format_amount(Some(order.amount_subtotal)),
+                        format_amount(Some(order.amount_shipping)),
+                        format_amount(Some(order.amount_total)),
+                        order.currency.clone(),
+                    ])?;
+                }
+                csv.flush()?;
+            }
+        }
+
+        if let Some(path) = output {
+            eprintln!("Exported {} orders to {}.", orders.len(), path.display());
+        }
+        Ok(())
+    }
+
+    async fn manage_admins(db: &DbPool, command: AdminsCommand) -> CliResult<()> {
+        match command {
+            AdminsCommand::List => {
+                for username in db::admins::get_admin_usernames(db).await? {
+                    println!("{}", username);
+                }
+            }
+            AdminsCommand::Set { username } => {
+                let password = inquire::Password::new("Password:").prompt()?;
+                if password.is_empty() {
+                    return Err(leptos::ServerFnError::ServerError(
+                        "Password can't be empty".into(),
+                    ));
+                }
+                db::admins::upsert_admin(db, &username, &auth::hash_password(&password)?).await?;
+                println!("Saved admin {}.", username);
+            }
+            AdminsCommand::Remove { username } => {
+                match db::admins::delete_admin(db, &username).await? {
+                    true => println!("Removed admin {}.", username),
+                    false => {
+                        return Err(leptos::ServerFnError::ServerError(format!(
+                            "No admin {}",
+                            username
+                        )))
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "ssr")]
+#[tokio::main]
+async fn main() -> cli::CliResult<()> {
+    use clap::Parser;
+
+    // Logs go to stderr so exports to stdout stay clean
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .init();
+
+    let cli = cli::Cli::parse();
+    let command = match cli.command {
+        Some(command) => command,
+        None => cli::prompt_command()?,
+    };
+
+    let db = farmtasker_au::db::init_db().await?;
+    cli::run(command, &db).await
+}
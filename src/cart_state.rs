@@ -14,7 +14,7 @@ impl ShoppingCart {
             if *quantity < add_limit {
                 *quantity += 1;
             }
-        } else {
+        } else if add_limit > 0 {
             // If the product is not in the cart, add it with a quantity of 1
             self.0.insert(product_id.clone(), 1);
         }
@@ -0,0 +1,195 @@
+//! Hot reload of the catalog. Polls products_config.json in site root and assets and the
+//! product images in products_assets/<item_number>/ and its ready_assets/, and when they change
+//! converts new source images and swaps the reloaded config into the shared AppState if it has no errors.
+
+use crate::db::DbPool;
+use crate::products_config::validation::validate_products_config;
+use crate::products_config::{diff_products_configs, CfgProducts};
+use crate::{SharedAppState, SyncKind, SyncReport};
+use log::*;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+const ACTOR: &str = "watcher";
+
+/// Modification times of the files the catalog is loaded from
+#[derive(PartialEq, Eq, Default, Debug)]
+struct Snapshot {
+    site_config: Option<SystemTime>,
+    public_config: Option<SystemTime>,
+    images: BTreeMap<PathBuf, Option<SystemTime>>,
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+fn take_snapshot(site_root: &Path, assets_dir: &Path) -> Snapshot {
+    let mut images = BTreeMap::new();
+    let product_dirs = std::fs::read_dir(site_root.join("products_assets"))
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok());
+    for product_dir in product_dirs {
+        // Source images of the image pipeline, and its output
+        let sources = std::fs::read_dir(product_dir.path())
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().is_file());
+        let ready_assets = std::fs::read_dir(product_dir.path().join("ready_assets"))
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok());
+        for image in sources.chain(ready_assets) {
+            images.insert(image.path(), modified(&image.path()));
+        }
+    }
+
+    Snapshot {
+        site_config: modified(&site_root.join("products_config.json")),
+        public_config: modified(&assets_dir.join("products_config.json")),
+        images,
+    }
+}
+
+fn read_config_file(path: &Path) -> Result<CfgProducts, leptos::ServerFnError> {
+    let contents = std::fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&contents)?)
+}
+
+/// Watches the catalog files for as long as the server runs. Spawned by main.rs.
+pub async fn watch_products_config(shared_appstate: SharedAppState, db: DbPool) {
+    let site_root =
+        PathBuf::from(std::env::var("LEPTOS_SITE_ROOT").unwrap_or_else(|_| "site".to_string()));
+    let assets_dir =
+        PathBuf::from(std::env::var("LEPTOS_ASSETS_DIR").unwrap_or_else(|_| "public".to_string()));
+
+    let mut last_snapshot = take_snapshot(&site_root, &assets_dir);
+    loop {
+        tokio::time::sleep(POLL_INTERVAL).await;
+
+        let snapshot = take_snapshot(&site_root, &assets_dir);
+        if snapshot == last_snapshot {
+            continue;
+        }
+        // Let editors and copies finish writing before reading
+        tokio::time::sleep(POLL_INTERVAL).await;
+        let snapshot = take_snapshot(&site_root, &assets_dir);
+
+        // An edit of only the public file is read from there, otherwise the site root file is the live one
+        let is_public_edit = snapshot.public_config != last_snapshot.public_config
+            && snapshot.site_config == last_snapshot.site_config;
+        let config_path = match is_public_edit {
+            true => assets_dir.join("products_config.json"),
+            false => site_root.join("products_config.json"),
+        };
+
+        let started_at = crate::db::unix_now();
+        let result = reload_products_config(
+            &shared_appstate,
+            &db,
+            &site_root,
+            &config_path,
+            is_public_edit,
+        )
+        .await;
+        if let Err(err) = &result {
+            error!("Kept the previous catalog: {}", err);
+        }
+        if !matches!(&result, Ok(None)) {
+            shared_appstate.record_sync(SyncReport::new(
+                SyncKind::CatalogReload,
+                ACTOR,
+                started_at,
+                result.map(Option::unwrap_or_default),
+            ));
+        }
+
+        // Includes the files written by the reload itself, so they don't trigger another one
+        last_snapshot = take_snapshot(&site_root, &assets_dir);
+    }
+}
+
+/// Reads the config at `config_path` with the images found now and serves it if it has no errors.
+/// Returns a summary of the changes, None if nothing changed.
+async fn reload_products_config(
+    shared_appstate: &SharedAppState,
+    db: &DbPool,
+    site_root: &Path,
+    config_path: &Path,
+    is_public_edit: bool,
+) -> Result<Option<String>, String> {
+    let images_report = crate::process_product_images()
+        .await
+        .map_err(|err| err.to_string())?;
+    if !images_report.processed.is_empty() || !images_report.failed.is_empty() {
+        info!("Processed product images: {}", images_report);
+    }
+
+    let file_products_config = read_config_file(config_path)
+        .map_err(|err| format!("Couldn't read {}: {}", config_path.display(), err))?;
+    let (products_config, _) = crate::add_images_to_products_config(file_products_config.clone())
+        .await
+        .map_err(|err| err.to_string())?;
+
+    let report = validate_products_config(&products_config, Some(site_root));
+    for issue in report.warnings() {
+        warn!(
+            "CONFIG WARNING: #{} {:?} ({}): {}",
+            issue.index, issue.name, issue.stripe_id, issue.kind
+        );
+    }
+    if report.has_errors() {
+        for issue in report.errors() {
+            error!(
+                "CONFIG ERROR: #{} {:?} ({}): {}",
+                issue.index, issue.name, issue.stripe_id, issue.kind
+            );
+        }
+        return Err(format!(
+            "{} has {} errors",
+            config_path.display(),
+            report.errors().count()
+        ));
+    }
+
+    let served = shared_appstate
+        .snapshot()
+        .products_config
+        .unwrap_or(CfgProducts(Vec::new()));
+    let diff = diff_products_configs(&served, &products_config);
+    if diff.is_empty() {
+        return Ok(None);
+    }
+
+    // Found images or an edit of the public file have to be written back, the site root file is the one read at startup
+    let is_written = is_public_edit
+        || !diff_products_configs(&file_products_config, &products_config).is_empty();
+    let note = format!("reload of {}", config_path.display());
+    if is_written {
+        crate::save_products_config(db, products_config.clone(), ACTOR, &note)
+            .await
+            .map_err(|err| err.to_string())?;
+    } else {
+        // Written by someone else, like a rollback from the CLI, which stored the revision already
+        let latest_revision = crate::db::products_config::get_latest_revision(db)
+            .await
+            .map_err(|err| err.to_string())?;
+        if latest_revision.is_none_or(|latest_revision| {
+            !diff_products_configs(&latest_revision, &products_config).is_empty()
+        }) {
+            crate::db::products_config::insert_revision(db, &products_config, ACTOR, &note)
+                .await
+                .map_err(|err| err.to_string())?;
+        }
+    }
+    shared_appstate.set_products_config(products_config);
+
+    info!("Reloaded catalog from {}:\n{}", config_path.display(), diff);
+    Ok(Some(diff.summary()))
+}
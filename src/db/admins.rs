@@ -0,0 +1,123 @@
+//! Repository functions of admin accounts and their login sessions.
+//! Passwords are hashed by auth.rs before they get here, sessions are looked up by the hash of their token.
+
+use super::{unix_now, DbPool};
+use sqlx::Row;
+
+pub async fn count_admins(pool: &DbPool) -> Result<i64, sqlx::Error> {
+    sqlx::query("SELECT COUNT(*) AS count FROM admins")
+        .fetch_one(pool)
+        .await?
+        .try_get("count")
+}
+
+/// Adds an admin, or replaces the password of an existing one
+pub async fn upsert_admin(
+    pool: &DbPool,
+    username: &str,
+    password_hash: &str,
+) -> Result<(), sqlx::Error> {
+    let now = unix_now();
+    sqlx::query(
+        "INSERT INTO admins (username, password_hash, created_at, updated_at) VALUES (?, ?, ?, ?)
+        ON CONFLICT(username) DO UPDATE SET
+            password_hash = excluded.password_hash,
+            updated_at = excluded.updated_at",
+    )
+    .bind(username)
+    .bind(password_hash)
+    .bind(now)
+    .bind(now)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Removes an admin together with all of their sessions
+pub async fn delete_admin(pool: &DbPool, username: &str) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("DELETE FROM admin_sessions WHERE username = ?")
+        .bind(username)
+        .execute(&mut *tx)
+        .await?;
+    let deleted = sqlx::query("DELETE FROM admins WHERE username = ?")
+        .bind(username)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+    tx.commit().await?;
+    Ok(deleted > 0)
+}
+
+pub async fn get_admin_usernames(pool: &DbPool) -> Result<Vec<String>, sqlx::Error> {
+    let rows = sqlx::query("SELECT username FROM admins ORDER BY username")
+        .fetch_all(pool)
+        .await?;
+
+    rows.iter().map(|row| row.try_get("username")).collect()
+}
+
+pub async fn get_password_hash(
+    pool: &DbPool,
+    username: &str,
+) -> Result<Option<String>, sqlx::Error> {
+    let row = sqlx::query("SELECT password_hash FROM admins WHERE username = ?")
+        .bind(username)
+        .fetch_optional(pool)
+        .await?;
+
+    row.map(|row| row.try_get("password_hash")).transpose()
+}
+
+pub async fn create_session(
+    pool: &DbPool,
+    token_hash: &str,
+    username: &str,
+    expires_at: i64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO admin_sessions (token_hash, username, created_at, expires_at) VALUES (?, ?, ?, ?)",
+    )
+    .bind(token_hash)
+    .bind(username)
+    .bind(unix_now())
+    .bind(expires_at)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Username and expiry of a session that hasn't expired yet
+pub async fn get_session(
+    pool: &DbPool,
+    token_hash: &str,
+) -> Result<Option<(String, i64)>, sqlx::Error> {
+    let row = sqlx::query(
+        "SELECT username, expires_at FROM admin_sessions WHERE token_hash = ? AND expires_at > ?",
+    )
+    .bind(token_hash)
+    .bind(unix_now())
+    .fetch_optional(pool)
+    .await?;
+
+    row.map(|row| Ok((row.try_get("username")?, row.try_get("expires_at")?)))
+        .transpose()
+}
+
+pub async fn delete_session(pool: &DbPool, token_hash: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM admin_sessions WHERE token_hash = ?")
+        .bind(token_hash)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn delete_expired_sessions(pool: &DbPool) -> Result<u64, sqlx::Error> {
+    Ok(
+        sqlx::query("DELETE FROM admin_sessions WHERE expires_at <= ?")
+            .bind(unix_now())
+            .execute(pool)
+            .await?
+            .rows_affected(),
+    )
+}
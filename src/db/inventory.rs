@@ -0,0 +1,292 @@
+//! Repository functions of product stock and the reservations checkout sessions hold on it.
+//! Stock is reserved before a checkout session is made, released when the session expires
+//! and taken off the stock when the session is paid.
+
+use super::{unix_now, DbPool};
+use crate::stripe_retypes::*;
+use sqlx::{Row, SqliteConnection};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use thiserror::Error;
+
+const RESERVED: &str = "Reserved";
+const RELEASED: &str = "Released";
+const COMMITTED: &str = "Committed";
+
+#[derive(Debug, Error)]
+pub enum InventoryError {
+    #[error("Not enough stock of {product_id}: {available} available, {requested} requested")]
+    OutOfStock {
+        product_id: String,
+        available: i64,
+        requested: u64,
+    },
+    #[error(transparent)]
+    Db(#[from] sqlx::Error),
+}
+
+/// Key to reserve stock under before the checkout session and its id exist
+pub fn new_reservation_key() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    format!(
+        "pending_{}_{}",
+        unix_now(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+/// Sets the units on hand of a product, None stops tracking its stock
+pub async fn set_stock(
+    pool: &DbPool,
+    product_id: &str,
+    stock: Option<i64>,
+) -> Result<(), sqlx::Error> {
+    match stock {
+        Some(stock) => {
+            sqlx::query(
+                "INSERT INTO inventory (product_id, stock, updated_at) VALUES (?, ?, ?)
+                ON CONFLICT(product_id) DO UPDATE SET
+                    stock = excluded.stock,
+                    updated_at = excluded.updated_at",
+            )
+            .bind(product_id)
+            .bind(stock)
+            .bind(unix_now())
+            .execute(pool)
+            .await?;
+        }
+        None => {
+            sqlx::query("DELETE FROM inventory WHERE product_id = ?")
+                .bind(product_id)
+                .execute(pool)
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+async fn available_stock_conn(
+    conn: &mut SqliteConnection,
+    product_id: &str,
+) -> Result<Option<i64>, sqlx::Error> {
+    let row = sqlx::query(
+        "SELECT inventory.stock - COALESCE(
+            (SELECT SUM(quantity) FROM stock_reservations
+            WHERE product_id = inventory.product_id AND status = ?), 0) AS available
+        FROM inventory WHERE product_id = ?",
+    )
+    .bind(RESERVED)
+    .bind(product_id)
+    .fetch_optional(conn)
+    .await?;
+
+    row.map(|row| row.try_get("available")).transpose()
+}
+
+/// Stock left to sell of every tracked product, reservations subtracted
+pub async fn get_available_stock(pool: &DbPool) -> Result<HashMap<String, i64>, sqlx::Error> {
+    let rows = sqlx::query(
+        "SELECT product_id, inventory.stock - COALESCE(
+            (SELECT SUM(quantity) FROM stock_reservations
+            WHERE product_id = inventory.product_id AND status = ?), 0) AS available
+        FROM inventory",
+    )
+    .bind(RESERVED)
+    .fetch_all(pool)
+    .await?;
+
+    rows.iter()
+        .map(|row| Ok((row.try_get("product_id")?, row.try_get("available")?)))
+        .collect()
+}
+
+/// Reserves `items` (product id, quantity) under `reservation_key`, all of them or none.
+/// Untracked products are skipped.
+pub async fn reserve_stock(
+    pool: &DbPool,
+    reservation_key: &str,
+    items: &[(String, u64)],
+) -> Result<(), InventoryError> {
+    // IMMEDIATE takes the write lock up front, so two checkouts can't both see the last unit as available
+    let mut tx = pool.begin_with("BEGIN IMMEDIATE").await?;
+    let now = unix_now();
+
+    for (product_id, quantity) in items {
+        let Some(available) = available_stock_conn(&mut tx, product_id).await? else {
+            continue;
+        };
+        if available < *quantity as i64 {
+            return Err(InventoryError::OutOfStock {
+                product_id: product_id.clone(),
+                available: available.max(0),
+                requested: *quantity,
+            });
+        }
+
+        sqlx::query(
+            "INSERT INTO stock_reservations (reservation_key, product_id, quantity, status, created_at, updated_at)
+            VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(reservation_key)
+        .bind(product_id)
+        .bind(*quantity as i64)
+        .bind(RESERVED)
+        .bind(now)
+        .bind(now)
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Moves the reservations made under a pending key over to the checkout session made for them
+pub async fn attach_checkout_session(
+    pool: &DbPool,
+    reservation_key: &str,
+    checkout_session_id: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE stock_reservations SET reservation_key = ?, updated_at = ? WHERE reservation_key = ?",
+    )
+    .bind(checkout_session_id)
+    .bind(unix_now())
+    .bind(reservation_key)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+async fn set_reservation_status_conn(
+    conn: &mut SqliteConnection,
+    reservation_key: &str,
+    status: &str,
+) -> Result<u64, sqlx::Error> {
+    Ok(sqlx::query(
+        "UPDATE stock_reservations SET status = ?, updated_at = ?
+        WHERE reservation_key = ? AND status = ?",
+    )
+    .bind(status)
+    .bind(unix_now())
+    .bind(reservation_key)
+    .bind(RESERVED)
+    .execute(conn)
+    .await?
+    .rows_affected())
+}
+
+/// Gives the stock held under `reservation_key` back, like when making the checkout session failed
+pub async fn release_reservation(pool: &DbPool, reservation_key: &str) -> Result<(), sqlx::Error> {
+    let mut conn = pool.acquire().await?;
+    set_reservation_status_conn(&mut conn, reservation_key, RELEASED).await?;
+    Ok(())
+}
+
+/// Releases reservations still held after `max_age` seconds, left behind when their checkout session was never stored
+pub async fn release_stale_reservations(pool: &DbPool, max_age: i64) -> Result<u64, sqlx::Error> {
+    let now = unix_now();
+    Ok(sqlx::query(
+        "UPDATE stock_reservations SET status = ?, updated_at = ?
+        WHERE status = ? AND created_at < ?",
+    )
+    .bind(RELEASED)
+    .bind(now)
+    .bind(RESERVED)
+    .bind(now - max_age)
+    .execute(pool)
+    .await?
+    .rows_affected())
+}
+
+/// Takes what a paid checkout session bought off the stock, once.
+/// Its reservations may have been released as stale before the payment arrived, those are taken too.
+/// The paid line items count, the reserved quantities are only used when the session has none.
+async fn commit_paid_checkout_session_conn(
+    conn: &mut SqliteConnection,
+    checkout_session: &DbCheckoutSession,
+) -> Result<(), sqlx::Error> {
+    // Sessions without uncommitted reservations had no tracked products or were taken off already
+    let reserved: Vec<(String, i64)> = sqlx::query(
+        "SELECT product_id, SUM(quantity) AS quantity FROM stock_reservations
+        WHERE reservation_key = ? AND status IN (?, ?)
+        GROUP BY product_id",
+    )
+    .bind(&checkout_session.id)
+    .bind(RESERVED)
+    .bind(RELEASED)
+    .fetch_all(&mut *conn)
+    .await?
+    .iter()
+    .map(|row| Ok((row.try_get("product_id")?, row.try_get("quantity")?)))
+    .collect::<Result<_, sqlx::Error>>()?;
+    if reserved.is_empty() {
+        return Ok(());
+    }
+
+    let paid: HashMap<String, i64> = match &checkout_session.line_items {
+        Some(line_items) => {
+            let mut paid = HashMap::new();
+            for item in line_items {
+                if let Some(product_id) =
+                    item.price.as_ref().and_then(|price| price.product.clone())
+                {
+                    *paid.entry(product_id).or_default() += item.quantity.unwrap_or(1) as i64;
+                }
+            }
+            paid
+        }
+        None => reserved.into_iter().collect(),
+    };
+
+    let now = unix_now();
+    for (product_id, quantity) in &paid {
+        sqlx::query("UPDATE inventory SET stock = stock - ?, updated_at = ? WHERE product_id = ?")
+            .bind(quantity)
+            .bind(now)
+            .bind(product_id)
+            .execute(&mut *conn)
+            .await?;
+    }
+    sqlx::query(
+        "UPDATE stock_reservations SET status = ?, updated_at = ?
+        WHERE reservation_key = ? AND status IN (?, ?)",
+    )
+    .bind(COMMITTED)
+    .bind(now)
+    .bind(&checkout_session.id)
+    .bind(RESERVED)
+    .bind(RELEASED)
+    .execute(&mut *conn)
+    .await?;
+    log::info!(
+        "Took stock of paid checkout session {} off the inventory.",
+        checkout_session.id
+    );
+    Ok(())
+}
+
+/// Applies what happened to a checkout session to the stock it reserved:
+/// expired sessions release it, paid sessions take it off the stock.
+pub(crate) async fn apply_checkout_session_conn(
+    conn: &mut SqliteConnection,
+    checkout_session: &DbCheckoutSession,
+) -> Result<(), sqlx::Error> {
+    match (&checkout_session.status, &checkout_session.payment_status) {
+        (Some(DbCheckoutSessionStatus::Expired), _) => {
+            let released =
+                set_reservation_status_conn(conn, &checkout_session.id, RELEASED).await?;
+            if released > 0 {
+                log::info!(
+                    "Released stock reserved by expired checkout session {}.",
+                    checkout_session.id
+                );
+            }
+        }
+        (Some(DbCheckoutSessionStatus::Complete), DbCheckoutSessionPaymentStatus::Paid) => {
+            commit_paid_checkout_session_conn(conn, checkout_session).await?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
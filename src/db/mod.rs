@@ -0,0 +1,75 @@
+#![cfg(feature = "ssr")]
+
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqlitePoolOptions},
+    SqlitePool,
+};
+use std::str::FromStr;
+
+pub mod admins;
+pub mod inventory;
+pub mod orders;
+pub mod products_config;
+pub mod stripe;
+
+pub type DbPool = SqlitePool;
+
+/// Opens the SQLite database and runs pending migrations from `migrations/`
+pub async fn init_db() -> Result<DbPool, sqlx::Error> {
+    let database_url = std::env::var("DATABASE_URL")
+        .unwrap_or_else(|_| "sqlite:./data/farmtasker.sqlite".to_string());
+
+    let _ = std::fs::create_dir_all("data");
+
+    let options = SqliteConnectOptions::from_str(&database_url)?.create_if_missing(true);
+
+    let pool = SqlitePoolOptions::new()
+        .max_connections(5)
+        .connect_with(options)
+        .await?;
+
+    sqlx::migrate!().run(&pool).await?;
+
+    Ok(pool)
+}
+
+/// Gets the DbPool provided to the current request by main.rs
+pub fn use_db() -> Result<DbPool, leptos::ServerFnError> {
+    match leptos::use_context::<DbPool>() {
+        Some(ok) => Ok(ok),
+        None => Err(leptos::ServerFnError::ServerError(
+            "DbPool not found in context".into(),
+        )),
+    }
+}
+
+/// Serializes a unit enum like `DbCheckoutSessionStatus::Open` to the plain string stored in columns ("Open")
+pub(crate) fn enum_to_column<T: serde::Serialize>(value: &T) -> Option<String> {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(s)) => Some(s),
+        _ => None,
+    }
+}
+
+/// Reads back a unit enum stored with `enum_to_column`
+pub(crate) fn enum_from_column<T: serde::de::DeserializeOwned>(
+    value: &str,
+) -> Result<T, sqlx::Error> {
+    serde_json::from_value(serde_json::Value::String(value.to_string()))
+        .map_err(|e| sqlx::Error::Decode(Box::new(e)))
+}
+
+pub(crate) fn to_json<T: serde::Serialize>(value: &T) -> Result<String, sqlx::Error> {
+    serde_json::to_string(value).map_err(|e| sqlx::Error::Encode(Box::new(e)))
+}
+
+pub(crate) fn from_json<T: serde::de::DeserializeOwned>(data: &str) -> Result<T, sqlx::Error> {
+    serde_json::from_str(data).map_err(|e| sqlx::Error::Decode(Box::new(e)))
+}
+
+/// Seconds since the Unix epoch, like the timestamps Stripe sends
+pub fn unix_now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs() as i64)
+}
@@ -0,0 +1,238 @@
+//! Repository functions of orders.
+//! Orders are made here, inside the transaction that stores a completed and paid checkout session.
+
+use super::{enum_from_column, enum_to_column, from_json, to_json, unix_now, DbPool};
+use crate::orders::{
+    FulfilmentError, FulfilmentStatus, FulfilmentTransition, FulfilmentUpdate, Order,
+};
+use crate::stripe_retypes::*;
+use sqlx::{Row, SqliteConnection};
+use thiserror::Error;
+
+/// Actor of the transitions made by payments coming in
+const PAYMENT_ACTOR: &str = "stripe";
+
+#[derive(Debug, Error)]
+pub enum OrderUpdateError {
+    #[error("No order {0}")]
+    NotFound(String),
+    #[error(transparent)]
+    Fulfilment(#[from] FulfilmentError),
+    #[error(transparent)]
+    Db(#[from] sqlx::Error),
+}
+
+fn order_number(id: i64) -> String {
+    format!("FT-{:06}", id)
+}
+
+/// Makes the order of `checkout_session` if it is Complete and Paid and has no order yet.
+/// Returns the new order, None if nothing was made.
+pub(crate) async fn create_order_conn(
+    conn: &mut SqliteConnection,
+    checkout_session: &DbCheckoutSession,
+) -> Result<Option<Order>, sqlx::Error> {
+    if !Order::is_order_ready(checkout_session) {
+        return Ok(None);
+    }
+
+    let exists = sqlx::query("SELECT 1 FROM orders WHERE checkout_session_id = ?")
+        .bind(&checkout_session.id)
+        .fetch_optional(&mut *conn)
+        .await?
+        .is_some();
+    if exists {
+        return Ok(None);
+    }
+
+    // Sessions fetched without expanded line items use the ones stored before
+    let line_items = match &checkout_session.line_items {
+        Some(line_items) => line_items.clone(),
+        None => super::stripe::get_checkout_session_items(conn, &checkout_session.id).await?,
+    };
+
+    let Some(mut order) = Order::from_checkout_session(checkout_session, &line_items, unix_now())
+    else {
+        return Ok(None);
+    };
+
+    let id = sqlx::query(
+        "INSERT INTO orders (checkout_session_id, customer_email, payment_status, amount_total, created_at, data)
+        VALUES (?, ?, ?, ?, ?, ?)",
+    )
+    .bind(&order.checkout_session_id)
+    .bind(&order.customer_email)
+    .bind(enum_to_column(&order.payment_status))
+    .bind(order.amount_total)
+    .bind(order.created_at)
+    .bind(to_json(&order)?)
+    .execute(&mut *conn)
+    .await?
+    .last_insert_rowid();
+
+    order.order_number = order_number(id);
+    sqlx::query("UPDATE orders SET order_number = ?, fulfilment_status = ?, data = ? WHERE id = ?")
+        .bind(&order.order_number)
+        .bind(enum_to_column(&order.fulfilment_status))
+        .bind(to_json(&order)?)
+        .bind(id)
+        .execute(&mut *conn)
+        .await?;
+
+    insert_transition_conn(
+        conn,
+        id,
+        &FulfilmentTransition {
+            from: None,
+            to: order.fulfilment_status,
+            actor: PAYMENT_ACTOR.to_string(),
+            note: None,
+            at: order.created_at,
+        },
+    )
+    .await?;
+
+    log::info!(
+        "New order {} from checkout session {}.",
+        order.order_number,
+        order.checkout_session_id
+    );
+
+    Ok(Some(order))
+}
+
+async fn insert_transition_conn(
+    conn: &mut SqliteConnection,
+    order_id: i64,
+    transition: &FulfilmentTransition,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO order_fulfilment_transitions (order_id, from_status, to_status, actor, note, at)
+        VALUES (?, ?, ?, ?, ?, ?)",
+    )
+    .bind(order_id)
+    .bind(transition.from.as_ref().and_then(enum_to_column))
+    .bind(enum_to_column(&transition.to))
+    .bind(&transition.actor)
+    .bind(&transition.note)
+    .bind(transition.at)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Moves an order along its fulfilment lifecycle and records who did it.
+/// Fails without changing anything if the transition isn't allowed from the order's current status.
+pub async fn update_order_fulfilment(
+    pool: &DbPool,
+    order_number: &str,
+    update: FulfilmentUpdate,
+) -> Result<(Order, FulfilmentTransition), OrderUpdateError> {
+    let mut tx = pool.begin().await?;
+
+    let row = sqlx::query("SELECT id, data FROM orders WHERE order_number = ?")
+        .bind(order_number)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| OrderUpdateError::NotFound(order_number.to_string()))?;
+    let id: i64 = row.try_get("id")?;
+    let mut order: Order = from_json(row.try_get("data")?)?;
+
+    let transition = order.apply_fulfilment_update(update, unix_now())?;
+
+    sqlx::query("UPDATE orders SET fulfilment_status = ?, data = ? WHERE id = ?")
+        .bind(enum_to_column(&order.fulfilment_status))
+        .bind(to_json(&order)?)
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+    insert_transition_conn(&mut tx, id, &transition).await?;
+
+    tx.commit().await?;
+
+    log::info!(
+        "Order {} is now {} ({}).",
+        order.order_number,
+        order.fulfilment_status,
+        transition.actor
+    );
+
+    Ok((order, transition))
+}
+
+/// Fulfilment history of an order, oldest first
+pub async fn get_order_transitions(
+    pool: &DbPool,
+    order_number: &str,
+) -> Result<Vec<FulfilmentTransition>, sqlx::Error> {
+    let rows = sqlx::query(
+        "SELECT t.from_status, t.to_status, t.actor, t.note, t.at
+        FROM order_fulfilment_transitions t JOIN orders ON orders.id = t.order_id
+        WHERE orders.order_number = ?
+        ORDER BY t.id",
+    )
+    .bind(order_number)
+    .fetch_all(pool)
+    .await?;
+
+    rows.iter()
+        .map(|row| {
+            let from: Option<String> = row.try_get("from_status")?;
+            let to: String = row.try_get("to_status")?;
+            Ok(FulfilmentTransition {
+                from: from.as_deref().map(enum_from_column).transpose()?,
+                to: enum_from_column(&to)?,
+                actor: row.try_get("actor")?,
+                note: row.try_get("note")?,
+                at: row.try_get("at")?,
+            })
+        })
+        .collect()
+}
+
+/// Orders in a fulfilment status, oldest first so the packing run starts with them
+pub async fn get_orders_by_fulfilment_status(
+    pool: &DbPool,
+    fulfilment_status: FulfilmentStatus,
+) -> Result<Vec<Order>, sqlx::Error> {
+    let rows = sqlx::query("SELECT data FROM orders WHERE fulfilment_status = ? ORDER BY id")
+        .bind(enum_to_column(&fulfilment_status))
+        .fetch_all(pool)
+        .await?;
+
+    rows.iter()
+        .map(|row| from_json(row.try_get("data")?))
+        .collect()
+}
+
+pub async fn get_order(pool: &DbPool, order_number: &str) -> Result<Option<Order>, sqlx::Error> {
+    let row = sqlx::query("SELECT data FROM orders WHERE order_number = ?")
+        .bind(order_number)
+        .fetch_optional(pool)
+        .await?;
+
+    row.map(|row| from_json(row.try_get("data")?)).transpose()
+}
+
+pub async fn get_order_by_checkout_session(
+    pool: &DbPool,
+    checkout_session_id: &str,
+) -> Result<Option<Order>, sqlx::Error> {
+    let row = sqlx::query("SELECT data FROM orders WHERE checkout_session_id = ?")
+        .bind(checkout_session_id)
+        .fetch_optional(pool)
+        .await?;
+
+    row.map(|row| from_json(row.try_get("data")?)).transpose()
+}
+
+/// All orders, newest first
+pub async fn get_orders(pool: &DbPool) -> Result<Vec<Order>, sqlx::Error> {
+    let rows = sqlx::query("SELECT data FROM orders ORDER BY id DESC")
+        .fetch_all(pool)
+        .await?;
+
+    rows.iter()
+        .map(|row| from_json(row.try_get("data")?))
+        .collect()
+}
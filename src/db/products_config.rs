@@ -0,0 +1,110 @@
+//! Repository functions of the merge base of products_config.json,
+//! the products as Stripe had them at the last refresh, and of its revisions.
+
+use super::{from_json, to_json, unix_now, DbPool};
+use crate::products_config::ProductsConfigRevision;
+use crate::{CfgProduct, CfgProducts};
+use sqlx::Row;
+
+pub async fn get_products_config_base(pool: &DbPool) -> Result<CfgProducts, sqlx::Error> {
+    let rows = sqlx::query("SELECT data FROM products_config_base ORDER BY stripe_id")
+        .fetch_all(pool)
+        .await?;
+
+    let products = rows
+        .iter()
+        .map(|row| from_json::<CfgProduct>(&row.get::<String, _>("data")))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(CfgProducts(products))
+}
+
+/// Replaces the merge base with the products Stripe has now
+pub async fn set_products_config_base(
+    pool: &DbPool,
+    products_config: &CfgProducts,
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let updated_at = unix_now();
+
+    sqlx::query("DELETE FROM products_config_base")
+        .execute(&mut *tx)
+        .await?;
+    for product in &products_config.0 {
+        sqlx::query(
+            "INSERT INTO products_config_base (stripe_id, data, updated_at) VALUES (?, ?, ?)",
+        )
+        .bind(&product.stripe_id)
+        .bind(to_json(product)?)
+        .bind(updated_at)
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await
+}
+
+/// Stores a version of products_config.json as the next revision and returns its number
+pub async fn insert_revision(
+    pool: &DbPool,
+    products_config: &CfgProducts,
+    actor: &str,
+    note: &str,
+) -> Result<i64, sqlx::Error> {
+    let result = sqlx::query(
+        "INSERT INTO products_config_revisions (created_at, actor, note, products_count, data)
+        VALUES (?, ?, ?, ?, ?)",
+    )
+    .bind(unix_now())
+    .bind(actor)
+    .bind(note)
+    .bind(products_config.0.len() as i64)
+    .bind(to_json(products_config)?)
+    .execute(pool)
+    .await?;
+    Ok(result.last_insert_rowid())
+}
+
+/// All revisions without their products, newest first
+pub async fn get_revisions(pool: &DbPool) -> Result<Vec<ProductsConfigRevision>, sqlx::Error> {
+    let rows = sqlx::query(
+        "SELECT revision, created_at, actor, note, products_count
+        FROM products_config_revisions ORDER BY revision DESC",
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .iter()
+        .map(|row| ProductsConfigRevision {
+            revision: row.get("revision"),
+            created_at: row.get("created_at"),
+            actor: row.get("actor"),
+            note: row.get("note"),
+            products_count: row.get("products_count"),
+        })
+        .collect())
+}
+
+/// Products of the latest revision, None before the first write
+pub async fn get_latest_revision(pool: &DbPool) -> Result<Option<CfgProducts>, sqlx::Error> {
+    let row =
+        sqlx::query("SELECT data FROM products_config_revisions ORDER BY revision DESC LIMIT 1")
+            .fetch_optional(pool)
+            .await?;
+
+    row.map(|row| from_json(&row.get::<String, _>("data")))
+        .transpose()
+}
+
+pub async fn get_revision(
+    pool: &DbPool,
+    revision: i64,
+) -> Result<Option<CfgProducts>, sqlx::Error> {
+    let row = sqlx::query("SELECT data FROM products_config_revisions WHERE revision = ?")
+        .bind(revision)
+        .fetch_optional(pool)
+        .await?;
+
+    row.map(|row| from_json(&row.get::<String, _>("data")))
+        .transpose()
+}
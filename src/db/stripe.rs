@@ -0,0 +1,362 @@
+//! Repository functions of the local Stripe mirror.
+//! Keeps StripeData in SQLite so the catalog and order history outlive the process.
+
+use super::{enum_to_column, from_json, inventory, orders, to_json, DbPool};
+use crate::stripe_retypes::*;
+use crate::StripeData;
+use sqlx::{Row, SqliteConnection};
+
+const DEFAULT_SHIPPING_RATE_ID_KEY: &str = "default_shipping_rate_id";
+const FREE_SHIPPING_RATE_ID_KEY: &str = "free_shipping_rate_id";
+
+async fn upsert_price_conn(conn: &mut SqliteConnection, price: &DbPrice) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO prices (id, product_id, active, unit_amount, data) VALUES (?, ?, ?, ?, ?)
+        ON CONFLICT(id) DO UPDATE SET
+            product_id = excluded.product_id,
+            active = excluded.active,
+            unit_amount = excluded.unit_amount,
+            data = excluded.data",
+    )
+    .bind(&price.id)
+    .bind(&price.product)
+    .bind(price.active)
+    .bind(price.unit_amount)
+    .bind(to_json(price)?)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+async fn upsert_product_conn(
+    conn: &mut SqliteConnection,
+    product: &DbProduct,
+) -> Result<(), sqlx::Error> {
+    if let Some(default_price) = &product.default_price {
+        upsert_price_conn(&mut *conn, default_price).await?;
+    }
+
+    // default_price lives in prices table, so it's stored by id only
+    let mut data = product.clone();
+    data.default_price = None;
+
+    sqlx::query(
+        "INSERT INTO products (id, active, name, default_price_id, updated, data) VALUES (?, ?, ?, ?, ?, ?)
+        ON CONFLICT(id) DO UPDATE SET
+            active = excluded.active,
+            name = excluded.name,
+            default_price_id = excluded.default_price_id,
+            updated = excluded.updated,
+            data = excluded.data",
+    )
+    .bind(&product.id)
+    .bind(product.active)
+    .bind(&product.name)
+    .bind(product.default_price.as_ref().map(|p| p.id.clone()))
+    .bind(product.updated)
+    .bind(to_json(&data)?)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+async fn upsert_customer_conn(
+    conn: &mut SqliteConnection,
+    customer: &DbCustomer,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO customers (id, email, name, created, data) VALUES (?, ?, ?, ?, ?)
+        ON CONFLICT(id) DO UPDATE SET
+            email = excluded.email,
+            name = excluded.name,
+            created = excluded.created,
+            data = excluded.data",
+    )
+    .bind(&customer.id)
+    .bind(&customer.email)
+    .bind(&customer.name)
+    .bind(customer.created)
+    .bind(to_json(customer)?)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+async fn upsert_checkout_session_conn(
+    conn: &mut SqliteConnection,
+    checkout_session: &DbCheckoutSession,
+) -> Result<(), sqlx::Error> {
+    // line items live in checkout_session_items table
+    let mut data = checkout_session.clone();
+    data.line_items = None;
+
+    sqlx::query(
+        "INSERT INTO checkout_sessions
+            (id, status, payment_status, customer_id, customer_email, amount_total, created, expires_at, data)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+        ON CONFLICT(id) DO UPDATE SET
+            status = excluded.status,
+            payment_status = excluded.payment_status,
+            customer_id = excluded.customer_id,
+            customer_email = excluded.customer_email,
+            amount_total = excluded.amount_total,
+            created = excluded.created,
+            expires_at = excluded.expires_at,
+            data = excluded.data",
+    )
+    .bind(&checkout_session.id)
+    .bind(checkout_session.status.as_ref().and_then(enum_to_column))
+    .bind(enum_to_column(&checkout_session.payment_status))
+    .bind(checkout_session.customer.as_ref().map(|c| c.id.clone()))
+    .bind(&checkout_session.customer_email)
+    .bind(checkout_session.amount_total)
+    .bind(checkout_session.created)
+    .bind(checkout_session.expires_at)
+    .bind(to_json(&data)?)
+    .execute(&mut *conn)
+    .await?;
+
+    // Sessions fetched without expanded line items keep the ones stored before
+    if let Some(line_items) = &checkout_session.line_items {
+        sqlx::query("DELETE FROM checkout_session_items WHERE checkout_session_id = ?")
+            .bind(&checkout_session.id)
+            .execute(&mut *conn)
+            .await?;
+
+        for (position, item) in line_items.iter().enumerate() {
+            sqlx::query(
+                "INSERT INTO checkout_session_items
+                    (checkout_session_id, id, position, price_id, quantity, amount_total, data)
+                VALUES (?, ?, ?, ?, ?, ?, ?)",
+            )
+            .bind(&checkout_session.id)
+            .bind(&item.id)
+            .bind(position as i64)
+            .bind(item.price.as_ref().map(|p| p.id.clone()))
+            .bind(item.quantity.map(|q| q as i64))
+            .bind(item.amount_total)
+            .bind(to_json(item)?)
+            .execute(&mut *conn)
+            .await?;
+        }
+    }
+
+    inventory::apply_checkout_session_conn(conn, checkout_session).await?;
+    orders::create_order_conn(conn, checkout_session).await?;
+    Ok(())
+}
+
+async fn set_setting_conn(
+    conn: &mut SqliteConnection,
+    key: &str,
+    value: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO stripe_settings (key, value) VALUES (?, ?)
+        ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+    )
+    .bind(key)
+    .bind(value)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+pub async fn upsert_price(pool: &DbPool, price: &DbPrice) -> Result<(), sqlx::Error> {
+    let mut conn = pool.acquire().await?;
+    upsert_price_conn(&mut conn, price).await
+}
+
+pub async fn upsert_product(pool: &DbPool, product: &DbProduct) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    upsert_product_conn(&mut tx, product).await?;
+    tx.commit().await
+}
+
+/// Stripe doesn't hand out deleted products anymore, so they are kept as inactive
+pub async fn deactivate_product(pool: &DbPool, product_id: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE products SET active = 0 WHERE id = ?")
+        .bind(product_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Removes a deleted price, products that had it as default price are left without one
+pub async fn delete_price(pool: &DbPool, price_id: &str) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("UPDATE products SET default_price_id = NULL WHERE default_price_id = ?")
+        .bind(price_id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("DELETE FROM prices WHERE id = ?")
+        .bind(price_id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn upsert_customer(pool: &DbPool, customer: &DbCustomer) -> Result<(), sqlx::Error> {
+    let mut conn = pool.acquire().await?;
+    upsert_customer_conn(&mut conn, customer).await
+}
+
+pub async fn delete_customer(pool: &DbPool, customer_id: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM customers WHERE id = ?")
+        .bind(customer_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn upsert_checkout_session(
+    pool: &DbPool,
+    checkout_session: &DbCheckoutSession,
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    upsert_checkout_session_conn(&mut tx, checkout_session).await?;
+    tx.commit().await
+}
+
+pub async fn get_products(pool: &DbPool, only_active: bool) -> Result<Vec<DbProduct>, sqlx::Error> {
+    let rows = sqlx::query(
+        "SELECT products.data AS data, prices.data AS price_data
+        FROM products LEFT JOIN prices ON prices.id = products.default_price_id
+        WHERE products.active = 1 OR ? = 0
+        ORDER BY products.name",
+    )
+    .bind(only_active)
+    .fetch_all(pool)
+    .await?;
+
+    rows.iter()
+        .map(|row| {
+            let mut product: DbProduct = from_json(row.try_get("data")?)?;
+            let price_data: Option<&str> = row.try_get("price_data")?;
+            product.default_price = price_data.map(from_json).transpose()?;
+            Ok(product)
+        })
+        .collect()
+}
+
+pub async fn get_customers(pool: &DbPool) -> Result<Vec<DbCustomer>, sqlx::Error> {
+    let rows = sqlx::query("SELECT data FROM customers ORDER BY created DESC")
+        .fetch_all(pool)
+        .await?;
+
+    rows.iter()
+        .map(|row| from_json(row.try_get("data")?))
+        .collect()
+}
+
+pub(crate) async fn get_checkout_session_items(
+    conn: &mut SqliteConnection,
+    checkout_session_id: &str,
+) -> Result<Vec<DbCheckoutSessionItem>, sqlx::Error> {
+    let rows = sqlx::query(
+        "SELECT data FROM checkout_session_items WHERE checkout_session_id = ? ORDER BY position",
+    )
+    .bind(checkout_session_id)
+    .fetch_all(conn)
+    .await?;
+
+    rows.iter()
+        .map(|row| from_json(row.try_get("data")?))
+        .collect()
+}
+
+pub async fn get_checkout_session(
+    pool: &DbPool,
+    checkout_session_id: &str,
+) -> Result<Option<DbCheckoutSession>, sqlx::Error> {
+    let mut conn = pool.acquire().await?;
+    let row = sqlx::query("SELECT data FROM checkout_sessions WHERE id = ?")
+        .bind(checkout_session_id)
+        .fetch_optional(&mut *conn)
+        .await?;
+
+    match row {
+        Some(row) => {
+            let mut checkout_session: DbCheckoutSession = from_json(row.try_get("data")?)?;
+            let line_items = get_checkout_session_items(&mut conn, &checkout_session.id).await?;
+            checkout_session.line_items = (!line_items.is_empty()).then_some(line_items);
+            Ok(Some(checkout_session))
+        }
+        None => Ok(None),
+    }
+}
+
+pub async fn get_checkout_sessions(pool: &DbPool) -> Result<Vec<DbCheckoutSession>, sqlx::Error> {
+    let mut conn = pool.acquire().await?;
+    let rows = sqlx::query("SELECT data FROM checkout_sessions ORDER BY created DESC")
+        .fetch_all(&mut *conn)
+        .await?;
+
+    let mut checkout_sessions = Vec::with_capacity(rows.len());
+    for row in rows {
+        let mut checkout_session: DbCheckoutSession = from_json(row.try_get("data")?)?;
+        let line_items = get_checkout_session_items(&mut conn, &checkout_session.id).await?;
+        checkout_session.line_items = (!line_items.is_empty()).then_some(line_items);
+        checkout_sessions.push(checkout_session);
+    }
+    Ok(checkout_sessions)
+}
+
+impl StripeData {
+    /// Stores a full fetch of StripeData.
+    /// Products missing from the fetch were archived in Stripe, so they are marked inactive.
+    pub async fn save_to_db(&self, pool: &DbPool) -> Result<(), sqlx::Error> {
+        let mut tx = pool.begin().await?;
+
+        sqlx::query("UPDATE products SET active = 0")
+            .execute(&mut *tx)
+            .await?;
+        for product in &self.products {
+            upsert_product_conn(&mut tx, product).await?;
+        }
+        for customer in &self.customers {
+            upsert_customer_conn(&mut tx, customer).await?;
+        }
+        for checkout_session in &self.checkout_sessions {
+            upsert_checkout_session_conn(&mut tx, checkout_session).await?;
+        }
+        set_setting_conn(
+            &mut tx,
+            DEFAULT_SHIPPING_RATE_ID_KEY,
+            &self.default_shipping_rate_id,
+        )
+        .await?;
+        set_setting_conn(&mut tx, FREE_SHIPPING_RATE_ID_KEY, &self.free_shipping_rate_id).await?;
+
+        tx.commit().await
+    }
+
+    /// Loads the last StripeData stored with `save_to_db`.
+    /// Returns None if nothing was ever stored.
+    pub async fn load_from_db(pool: &DbPool) -> Result<Option<Self>, sqlx::Error> {
+        let setting = |key: &'static str| async move {
+            sqlx::query("SELECT value FROM stripe_settings WHERE key = ?")
+                .bind(key)
+                .fetch_optional(pool)
+                .await?
+                .map(|row| row.try_get::<String, _>("value"))
+                .transpose()
+        };
+
+        let (Some(default_shipping_rate_id), Some(free_shipping_rate_id)) = (
+            setting(DEFAULT_SHIPPING_RATE_ID_KEY).await?,
+            setting(FREE_SHIPPING_RATE_ID_KEY).await?,
+        ) else {
+            return Ok(None);
+        };
+
+        Ok(Some(StripeData {
+            products: get_products(pool, true).await?,
+            customers: get_customers(pool).await?,
+            checkout_sessions: get_checkout_sessions(pool).await?,
+            default_shipping_rate_id,
+            free_shipping_rate_id,
+        }))
+    }
+}
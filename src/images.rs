@@ -0,0 +1,259 @@
+//! Image pipeline of the product assets. Source images in any common format put into
+//! products_assets/<item_number>/ are converted to webp in its ready_assets/: a copy at most
+//! MAX_WIDTH wide, narrower copies named like "front-640w.webp" for srcset, and thumbnail.webp.
+//! The file name of the source thumbnail.webp was made from is kept next to it in thumbnail.source.
+//! Images are encoded lossy at WEBP_QUALITY, decoding and encoding again leaves EXIF and other metadata behind.
+
+use crate::products_config::{ImageVariant, LocalImage};
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageDecoder, ImageReader};
+use log::*;
+use std::path::{Path, PathBuf};
+
+/// Widths of the narrower copies, only the ones narrower than the image are made
+pub const VARIANT_WIDTHS: [u32; 3] = [320, 640, 1024];
+const MAX_WIDTH: u32 = 1600;
+const THUMBNAIL_WIDTH: u32 = 480;
+const THUMBNAIL_SOURCE_FILE: &str = "thumbnail.source";
+/// Quality of the lossy webp encoder from 0 to 100, photos look the same as lossless at a fraction of the size
+const WEBP_QUALITY: f32 = 80.0;
+const SOURCE_EXTENSIONS: [&str; 8] = ["png", "jpg", "jpeg", "webp", "gif", "bmp", "tif", "tiff"];
+
+#[derive(Debug, Default)]
+pub struct ImagesReport {
+    pub processed: Vec<PathBuf>,
+    pub up_to_date: usize,
+    pub failed: Vec<(PathBuf, String)>,
+}
+
+impl std::fmt::Display for ImagesReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for path in &self.processed {
+            writeln!(f, "processed {}", path.display())?;
+        }
+        for (path, err) in &self.failed {
+            writeln!(f, "failed {}: {}", path.display(), err)?;
+        }
+        writeln!(
+            f,
+            "{} processed, {} up to date, {} failed.",
+            self.processed.len(),
+            self.up_to_date,
+            self.failed.len()
+        )
+    }
+}
+
+/// Width of a narrower copy from its file name, like 640 of "front-640w.webp"
+pub fn variant_width(path: &Path) -> Option<u32> {
+    let stem = path.file_stem()?.to_str()?;
+    let (_, width) = stem.rsplit_once('-')?;
+    width.strip_suffix('w')?.parse().ok()
+}
+
+fn variant_path(path: &Path, width: u32) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{}-{}w.webp", stem, width))
+}
+
+fn is_source_image(path: &Path) -> bool {
+    path.is_file()
+        && path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| SOURCE_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+fn modified(path: &Path) -> Option<std::time::SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Converts the source images of every product in site_root/products_assets.
+/// Sources whose webp is newer than them are skipped.
+pub fn process_product_images(site_root: &Path) -> ImagesReport {
+    let mut report = ImagesReport::default();
+
+    let mut product_dirs: Vec<PathBuf> = std::fs::read_dir(site_root.join("products_assets"))
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect();
+    product_dirs.sort();
+
+    for product_dir in product_dirs {
+        let mut sources: Vec<PathBuf> = std::fs::read_dir(&product_dir)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| is_source_image(path))
+            .collect();
+        sources.sort();
+        if sources.is_empty() {
+            continue;
+        }
+
+        let ready_dir = product_dir.join("ready_assets");
+        if let Err(err) = std::fs::create_dir_all(&ready_dir) {
+            report.failed.push((ready_dir, err.to_string()));
+            continue;
+        }
+
+        // A source named thumbnail.* becomes the thumbnail, otherwise the one it was made from before.
+        // Without a thumbnail or when its source is gone the first source does, a thumbnail that
+        // no source made was made by hand and stays.
+        let recorded_source = std::fs::read_to_string(ready_dir.join(THUMBNAIL_SOURCE_FILE))
+            .ok()
+            .map(|file_name| product_dir.join(file_name.trim()));
+        let thumbnail_source = sources
+            .iter()
+            .find(|path| path.file_stem().is_some_and(|stem| stem == "thumbnail"))
+            .or_else(|| {
+                recorded_source
+                    .as_ref()
+                    .filter(|path| sources.contains(path))
+            })
+            .or_else(|| {
+                (recorded_source.is_some() || !ready_dir.join("thumbnail.webp").exists())
+                    .then(|| &sources[0])
+            })
+            .cloned();
+
+        for source in &sources {
+            let stem = source.file_stem().unwrap_or_default().to_string_lossy();
+            let is_thumbnail_source = thumbnail_source.as_ref() == Some(source);
+            let mut outputs = vec![(ready_dir.join(format!("{}.webp", stem)), MAX_WIDTH)];
+            if is_thumbnail_source && stem != "thumbnail" {
+                outputs.push((ready_dir.join("thumbnail.webp"), THUMBNAIL_WIDTH));
+            }
+
+            let source_modified = modified(source);
+            // A thumbnail.webp newer than its new source was made from another one
+            let is_up_to_date = outputs
+                .iter()
+                .all(|(output, _)| modified(output) >= source_modified)
+                && (!is_thumbnail_source || recorded_source.as_ref() == Some(source));
+            if is_up_to_date {
+                report.up_to_date += 1;
+                continue;
+            }
+
+            match process_source(source, &outputs) {
+                Ok(()) => {
+                    if is_thumbnail_source {
+                        let file_name = source.file_name().unwrap_or_default().to_string_lossy();
+                        // Without it the thumbnail is only made again next time
+                        if let Err(err) = std::fs::write(
+                            ready_dir.join(THUMBNAIL_SOURCE_FILE),
+                            file_name.as_bytes(),
+                        ) {
+                            warn!(
+                                "Couldn't record the thumbnail source {}: {}",
+                                source.display(),
+                                err
+                            );
+                        }
+                    }
+                    report.processed.push(source.clone())
+                }
+                Err(err) => {
+                    error!("Couldn't process image {}: {}", source.display(), err);
+                    report.failed.push((source.clone(), err.to_string()))
+                }
+            }
+        }
+    }
+
+    report
+}
+
+/// Writes the source as webp to each output at most `max_width` wide, with its narrower copies
+fn process_source(source: &Path, outputs: &[(PathBuf, u32)]) -> Result<(), image::ImageError> {
+    let mut decoder = ImageReader::open(source)?
+        .with_guessed_format()?
+        .into_decoder()?;
+    // The orientation lives in EXIF, which isn't written back, so it's applied to the pixels
+    let orientation = decoder.orientation()?;
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    image.apply_orientation(orientation);
+    // The webp encoder only takes 8 bit RGB(A)
+    let image = match image.color().has_alpha() {
+        true => DynamicImage::ImageRgba8(image.into_rgba8()),
+        false => DynamicImage::ImageRgb8(image.into_rgb8()),
+    };
+
+    for (output, max_width) in outputs {
+        let image = resize_to_width(&image, *max_width);
+        write_webp(&image, output)?;
+        for width in VARIANT_WIDTHS {
+            if width < image.width() {
+                write_webp(
+                    &resize_to_width(&image, width),
+                    &variant_path(output, width),
+                )?;
+            }
+        }
+    }
+    info!("Processed image {}", source.display());
+    Ok(())
+}
+
+fn resize_to_width(image: &DynamicImage, width: u32) -> DynamicImage {
+    match image.width() > width {
+        true => image.resize(width, u32::MAX, FilterType::Lanczos3),
+        false => image.clone(),
+    }
+}
+
+/// Writes to a temporary file first, so pages and the catalog watcher never see half written images.
+/// The webp encoder of the image crate is lossless only, libwebp encodes lossy.
+fn write_webp(image: &DynamicImage, path: &Path) -> Result<(), image::ImageError> {
+    let encoder = match image {
+        DynamicImage::ImageRgba8(rgba) => {
+            webp::Encoder::from_rgba(rgba, rgba.width(), rgba.height())
+        }
+        image => webp::Encoder::from_rgb(image.as_bytes(), image.width(), image.height()),
+    };
+    let webp = encoder.encode_simple(false, WEBP_QUALITY).map_err(|err| {
+        image::ImageError::Encoding(image::error::EncodingError::new(
+            image::ImageFormat::WebP.into(),
+            format!("{:?}", err),
+        ))
+    })?;
+
+    let tmp_path = path.with_extension("webp.tmp");
+    std::fs::write(&tmp_path, &*webp)?;
+    std::fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+/// Size and narrower copies of a local image, `path` being absolute in the site like in local_images
+pub fn describe_local_image(site_root: &Path, path: &Path) -> Option<LocalImage> {
+    let file_path = site_root.join(path.strip_prefix("/").unwrap_or(path));
+    let (width, height) = image::image_dimensions(&file_path).ok()?;
+
+    let mut variants: Vec<ImageVariant> = VARIANT_WIDTHS
+        .into_iter()
+        .filter_map(|width| {
+            let variant = variant_path(path, width);
+            let (width, height) = image::image_dimensions(variant_path(&file_path, width)).ok()?;
+            Some(ImageVariant {
+                path: variant,
+                width,
+                height,
+            })
+        })
+        .collect();
+    variants.sort_by_key(|variant| variant.width);
+
+    Some(LocalImage {
+        path: path.to_path_buf(),
+        width,
+        height,
+        variants,
+    })
+}
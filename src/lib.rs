@@ -1,10 +1,19 @@
 #![allow(unused)]
 pub mod app;
 #[cfg(feature = "ssr")]
+pub mod auth;
+#[cfg(feature = "ssr")]
+pub mod catalog_watch;
+#[cfg(feature = "ssr")]
 pub mod db;
 pub mod error_template;
 #[cfg(feature = "ssr")]
 pub mod fileserv;
+#[cfg(feature = "ssr")]
+pub mod images;
+pub mod orders;
+#[cfg(feature = "ssr")]
+pub mod payments;
 pub mod products_config;
 pub mod stripe_retypes;
 
@@ -13,10 +22,14 @@ mod server;
 mod state;
 #[cfg(feature = "ssr")]
 pub mod sync;
+#[cfg(feature = "ssr")]
+pub mod webhook;
 
 pub use cart_state::ShoppingCart;
 pub use server::*;
-pub use state::{AppState, StripeData};
+pub use state::{AppState, Catalog, StripeData, StripeStatus, SyncKind, SyncReport, SyncStatus};
+#[cfg(feature = "ssr")]
+pub use state::SharedAppState;
 
 #[cfg(feature = "hydrate")]
 #[wasm_bindgen::prelude::wasm_bindgen]
@@ -66,12 +66,15 @@ async fn main() {
 
     tracing::info!("LEPTOS_SITE_ROOT={}", site_root);
 
-    let key = std::env::var("STRIPE_KEY")
-        .expect("Missing STRIPE_KEY variable in env.
-                \nPlease do 'export STRIPE_KEY=sk_*******'.
-                \nYou can verify if env variable 'STRIPE_KEY' is present with 'env | grep STRIPE_KEY'");
-
-    let stripe_client = stripe::Client::new(key.clone());
+    // STRIPE_KEY is only needed by the Stripe backend, PAYMENT_BACKEND=fake runs without it
+    if let Err(err) = farmtasker_au::payments::payment_backend() {
+        tracing::error!(
+            "Couldn't set up the payment backend: {}
+                \nPlease do 'export STRIPE_KEY=sk_*******' or 'export PAYMENT_BACKEND=fake'.",
+            err
+        );
+        return;
+    }
 
     let db = match farmtasker_au::db::init_db().await {
         Ok(pool) => pool,
@@ -81,82 +84,159 @@ async fn main() {
         }
     };
 
-    let appstate = farmtasker_au::AppState {
-        stripe_data: match farmtasker_au::StripeData::new_fetch().await {
-            Ok(ok) => Some(ok),
-            Err(err) => {
-                leptos::logging::log!("No StripeData in AppState");
-                None
+    if let Err(err) = farmtasker_au::auth::bootstrap_admin(&db).await {
+        tracing::error!("Couldn't create admin from env: {}", err);
+    }
+
+    // Refresh config before it's loaded into AppState, so the server starts with the refreshed one
+    let started_at = farmtasker_au::db::unix_now();
+    let refresh_result = refresh_products_config(&db, "startup", false).await;
+    if let Err(err) = &refresh_result {
+        tracing::error!("Couldn't refresh local CfgProducts: {}", err);
+    }
+    let refresh_report = farmtasker_au::SyncReport::new(
+        farmtasker_au::SyncKind::ProductsRefresh,
+        "startup",
+        started_at,
+        refresh_result.map(|diff| diff.summary()),
+    );
+    tracing::info!("");
+
+    // When Stripe can't be reached, boot from the last StripeData stored in the database
+    // and keep retrying the live fetch in the background (see spawn below)
+    let started_at = farmtasker_au::db::unix_now();
+    let fetch_result = farmtasker_au::StripeData::new_fetch().await;
+    let fetch_report = farmtasker_au::SyncReport::new(
+        farmtasker_au::SyncKind::StripeSync,
+        "startup",
+        started_at,
+        fetch_result.as_ref().map(|stripe_data| {
+            format!(
+                "{} products, {} customers",
+                stripe_data.products.len(),
+                stripe_data.customers.len()
+            )
+        }),
+    );
+    let (stripe_data, stripe_status) = match fetch_result {
+        Ok(ok) => {
+            if let Err(err) = ok.save_to_db(&db).await {
+                tracing::error!("Failed to store StripeData in database: {}", err);
             }
-        },
-        products_config: match fetch_local_product_info().await {
-            Ok(ok) => Some(ok),
-            Err(err) => {
-                leptos::logging::log!("No local CfgProducts in AppState");
-                None
+            (Some(ok), farmtasker_au::StripeStatus::Live)
+        }
+        Err(err) => {
+            tracing::error!("Couldn't fetch StripeData from Stripe: {}", err);
+            tracing::warn!("Starting in degraded mode, checkout is unavailable until Stripe is reachable.");
+            match farmtasker_au::StripeData::load_from_db(&db).await {
+                Ok(Some(ok)) => {
+                    tracing::info!("Loaded last stored StripeData from database.");
+                    (Some(ok), farmtasker_au::StripeStatus::Degraded)
+                }
+                Ok(None) => {
+                    tracing::error!("No StripeData stored in database.");
+                    (None, farmtasker_au::StripeStatus::Degraded)
+                }
+                Err(err) => {
+                    tracing::error!("Failed to load StripeData from database: {}", err);
+                    (None, farmtasker_au::StripeStatus::Degraded)
+                }
             }
-        },
+        }
     };
 
-    refresh_local_product_info(false).await;
-    tracing::info!("");
+    let products_config = match read_products_config().await {
+        Ok(ok) => Some(ok),
+        Err(err) => {
+            tracing::error!("Couldn't fetch local CfgProducts: {}", err);
+            // Derive the catalog from the stored StripeData as a last resort
+            stripe_data
+                .clone()
+                .map(farmtasker_au::StripeData::derive_products_config)
+        }
+    };
 
-    assert!(
-        &appstate.stripe_data.clone().is_some(),
-        "No StripeData in AppState during server init.
-        \nCheck if the StripeData could be fetched from internet.
-        \nPlease verify that you have internet connection.",
-    );
-    assert!(
-        &appstate.products_config.clone().is_some(),
-        "No CfgProducts in AppState during server init.
-        \nCheck if the products config could be initiated.
-        \nHow did this happen?",
-    );
+    let appstate = farmtasker_au::AppState {
+        stripe_data,
+        products_config,
+        stripe_status,
+        sync_reports: vec![fetch_report, refresh_report],
+    };
+
+    if appstate.stripe_data.is_none() {
+        tracing::error!(
+            "No StripeData in AppState during server init.
+            \nCheck if the StripeData could be fetched from internet.
+            \nPlease verify that you have internet connection."
+        );
+    }
+    if appstate.products_config.is_none() {
+        tracing::error!(
+            "No CfgProducts in AppState during server init.
+            \nCheck if the products config could be initiated."
+        );
+    }
+
+    if let Some(stripe_data) = &appstate.stripe_data {
+        let products = &stripe_data.products;
+        tracing::info!("Listing products:");
+        for i in products {
+            tracing::info!(
+                "#{:?} Product: {:#?} - {:#?}$ AUD",
+                i.metadata
+                    .clone()
+                    .unwrap_or(HashMap::new())
+                    .get("item_number")
+                    .map_or("_", |v| v)
+                    .parse()
+                    .unwrap_or(-1),
+                i.name,
+                i.default_price
+                    .as_ref()
+                    .and_then(|price| price.unit_amount)
+                    .unwrap_or_default() as f64
+                    / 100.0
+            );
+        }
+        let customers = &stripe_data.customers;
+
+        let checkout_sessions = &stripe_data.checkout_sessions;
+        tracing::info!("Total \"Products\": {:}", products.len());
+
+        tracing::info!("Total \"Customers\": {:}", customers.len());
 
-    let products = &appstate.stripe_data.clone().unwrap().products;
-    tracing::info!("Listing products:");
-    for i in products {
         tracing::info!(
-            "#{:?} Product: {:#?} - {:#?}$ AUD",
-            i.metadata
-                .clone()
-                .unwrap_or(HashMap::new())
-                .get("item_number")
-                .map_or("_", |v| v)
-                .parse()
-                .unwrap_or(-1),
-            i.name,
-            i.default_price.clone().unwrap().unit_amount.unwrap() as f64 / 100.0
+            "Total of currently Open \"Checkout Sessions\": {:}",
+            checkout_sessions
+                .iter()
+                .filter(|c| match &c.status {
+                    Some(s) => match s {
+                        crate::stripe_retypes::DbCheckoutSessionStatus::Complete => false,
+                        crate::stripe_retypes::DbCheckoutSessionStatus::Expired => false,
+                        crate::stripe_retypes::DbCheckoutSessionStatus::Open => true,
+                    },
+                    None => false,
+                })
+                .collect::<Vec<&crate::stripe_retypes::DbCheckoutSession>>()
+                .len()
         );
     }
-    let customers = &appstate.stripe_data.clone().unwrap().customers;
-
-    let checkout_sessions = &appstate
-        .stripe_data
-        .clone()
-        .unwrap()
-        .checkout_sessions
-        .clone();
-    tracing::info!("Total \"Products\": {:}", products.len());
-
-    tracing::info!("Total \"Customers\": {:}", customers.len());
-
-    tracing::info!(
-        "Total of currently Open \"Checkout Sessions\": {:}",
-        checkout_sessions
-            .iter()
-            .filter(|c| match &c.status {
-                Some(s) => match s {
-                    crate::stripe_retypes::DbCheckoutSessionStatus::Complete => false,
-                    crate::stripe_retypes::DbCheckoutSessionStatus::Expired => false,
-                    crate::stripe_retypes::DbCheckoutSessionStatus::Open => true,
-                },
-                None => false,
-            })
-            .collect::<Vec<&crate::stripe_retypes::DbCheckoutSession>>()
-            .len()
-    );
+
+    // One shared AppState for the whole server, sync/refresh/webhooks swap new data into it
+    let shared_appstate = farmtasker_au::SharedAppState::new(appstate);
+
+    if stripe_status == farmtasker_au::StripeStatus::Degraded {
+        tokio::spawn(farmtasker_au::sync::retry_stripe_fetch_until_live(
+            shared_appstate.clone(),
+            db.clone(),
+        ));
+    }
+
+    // Serve edits of products_config.json and new product images without a restart
+    tokio::spawn(farmtasker_au::catalog_watch::watch_products_config(
+        shared_appstate.clone(),
+        db.clone(),
+    ));
 
     // build our application with a route
     let app = Router::new()
@@ -164,15 +244,38 @@ async fn main() {
             &leptos_options,
             routes,
             {
-                let appstate = appstate.clone();
-                move || provide_context(Some(appstate.clone()))
+                let shared_appstate = shared_appstate.clone();
+                let db = db.clone();
+                move || {
+                    provide_context(shared_appstate.clone());
+                    provide_context(db.clone());
+                }
             },
             App,
         )
         .fallback(file_and_error_handler)
-        .route("/api/*fn_name", post(leptos_axum::handle_server_fns))
+        .route(
+            "/api/*fn_name",
+            post({
+                let shared_appstate = shared_appstate.clone();
+                let db = db.clone();
+                move |req: http::Request<body::Body>| {
+                    leptos_axum::handle_server_fns_with_context(
+                        move || {
+                            provide_context(shared_appstate.clone());
+                            provide_context(db.clone());
+                        },
+                        req,
+                    )
+                }
+            }),
+        )
+        .route(
+            "/api/webhooks/stripe",
+            post(farmtasker_au::webhook::stripe_webhook_handler),
+        )
         .layer(Extension(db.clone()))
-        .layer(Extension(appstate.clone()))
+        .layer(Extension(shared_appstate.clone()))
         .with_state(leptos_options);
 
     let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
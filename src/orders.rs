@@ -0,0 +1,416 @@
+//! Orders are our own record of a purchase, made once from a completed and paid checkout session.
+//! Unlike the mirrored DbCheckoutSession they keep a snapshot of what was bought and at what price.
+
+use crate::stripe_retypes::*;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Order {
+    pub order_number: String, // like "FT-000042", assigned when the order is stored
+    pub checkout_session_id: String,
+    pub customer_id: Option<String>,
+    pub customer_name: Option<String>,
+    pub customer_email: Option<String>,
+    pub customer_phone: Option<String>,
+    pub shipping_address: Option<DbShipping>,
+    pub billing_address: Option<DbAddress>,
+    pub items: Vec<OrderItem>,
+    pub amount_subtotal: i64,
+    pub amount_shipping: i64,
+    pub amount_total: i64,
+    pub currency: String, // lowercase ISO code like "aud"
+    pub payment_status: DbCheckoutSessionPaymentStatus,
+    pub livemode: bool,
+    #[serde(default)]
+    pub fulfilment_status: FulfilmentStatus,
+    // Measured in seconds since the Unix epoch.
+    pub placed_at: Option<i64>, // when the checkout session was created
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+/// A line of an order with the price it was bought at
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OrderItem {
+    pub product_id: Option<String>,
+    pub price_id: Option<String>,
+    pub name: String,
+    pub quantity: u64,
+    pub unit_amount: Option<i64>,
+    pub amount_discount: i64,
+    pub amount_total: i64,
+}
+
+/// What the checkout success page shows of an order, without the customer's contact details or address.
+/// Anyone with the checkout session id can ask for it, the full Order is only for admins.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OrderSummary {
+    pub order_number: String,
+    pub fulfilment_status: FulfilmentStatus,
+    pub items: Vec<OrderSummaryItem>,
+    pub amount_shipping: i64,
+    pub amount_total: i64,
+    pub currency: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OrderSummaryItem {
+    pub name: String,
+    pub quantity: u64,
+    pub amount_total: i64,
+}
+
+impl From<&Order> for OrderSummary {
+    fn from(order: &Order) -> Self {
+        OrderSummary {
+            order_number: order.order_number.clone(),
+            fulfilment_status: order.fulfilment_status,
+            items: order
+                .items
+                .iter()
+                .map(|item| OrderSummaryItem {
+                    name: item.name.clone(),
+                    quantity: item.quantity,
+                    amount_total: item.amount_total,
+                })
+                .collect(),
+            amount_shipping: order.amount_shipping,
+            amount_total: order.amount_total,
+            currency: order.currency.clone(),
+        }
+    }
+}
+
+impl From<&DbCheckoutSessionItem> for OrderItem {
+    fn from(value: &DbCheckoutSessionItem) -> Self {
+        OrderItem {
+            product_id: value.price.as_ref().and_then(|price| price.product.clone()),
+            price_id: value.price.as_ref().map(|price| price.id.clone()),
+            name: value.description.clone(),
+            quantity: value.quantity.unwrap_or(1),
+            unit_amount: value.price.as_ref().and_then(|price| price.unit_amount),
+            amount_discount: value.amount_discount,
+            amount_total: value.amount_total,
+        }
+    }
+}
+
+impl Order {
+    /// Only sessions that are Complete and Paid turn into orders
+    pub fn is_order_ready(checkout_session: &DbCheckoutSession) -> bool {
+        checkout_session.status == Some(DbCheckoutSessionStatus::Complete)
+            && checkout_session.payment_status == DbCheckoutSessionPaymentStatus::Paid
+    }
+
+    /// Builds the order of a completed and paid checkout session, without an order number yet.
+    /// `line_items` are passed separately since sessions aren't always fetched with them expanded.
+    pub fn from_checkout_session(
+        checkout_session: &DbCheckoutSession,
+        line_items: &[DbCheckoutSessionItem],
+        now: i64,
+    ) -> Option<Self> {
+        if !Order::is_order_ready(checkout_session) {
+            return None;
+        }
+
+        let customer_details = checkout_session.customer_details.as_ref();
+        let items: Vec<OrderItem> = line_items.iter().map(|item| item.into()).collect();
+        let amount_subtotal = checkout_session
+            .amount_subtotal
+            .unwrap_or_else(|| items.iter().map(|item| item.amount_total).sum());
+        let amount_shipping = checkout_session.amount_shipping.unwrap_or_default();
+
+        Some(Order {
+            order_number: String::new(),
+            checkout_session_id: checkout_session.id.clone(),
+            customer_id: checkout_session.customer.as_ref().map(|c| c.id.clone()),
+            customer_name: customer_details.and_then(|c| c.name.clone()).or_else(|| {
+                checkout_session
+                    .shipping_details
+                    .as_ref()
+                    .and_then(|s| s.name.clone())
+            }),
+            customer_email: customer_details
+                .and_then(|c| c.email.clone())
+                .or_else(|| checkout_session.customer_email.clone()),
+            customer_phone: customer_details.and_then(|c| c.phone.clone()),
+            shipping_address: checkout_session.shipping_details.clone(),
+            billing_address: customer_details.and_then(|c| c.address.clone()),
+            items,
+            amount_subtotal,
+            amount_shipping,
+            amount_total: checkout_session
+                .amount_total
+                .unwrap_or(amount_subtotal + amount_shipping),
+            currency: checkout_session
+                .currency
+                .clone()
+                .unwrap_or_else(|| "aud".to_string()),
+            payment_status: checkout_session.payment_status.clone(),
+            livemode: checkout_session.livemode,
+            fulfilment_status: FulfilmentStatus::Paid,
+            placed_at: checkout_session.created,
+            created_at: now,
+            updated_at: now,
+        })
+    }
+}
+
+/// Where an order is in the packing and delivery run.
+///
+/// Paid -> Packed -> OutForDelivery -> Delivered is the normal run.
+/// Orders that haven't left can be Cancelled, and Cancelled or Delivered orders can be Refunded.
+/// A failed delivery goes from OutForDelivery back to Packed.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FulfilmentStatus {
+    #[default]
+    Paid,
+    Packed,
+    OutForDelivery,
+    Delivered,
+    Cancelled,
+    Refunded,
+}
+
+impl FulfilmentStatus {
+    pub const ALL: [FulfilmentStatus; 6] = [
+        FulfilmentStatus::Paid,
+        FulfilmentStatus::Packed,
+        FulfilmentStatus::OutForDelivery,
+        FulfilmentStatus::Delivered,
+        FulfilmentStatus::Cancelled,
+        FulfilmentStatus::Refunded,
+    ];
+
+    /// Statuses an order in this status is allowed to move to
+    pub fn next_statuses(self) -> &'static [FulfilmentStatus] {
+        use FulfilmentStatus::*;
+        match self {
+            Paid => &[Packed, Cancelled],
+            Packed => &[OutForDelivery, Cancelled],
+            OutForDelivery => &[Delivered, Packed],
+            Delivered => &[Refunded],
+            Cancelled => &[Refunded],
+            Refunded => &[],
+        }
+    }
+
+    pub fn can_transition_to(self, next: FulfilmentStatus) -> bool {
+        self.next_statuses().contains(&next)
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            FulfilmentStatus::Paid => "Paid",
+            FulfilmentStatus::Packed => "Packed",
+            FulfilmentStatus::OutForDelivery => "Out for delivery",
+            FulfilmentStatus::Delivered => "Delivered",
+            FulfilmentStatus::Cancelled => "Cancelled",
+            FulfilmentStatus::Refunded => "Refunded",
+        }
+    }
+}
+
+impl std::fmt::Display for FulfilmentStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
+/// One step of an order's fulfilment history
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FulfilmentTransition {
+    pub from: Option<FulfilmentStatus>, // None for the first step, when the order was made
+    pub to: FulfilmentStatus,
+    pub actor: String, // who made the change, like "stripe" or an admin username
+    pub note: Option<String>,
+    // Measured in seconds since the Unix epoch.
+    pub at: i64,
+}
+
+/// A requested move of an order to another fulfilment status
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FulfilmentUpdate {
+    pub to: FulfilmentStatus,
+    pub actor: String,
+    pub note: Option<String>,
+    // Required when going OutForDelivery, tracking_number is optional for our own delivery runs
+    pub carrier: Option<String>,
+    pub tracking_number: Option<String>,
+}
+
+#[derive(Debug, Clone, Error, PartialEq, Eq)]
+pub enum FulfilmentError {
+    #[error("Order can't go from {from} to {to}")]
+    InvalidTransition {
+        from: FulfilmentStatus,
+        to: FulfilmentStatus,
+    },
+    #[error("A carrier is required to send an order out for delivery")]
+    MissingCarrier,
+}
+
+impl Order {
+    /// Moves the order to `update.to`, filling in carrier and tracking number of its shipping when going out for delivery.
+    /// Returns the transition to record in the order's history.
+    pub fn apply_fulfilment_update(
+        &mut self,
+        update: FulfilmentUpdate,
+        now: i64,
+    ) -> Result<FulfilmentTransition, FulfilmentError> {
+        let from = self.fulfilment_status;
+        if !from.can_transition_to(update.to) {
+            return Err(FulfilmentError::InvalidTransition {
+                from,
+                to: update.to,
+            });
+        }
+
+        if update.to == FulfilmentStatus::OutForDelivery {
+            let carrier = update
+                .carrier
+                .filter(|carrier| !carrier.trim().is_empty())
+                .ok_or(FulfilmentError::MissingCarrier)?;
+            let shipping = self.shipping_address.get_or_insert(DbShipping {
+                address: None,
+                carrier: None,
+                name: self.customer_name.clone(),
+                phone: self.customer_phone.clone(),
+                tracking_number: None,
+            });
+            shipping.carrier = Some(carrier);
+            shipping.tracking_number = update
+                .tracking_number
+                .filter(|tracking_number| !tracking_number.trim().is_empty());
+        }
+
+        self.fulfilment_status = update.to;
+        self.updated_at = now;
+
+        Ok(FulfilmentTransition {
+            from: Some(from),
+            to: update.to,
+            actor: update.actor,
+            note: update.note,
+            at: now,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paid_session() -> DbCheckoutSession {
+        DbCheckoutSession {
+            id: "cs_test_1".to_string(),
+            amount_subtotal: Some(2000),
+            amount_total: Some(2500),
+            cancel_url: None,
+            created: Some(1_700_000_000),
+            customer: None,
+            customer_email: None,
+            expires_at: None,
+            line_items: None,
+            livemode: false,
+            metadata: None,
+            mode: DbCheckoutSessionMode::Payment,
+            payment_status: DbCheckoutSessionPaymentStatus::Paid,
+            status: Some(DbCheckoutSessionStatus::Complete),
+            success_url: None,
+            url: None,
+            currency: Some("aud".to_string()),
+            customer_details: None,
+            shipping_details: None,
+            amount_shipping: Some(500),
+        }
+    }
+
+    fn paid_order() -> Order {
+        Order::from_checkout_session(&paid_session(), &[], 1_700_000_100).unwrap()
+    }
+
+    fn update(to: FulfilmentStatus, carrier: Option<&str>) -> FulfilmentUpdate {
+        FulfilmentUpdate {
+            to,
+            actor: "admin".to_string(),
+            note: None,
+            carrier: carrier.map(str::to_string),
+            tracking_number: Some("  ".to_string()),
+        }
+    }
+
+    #[test]
+    fn only_complete_and_paid_sessions_make_orders() {
+        let mut session = paid_session();
+        assert!(Order::from_checkout_session(&session, &[], 0).is_some());
+
+        session.payment_status = DbCheckoutSessionPaymentStatus::Unpaid;
+        assert!(Order::from_checkout_session(&session, &[], 0).is_none());
+
+        session.payment_status = DbCheckoutSessionPaymentStatus::Paid;
+        session.status = Some(DbCheckoutSessionStatus::Open);
+        assert!(Order::from_checkout_session(&session, &[], 0).is_none());
+    }
+
+    #[test]
+    fn normal_run_reaches_delivered() {
+        let mut order = paid_order();
+        assert_eq!(order.fulfilment_status, FulfilmentStatus::Paid);
+
+        order
+            .apply_fulfilment_update(update(FulfilmentStatus::Packed, None), 1)
+            .unwrap();
+        let transition = order
+            .apply_fulfilment_update(
+                update(FulfilmentStatus::OutForDelivery, Some("Farm van")),
+                2,
+            )
+            .unwrap();
+        assert_eq!(transition.from, Some(FulfilmentStatus::Packed));
+        let shipping = order.shipping_address.as_ref().unwrap();
+        assert_eq!(shipping.carrier.as_deref(), Some("Farm van"));
+        assert_eq!(shipping.tracking_number, None);
+
+        order
+            .apply_fulfilment_update(update(FulfilmentStatus::Delivered, None), 3)
+            .unwrap();
+        assert_eq!(order.fulfilment_status, FulfilmentStatus::Delivered);
+        assert_eq!(order.updated_at, 3);
+    }
+
+    #[test]
+    fn invalid_transitions_leave_the_order_alone() {
+        let mut order = paid_order();
+        assert_eq!(
+            order
+                .apply_fulfilment_update(update(FulfilmentStatus::Delivered, None), 1)
+                .unwrap_err(),
+            FulfilmentError::InvalidTransition {
+                from: FulfilmentStatus::Paid,
+                to: FulfilmentStatus::Delivered,
+            }
+        );
+        assert_eq!(order.fulfilment_status, FulfilmentStatus::Paid);
+
+        assert!(FulfilmentStatus::Refunded.next_statuses().is_empty());
+        assert!(FulfilmentStatus::Cancelled.can_transition_to(FulfilmentStatus::Refunded));
+        assert!(!FulfilmentStatus::Delivered.can_transition_to(FulfilmentStatus::Cancelled));
+    }
+
+    #[test]
+    fn out_for_delivery_needs_a_carrier() {
+        let mut order = paid_order();
+        order
+            .apply_fulfilment_update(update(FulfilmentStatus::Packed, None), 1)
+            .unwrap();
+        assert_eq!(
+            order
+                .apply_fulfilment_update(update(FulfilmentStatus::OutForDelivery, Some(" ")), 2)
+                .unwrap_err(),
+            FulfilmentError::MissingCarrier
+        );
+        assert_eq!(order.fulfilment_status, FulfilmentStatus::Packed);
+    }
+}
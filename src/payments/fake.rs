@@ -0,0 +1,382 @@
+use super::{CheckoutSessionParams, PaymentBackend, ProductUpdateParams, ShippingRateParams};
+use crate::products_config::CfgProducts;
+use crate::stripe_retypes::*;
+use async_trait::async_trait;
+use leptos::ServerFnError;
+use std::sync::Mutex;
+
+/// Timestamps of the fake backend come from a counter starting here, so runs are reproducible
+const FAKE_CLOCK_START: i64 = 1_700_000_000;
+
+/// In-memory PaymentBackend seeded from products_config.json.
+/// Ids and timestamps are deterministic, nothing leaves the process.
+pub struct FakeBackend {
+    state: Mutex<FakeState>,
+}
+
+#[derive(Default)]
+struct FakeState {
+    products: Vec<DbProduct>,
+    // Prices that are no longer the default price of their product
+    prices: Vec<DbPrice>,
+    customers: Vec<DbCustomer>,
+    shipping_rates: Vec<DbShippingRate>,
+    checkout_sessions: Vec<DbCheckoutSession>,
+    counter: i64,
+}
+
+impl FakeState {
+    fn next_id(&mut self, prefix: &str) -> String {
+        self.counter += 1;
+        format!("{}_fake_{:04}", prefix, self.counter)
+    }
+
+    fn now(&self) -> i64 {
+        FAKE_CLOCK_START + self.counter
+    }
+}
+
+fn not_found(kind: &str, id: &str) -> ServerFnError {
+    ServerFnError::ServerError(format!("No such {}: {}", kind, id))
+}
+
+impl FakeBackend {
+    pub fn from_products_config(products_config: CfgProducts) -> Self {
+        let products = products_config
+            .0
+            .into_iter()
+            .map(|p| {
+                // Stripe keeps item_number, variants and attributes in metadata, so the fake does too
+                let metadata = crate::products_config::push::pushed_metadata(&p);
+                DbProduct {
+                    default_price: p.price.map(|mut price| {
+                        price.product.get_or_insert(p.stripe_id.clone());
+                        price
+                    }),
+                    id: p.stripe_id,
+                    active: p.active.unwrap_or(true),
+                    created: Some(FAKE_CLOCK_START),
+                    description: p.description,
+                    images: p.images,
+                    local_images: None,
+                    metadata: (p.metadata.is_some() || !metadata.is_empty()).then_some(metadata),
+                    name: p.name,
+                    unit_label: p.unit_label,
+                    updated: Some(FAKE_CLOCK_START),
+                    url: None,
+                }
+            })
+            .collect();
+
+        FakeBackend {
+            state: Mutex::new(FakeState {
+                products,
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// Seeds from products_config.json in LEPTOS_ASSETS_DIR
+    pub fn from_products_config_file() -> Result<Self, ServerFnError> {
+        let assets_dir =
+            std::env::var("LEPTOS_ASSETS_DIR").unwrap_or_else(|_| "public".to_string());
+        let products_config_public_file_path =
+            std::path::Path::new(&assets_dir).join("products_config.json");
+
+        let products_config_file_contents =
+            std::fs::read_to_string(products_config_public_file_path)?;
+        let products_config: CfgProducts = serde_json::from_str(&products_config_file_contents)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+
+        Ok(FakeBackend::from_products_config(products_config))
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, FakeState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn add_customer(&self, customer: DbCustomer) {
+        self.state().customers.push(customer);
+    }
+
+    /// Marks a checkout session as paid, like a customer finishing the hosted checkout page
+    pub fn complete_checkout_session(
+        &self,
+        checkout_session_id: &str,
+    ) -> Option<DbCheckoutSession> {
+        let mut state = self.state();
+        let session = state
+            .checkout_sessions
+            .iter_mut()
+            .find(|s| s.id == checkout_session_id)?;
+        session.status = Some(DbCheckoutSessionStatus::Complete);
+        session.payment_status = DbCheckoutSessionPaymentStatus::Paid;
+        Some(session.clone())
+    }
+
+    pub fn expire_checkout_session(&self, checkout_session_id: &str) -> Option<DbCheckoutSession> {
+        let mut state = self.state();
+        let session = state
+            .checkout_sessions
+            .iter_mut()
+            .find(|s| s.id == checkout_session_id)?;
+        session.status = Some(DbCheckoutSessionStatus::Expired);
+        Some(session.clone())
+    }
+}
+
+#[async_trait]
+impl PaymentBackend for FakeBackend {
+    async fn list_products(&self) -> Result<Vec<DbProduct>, ServerFnError> {
+        Ok(self
+            .state()
+            .products
+            .iter()
+            .filter(|p| p.active)
+            .cloned()
+            .collect())
+    }
+
+    async fn retrieve_product(&self, product_id: &str) -> Result<DbProduct, ServerFnError> {
+        self.state()
+            .products
+            .iter()
+            .find(|p| p.id == product_id)
+            .cloned()
+            .ok_or_else(|| not_found("product", product_id))
+    }
+
+    async fn retrieve_price(&self, price_id: &str) -> Result<DbPrice, ServerFnError> {
+        let state = self.state();
+        state
+            .products
+            .iter()
+            .filter_map(|p| p.default_price.as_ref())
+            .chain(state.prices.iter())
+            .find(|price| price.id == price_id)
+            .cloned()
+            .ok_or_else(|| not_found("price", price_id))
+    }
+
+    async fn update_product(
+        &self,
+        product_id: &str,
+        params: ProductUpdateParams,
+    ) -> Result<DbProduct, ServerFnError> {
+        let mut state = self.state();
+        let now = state.now();
+        let FakeState {
+            products, prices, ..
+        } = &mut *state;
+        let product = products
+            .iter_mut()
+            .find(|p| p.id == product_id)
+            .ok_or_else(|| not_found("product", product_id))?;
+
+        if let Some(price_id) = params.default_price {
+            let index = prices
+                .iter()
+                .position(|price| {
+                    price.id == price_id && price.product.as_deref() == Some(product_id)
+                })
+                .ok_or_else(|| not_found("price", &price_id))?;
+            let price = prices.remove(index);
+            if let Some(old_price) = product.default_price.replace(price) {
+                prices.push(old_price);
+            }
+        }
+        if let Some(name) = params.name {
+            product.name = name;
+        }
+        if let Some(description) = params.description {
+            product.description = (!description.is_empty()).then_some(description);
+        }
+        if let Some(images) = params.images {
+            product.images = Some(images);
+        }
+        if let Some(metadata) = params.metadata {
+            let product_metadata = product.metadata.get_or_insert_with(Default::default);
+            for (key, value) in metadata {
+                match value.is_empty() {
+                    true => product_metadata.remove(&key),
+                    false => product_metadata.insert(key, value),
+                };
+            }
+        }
+        if let Some(active) = params.active {
+            product.active = active;
+        }
+        if let Some(unit_label) = params.unit_label {
+            product.unit_label = (!unit_label.is_empty()).then_some(unit_label);
+        }
+        product.updated = Some(now);
+        Ok(product.clone())
+    }
+
+    async fn create_price(
+        &self,
+        product_id: &str,
+        unit_amount: i64,
+    ) -> Result<DbPrice, ServerFnError> {
+        let mut state = self.state();
+        if !state.products.iter().any(|p| p.id == product_id) {
+            return Err(not_found("product", product_id));
+        }
+        let price = DbPrice {
+            id: state.next_id("price"),
+            active: true,
+            billing_scheme: Some(DbPriceBillingScheme::PerUnit),
+            created: Some(state.now()),
+            custom_unit_amount: None,
+            livemode: false,
+            lookup_key: None,
+            metadata: None,
+            nickname: None,
+            product: Some(product_id.to_string()),
+            recurring: None,
+            type_: Some(DbPriceType::OneTime),
+            unit_amount: Some(unit_amount),
+            unit_amount_decimal: Some(unit_amount.to_string()),
+        };
+        state.prices.push(price.clone());
+        Ok(price)
+    }
+
+    async fn archive_price(&self, price_id: &str) -> Result<DbPrice, ServerFnError> {
+        let mut state = self.state();
+        if state.products.iter().any(|p| {
+            p.default_price
+                .as_ref()
+                .is_some_and(|price| price.id == price_id)
+        }) {
+            return Err(ServerFnError::ServerError(format!(
+                "Price {} is the default price of its product and can't be archived",
+                price_id
+            )));
+        }
+        let price = state
+            .prices
+            .iter_mut()
+            .find(|price| price.id == price_id)
+            .ok_or_else(|| not_found("price", price_id))?;
+        price.active = false;
+        Ok(price.clone())
+    }
+
+    async fn list_customers(&self) -> Result<Vec<DbCustomer>, ServerFnError> {
+        Ok(self.state().customers.clone())
+    }
+
+    async fn list_shipping_rates(&self) -> Result<Vec<DbShippingRate>, ServerFnError> {
+        Ok(self
+            .state()
+            .shipping_rates
+            .iter()
+            .filter(|rate| rate.active)
+            .cloned()
+            .collect())
+    }
+
+    async fn create_shipping_rate(
+        &self,
+        params: ShippingRateParams,
+    ) -> Result<DbShippingRate, ServerFnError> {
+        let mut state = self.state();
+        let shipping_rate = DbShippingRate {
+            id: state.next_id("shr"),
+            active: true,
+            display_name: Some(params.display_name),
+            fixed_amount: Some(DbShippingRateFixedAmount {
+                amount: params.amount,
+                currency: "aud".to_string(),
+            }),
+            livemode: false,
+        };
+        state.shipping_rates.push(shipping_rate.clone());
+        Ok(shipping_rate)
+    }
+
+    async fn list_checkout_sessions(&self) -> Result<Vec<DbCheckoutSession>, ServerFnError> {
+        Ok(self.state().checkout_sessions.clone())
+    }
+
+    async fn retrieve_checkout_session(
+        &self,
+        checkout_session_id: &str,
+    ) -> Result<DbCheckoutSession, ServerFnError> {
+        self.state()
+            .checkout_sessions
+            .iter()
+            .find(|s| s.id == checkout_session_id)
+            .cloned()
+            .ok_or_else(|| not_found("checkout session", checkout_session_id))
+    }
+
+    async fn create_checkout_session(
+        &self,
+        params: CheckoutSessionParams,
+    ) -> Result<DbCheckoutSession, ServerFnError> {
+        let mut state = self.state();
+
+        let mut line_items = Vec::with_capacity(params.line_items.len());
+        for item in &params.line_items {
+            let (description, price) = state
+                .products
+                .iter()
+                .find_map(|p| {
+                    p.default_price
+                        .as_ref()
+                        .filter(|price| price.id == item.price_id)
+                        .map(|price| (p.name.clone(), price.clone()))
+                })
+                .ok_or_else(|| not_found("price", &item.price_id))?;
+            let amount = price.unit_amount.unwrap_or_default() * item.quantity as i64;
+            line_items.push(DbCheckoutSessionItem {
+                id: state.next_id("li"),
+                amount_discount: 0,
+                amount_subtotal: amount,
+                amount_total: amount,
+                description,
+                price: Some(price),
+                quantity: Some(item.quantity),
+            });
+        }
+
+        let shipping_amount = state
+            .shipping_rates
+            .iter()
+            .find(|rate| rate.id == params.shipping_rate_id)
+            .and_then(|rate| rate.fixed_amount.as_ref())
+            .map_or(0, |fixed_amount| fixed_amount.amount);
+        let amount_subtotal: i64 = line_items.iter().map(|item| item.amount_total).sum();
+
+        let id = state.next_id("cs");
+        let created = state.now();
+        let session = DbCheckoutSession {
+            url: Some(params.success_url.replace("{CHECKOUT_SESSION_ID}", &id)),
+            id,
+            amount_subtotal: Some(amount_subtotal),
+            amount_total: Some(amount_subtotal + shipping_amount),
+            cancel_url: Some(params.cancel_url),
+            created: Some(created),
+            customer: None,
+            customer_email: None,
+            expires_at: Some(params.expires_at.unwrap_or(created + 24 * 60 * 60)),
+            line_items: Some(line_items),
+            livemode: false,
+            metadata: params.metadata,
+            mode: DbCheckoutSessionMode::Payment,
+            payment_status: DbCheckoutSessionPaymentStatus::Unpaid,
+            status: Some(DbCheckoutSessionStatus::Open),
+            success_url: Some(params.success_url),
+            currency: Some("aud".to_string()),
+            customer_details: None,
+            shipping_details: None,
+            amount_shipping: Some(shipping_amount),
+        };
+        state.checkout_sessions.push(session.clone());
+        Ok(session)
+    }
+}
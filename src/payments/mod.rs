@@ -0,0 +1,131 @@
+#![cfg(feature = "ssr")]
+
+//! Payment backends the server talks to for products, prices, customers, shipping rates and checkout sessions.
+//! `StripeBackend` talks to the Stripe API, `FakeBackend` keeps everything in memory for tests and local dev.
+//! Which one is used is decided once per process by `payment_backend()`.
+
+use crate::stripe_retypes::*;
+use async_trait::async_trait;
+use leptos::ServerFnError;
+use log::*;
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock};
+
+mod fake;
+mod stripe;
+
+pub use self::fake::FakeBackend;
+pub use self::stripe::{StripeBackend, StripePageSizes};
+
+pub type SharedPaymentBackend = Arc<dyn PaymentBackend>;
+
+#[async_trait]
+pub trait PaymentBackend: Send + Sync {
+    /// All active products with their default price
+    async fn list_products(&self) -> Result<Vec<DbProduct>, ServerFnError>;
+    async fn retrieve_product(&self, product_id: &str) -> Result<DbProduct, ServerFnError>;
+    async fn retrieve_price(&self, price_id: &str) -> Result<DbPrice, ServerFnError>;
+    /// Changes the fields of a product that are Some in `params`
+    async fn update_product(
+        &self,
+        product_id: &str,
+        params: ProductUpdateParams,
+    ) -> Result<DbProduct, ServerFnError>;
+    /// New one-time AUD price of a product, prices can't change their amount once made
+    async fn create_price(
+        &self,
+        product_id: &str,
+        unit_amount: i64,
+    ) -> Result<DbPrice, ServerFnError>;
+    /// Stops a price from being used for new purchases, it must not be the default price of its product
+    async fn archive_price(&self, price_id: &str) -> Result<DbPrice, ServerFnError>;
+    async fn list_customers(&self) -> Result<Vec<DbCustomer>, ServerFnError>;
+    /// All active shipping rates
+    async fn list_shipping_rates(&self) -> Result<Vec<DbShippingRate>, ServerFnError>;
+    async fn create_shipping_rate(
+        &self,
+        params: ShippingRateParams,
+    ) -> Result<DbShippingRate, ServerFnError>;
+    /// All checkout sessions with their line items
+    async fn list_checkout_sessions(&self) -> Result<Vec<DbCheckoutSession>, ServerFnError>;
+    async fn retrieve_checkout_session(
+        &self,
+        checkout_session_id: &str,
+    ) -> Result<DbCheckoutSession, ServerFnError>;
+    async fn create_checkout_session(
+        &self,
+        params: CheckoutSessionParams,
+    ) -> Result<DbCheckoutSession, ServerFnError>;
+}
+
+/// Fields of a product to change, None leaves them as they are
+#[derive(Debug, Clone, Default)]
+pub struct ProductUpdateParams {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub images: Option<Vec<String>>,
+    // Keys with an empty value are removed, keys missing here are kept
+    pub metadata: Option<HashMap<String, String>>,
+    pub active: Option<bool>,
+    pub unit_label: Option<String>, // an empty label removes it
+    pub default_price: Option<String>, // id of a price of the product
+}
+
+/// Fixed amount AUD shipping rate
+#[derive(Debug, Clone)]
+pub struct ShippingRateParams {
+    pub display_name: String,
+    pub amount: i64,
+    pub min_delivery_days: i64,
+    pub max_delivery_days: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct CheckoutSessionParams {
+    pub line_items: Vec<CheckoutLineItem>,
+    pub shipping_rate_id: String,
+    pub success_url: String,
+    pub cancel_url: String,
+    pub metadata: Option<HashMap<String, String>>,
+    // Seconds since the Unix epoch, None keeps the default of the backend
+    pub expires_at: Option<i64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CheckoutLineItem {
+    pub price_id: String,
+    pub quantity: u64,
+    // Whether the customer may change the quantity on the checkout page, off for products with reserved stock
+    pub adjustable: bool,
+}
+
+static PAYMENT_BACKEND: OnceLock<SharedPaymentBackend> = OnceLock::new();
+
+/// Gets the payment backend of this process, creating it from env on first use.
+/// `PAYMENT_BACKEND=fake` selects the in-memory FakeBackend, otherwise StripeBackend is made with STRIPE_KEY.
+pub fn payment_backend() -> Result<SharedPaymentBackend, ServerFnError> {
+    if let Some(backend) = PAYMENT_BACKEND.get() {
+        return Ok(backend.clone());
+    }
+
+    let backend: SharedPaymentBackend = match std::env::var("PAYMENT_BACKEND").as_deref() {
+        Ok("fake") => {
+            info!("Using FakeBackend for payments.");
+            Arc::new(FakeBackend::from_products_config_file()?)
+        }
+        Ok("stripe") | Err(_) => Arc::new(StripeBackend::from_env()?),
+        Ok(other) => {
+            let err = format!("Unknown PAYMENT_BACKEND: {:?}", other);
+            error!("{}", err);
+            return Err(ServerFnError::ServerError(err));
+        }
+    };
+
+    Ok(PAYMENT_BACKEND.get_or_init(|| backend).clone())
+}
+
+/// Installs `backend` as the payment backend of this process, for tests and tools that bring their own.
+/// Returns false if a backend was already in use.
+pub fn set_payment_backend(backend: SharedPaymentBackend) -> bool {
+    PAYMENT_BACKEND.set(backend).is_ok()
+}
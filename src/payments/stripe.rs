@@ -0,0 +1,427 @@
+use super::{CheckoutSessionParams, PaymentBackend, ProductUpdateParams, ShippingRateParams};
+use crate::stripe_retypes::*;
+use async_trait::async_trait;
+use futures_util::{Stream, TryStreamExt};
+use leptos::ServerFnError;
+use log::*;
+use stripe::*;
+
+/// Stripe allows at most 100 objects per page of a list call
+const MAX_PAGE_SIZE: u64 = 100;
+
+/// PaymentBackend talking to the Stripe API through async-stripe
+pub struct StripeBackend {
+    client: Client,
+    page_sizes: StripePageSizes,
+}
+
+/// How many objects each Stripe list call asks for per page.
+/// Lists are always followed to the last page, this only changes how many requests that takes.
+#[derive(Debug, Clone, Copy)]
+pub struct StripePageSizes {
+    pub products: u64,
+    pub customers: u64,
+    pub shipping_rates: u64,
+    pub checkout_sessions: u64,
+}
+
+impl Default for StripePageSizes {
+    fn default() -> Self {
+        StripePageSizes {
+            products: MAX_PAGE_SIZE,
+            customers: MAX_PAGE_SIZE,
+            shipping_rates: MAX_PAGE_SIZE,
+            checkout_sessions: MAX_PAGE_SIZE,
+        }
+    }
+}
+
+/// A page size (1 to 100) from the environment variable `name`, None when it isn't set or is invalid
+fn page_size_from_env(name: &str) -> Option<u64> {
+    let value = std::env::var(name).ok()?;
+    match value.parse::<u64>() {
+        Ok(page_size) if (1..=MAX_PAGE_SIZE).contains(&page_size) => Some(page_size),
+        _ => {
+            warn!(
+                "Ignoring {}={:?}, it must be between 1 and {}.",
+                name, value, MAX_PAGE_SIZE
+            );
+            None
+        }
+    }
+}
+
+impl StripePageSizes {
+    /// Reads STRIPE_PAGE_SIZE_PRODUCTS, _CUSTOMERS, _SHIPPING_RATES and _CHECKOUT_SESSIONS,
+    /// each list falling back to STRIPE_PAGE_SIZE and then to the default
+    pub fn from_env() -> Self {
+        let page_size = page_size_from_env("STRIPE_PAGE_SIZE").unwrap_or(MAX_PAGE_SIZE);
+        let list_page_size = |list: &str| {
+            page_size_from_env(&format!("STRIPE_PAGE_SIZE_{}", list)).unwrap_or(page_size)
+        };
+
+        StripePageSizes {
+            products: list_page_size("PRODUCTS"),
+            customers: list_page_size("CUSTOMERS"),
+            shipping_rates: list_page_size("SHIPPING_RATES"),
+            checkout_sessions: list_page_size("CHECKOUT_SESSIONS"),
+        }
+    }
+}
+
+fn parse_id<T: std::str::FromStr>(id: &str) -> Result<T, ServerFnError>
+where
+    T::Err: std::fmt::Debug,
+{
+    id.parse::<T>().map_err(|err| {
+        log::error!("{:#?}", err);
+        ServerFnError::ServerError(format!("Invalid Stripe id {:?}: {:?}", id, err))
+    })
+}
+
+fn stripe_error(err: StripeError) -> ServerFnError {
+    log::error!("{:#?}", err);
+    ServerFnError::ServerError(err.to_string())
+}
+
+/// Drains an auto-paging list stream, requesting the next page whenever the current one runs out
+async fn collect_all<T, D, S>(what: &str, stream: S) -> Result<Vec<D>, ServerFnError>
+where
+    S: Stream<Item = Result<T, StripeError>> + Unpin,
+    T: Into<D>,
+{
+    let items: Vec<T> = stream.try_collect().await.map_err(stripe_error)?;
+    info!("Fetched {} {} from Stripe.", items.len(), what);
+    Ok(items.into_iter().map(|x| x.into()).collect())
+}
+
+impl StripeBackend {
+    pub fn new(key: String) -> Self {
+        StripeBackend::with_page_sizes(key, StripePageSizes::default())
+    }
+
+    pub fn with_page_sizes(key: String, page_sizes: StripePageSizes) -> Self {
+        StripeBackend {
+            client: Client::new(key),
+            page_sizes,
+        }
+    }
+
+    pub fn from_env() -> Result<Self, ServerFnError> {
+        match std::env::var("STRIPE_KEY") {
+            Ok(ok) => Ok(StripeBackend::with_page_sizes(
+                ok,
+                StripePageSizes::from_env(),
+            )),
+            Err(err) => {
+                log::error!("{:#?}", err);
+                Err(ServerFnError::ServerError(err.to_string()))
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl PaymentBackend for StripeBackend {
+    async fn list_products(&self) -> Result<Vec<DbProduct>, ServerFnError> {
+        let product_list_params = ListProducts {
+            active: Some(true),
+            expand: &["data.default_price"],
+            limit: Some(self.page_sizes.products),
+            ..ListProducts::new()
+        };
+        let products = Product::list(&self.client, &product_list_params)
+            .await
+            .map_err(stripe_error)?
+            .paginate(product_list_params)
+            .stream(&self.client);
+
+        collect_all("products", products).await
+    }
+
+    async fn retrieve_product(&self, product_id: &str) -> Result<DbProduct, ServerFnError> {
+        let product_id: ProductId = parse_id(product_id)?;
+        Ok(
+            Product::retrieve(&self.client, &product_id, &["default_price"])
+                .await?
+                .into(),
+        )
+    }
+
+    async fn retrieve_price(&self, price_id: &str) -> Result<DbPrice, ServerFnError> {
+        let price_id: PriceId = parse_id(price_id)?;
+        Ok(Price::retrieve(&self.client, &price_id, &[]).await?.into())
+    }
+
+    async fn update_product(
+        &self,
+        product_id: &str,
+        params: ProductUpdateParams,
+    ) -> Result<DbProduct, ServerFnError> {
+        let product_id: ProductId = parse_id(product_id)?;
+        let update_product_params = UpdateProduct {
+            name: params.name.as_deref(),
+            description: params.description,
+            images: params.images,
+            metadata: params.metadata,
+            active: params.active,
+            unit_label: params.unit_label,
+            default_price: params.default_price.as_deref(),
+            expand: &["default_price"],
+            ..UpdateProduct::new()
+        };
+        let product = Product::update(&self.client, &product_id, update_product_params)
+            .await
+            .map_err(stripe_error)?;
+        info!("Updated product {} in Stripe.", product.id);
+        Ok(product.into())
+    }
+
+    async fn create_price(
+        &self,
+        product_id: &str,
+        unit_amount: i64,
+    ) -> Result<DbPrice, ServerFnError> {
+        let create_price_params = CreatePrice {
+            product: Some(IdOrCreate::Id(product_id)),
+            unit_amount: Some(unit_amount),
+            ..CreatePrice::new(Currency::AUD)
+        };
+        let price = Price::create(&self.client, create_price_params)
+            .await
+            .map_err(stripe_error)?;
+        info!(
+            "Created price {} of product {} in Stripe.",
+            price.id, product_id
+        );
+        Ok(price.into())
+    }
+
+    async fn archive_price(&self, price_id: &str) -> Result<DbPrice, ServerFnError> {
+        let price_id: PriceId = parse_id(price_id)?;
+        let update_price_params = UpdatePrice {
+            active: Some(false),
+            ..UpdatePrice::new()
+        };
+        let price = Price::update(&self.client, &price_id, update_price_params)
+            .await
+            .map_err(stripe_error)?;
+        info!("Archived price {} in Stripe.", price.id);
+        Ok(price.into())
+    }
+
+    async fn list_customers(&self) -> Result<Vec<DbCustomer>, ServerFnError> {
+        let customer_list_params = ListCustomers {
+            limit: Some(self.page_sizes.customers),
+            ..ListCustomers::new()
+        };
+        let customers = Customer::list(&self.client, &customer_list_params)
+            .await
+            .map_err(stripe_error)?
+            .paginate(customer_list_params)
+            .stream(&self.client);
+
+        collect_all("customers", customers).await
+    }
+
+    async fn list_shipping_rates(&self) -> Result<Vec<DbShippingRate>, ServerFnError> {
+        let shipping_rates_list_params = ListShippingRates {
+            active: Some(true),
+            limit: Some(self.page_sizes.shipping_rates),
+            ..ListShippingRates::new()
+        };
+        let shipping_rates = ShippingRate::list(&self.client, &shipping_rates_list_params)
+            .await
+            .map_err(stripe_error)?
+            .paginate(shipping_rates_list_params)
+            .stream(&self.client);
+
+        collect_all("shipping rates", shipping_rates).await
+    }
+
+    async fn create_shipping_rate(
+        &self,
+        params: ShippingRateParams,
+    ) -> Result<DbShippingRate, ServerFnError> {
+        let create_shipping_rate_params = CreateShippingRate {
+            delivery_estimate: Some(CreateShippingRateDeliveryEstimate {
+                maximum: Some(CreateShippingRateDeliveryEstimateMaximum {
+                    unit: CreateShippingRateDeliveryEstimateMaximumUnit::Day,
+                    value: params.max_delivery_days,
+                }),
+                minimum: Some(CreateShippingRateDeliveryEstimateMinimum {
+                    unit: CreateShippingRateDeliveryEstimateMinimumUnit::Day,
+                    value: params.min_delivery_days,
+                }),
+            }),
+            display_name: &params.display_name,
+            expand: &[],
+            fixed_amount: Some(CreateShippingRateFixedAmount {
+                amount: params.amount,
+                currency: Currency::AUD,
+                currency_options: None,
+            }),
+            metadata: None,
+            tax_behavior: None,
+            tax_code: None,
+            type_: Some(ShippingRateType::FixedAmount),
+        };
+        let shipping_rate = ShippingRate::create(&self.client, create_shipping_rate_params)
+            .await
+            .map_err(stripe_error)?;
+        Ok(shipping_rate.into())
+    }
+
+    async fn list_checkout_sessions(&self) -> Result<Vec<DbCheckoutSession>, ServerFnError> {
+        let checkout_session_list_params = ListCheckoutSessions {
+            expand: &["data.line_items"],
+            limit: Some(self.page_sizes.checkout_sessions),
+            ..ListCheckoutSessions::new()
+        };
+        let checkout_sessions = CheckoutSession::list(&self.client, &checkout_session_list_params)
+            .await
+            .map_err(stripe_error)?
+            .paginate(checkout_session_list_params)
+            .stream(&self.client);
+
+        collect_all("checkout sessions", checkout_sessions).await
+    }
+
+    async fn retrieve_checkout_session(
+        &self,
+        checkout_session_id: &str,
+    ) -> Result<DbCheckoutSession, ServerFnError> {
+        let checkout_session_id: CheckoutSessionId = parse_id(checkout_session_id)?;
+        Ok(
+            CheckoutSession::retrieve(&self.client, &checkout_session_id, &["line_items"])
+                .await?
+                .into(),
+        )
+    }
+
+    async fn create_checkout_session(
+        &self,
+        checkout_session_params: CheckoutSessionParams,
+    ) -> Result<DbCheckoutSession, ServerFnError> {
+        let mut params = stripe::CreateCheckoutSession::new();
+        params.cancel_url = Some(&checkout_session_params.cancel_url);
+        params.success_url = Some(&checkout_session_params.success_url);
+        params.customer = None;
+        params.customer_creation = Some(stripe::CheckoutSessionCustomerCreation::IfRequired);
+        params.shipping_address_collection =
+            Some(stripe::CreateCheckoutSessionShippingAddressCollection {
+                allowed_countries: vec![
+                    stripe::CreateCheckoutSessionShippingAddressCollectionAllowedCountries::Au,
+                ],
+            });
+        params.shipping_options = Some(vec![CreateCheckoutSessionShippingOptions {
+            /// The ID of the Shipping Rate to use for this shipping option.
+            shipping_rate: Some(checkout_session_params.shipping_rate_id.clone()),
+
+            /// Parameters to be passed to Shipping Rate creation for this shipping option.
+            shipping_rate_data: None,
+        }]);
+        params.consent_collection = Some(CreateCheckoutSessionConsentCollection {
+            payment_method_reuse_agreement: Some(CreateCheckoutSessionConsentCollectionPaymentMethodReuseAgreement {
+                position: CreateCheckoutSessionConsentCollectionPaymentMethodReuseAgreementPosition::Hidden,
+            }),
+            ..Default::default()
+        });
+        // Collect additional information from your customer using custom fields.
+        //
+        // Up to 3 fields are supported.
+        // params.custom_fields = Some(vec![CreateCheckoutSessionCustomFields {
+        //     // Configuration for `type=dropdown` fields.
+        //     dropdown: Some(CreateCheckoutSessionCustomFieldsDropdown {
+        //         options: vec![CreateCheckoutSessionCustomFieldsDropdownOptions {
+        //             // The label for the option, displayed to the customer.
+        //             //
+        //             // Up to 100 characters.
+        //             label: String::from("I understand that I live within the delivery route "),
+
+        //             // The value for this option, not displayed to the customer,
+        //             // used by your integration to reconcile the option selected by the customer.
+        //             //
+        //             // Must be unique to this option, alphanumeric, and up to 100 characters.
+        //             value: String::from("deliverycollection"),
+        //         }],
+        //     }),
+        //     // dropdown: None,
+
+        //     // The label for the field, displayed to the customer.
+        //     label: CreateCheckoutSessionCustomFieldsLabel {
+        //         custom: String::from("How to collect your order?"),
+        //         type_: CreateCheckoutSessionCustomFieldsLabelType::Custom,
+        //     },
+
+        //     // Configuration for `type=numeric` fields.
+        //     // numeric: Some(CreateCheckoutSessionCustomFieldsNumeric {
+        //     //     ..Default::default()
+        //     // }),
+        //     numeric: None,
+
+        //     // Whether the customer is required to complete the field before completing the Checkout Session.
+        //     //
+        //     // Defaults to `false`.
+        //     optional: Some(false),
+
+        //     // Configuration for `type=text` fields.
+        //     // text: Some(CreateCheckoutSessionCustomFieldsText {}),
+        //     text: None,
+
+        //     // The type of the field.
+        //     type_: CreateCheckoutSessionCustomFieldsType::Dropdown,
+
+        //     // String of your choice that your integration can use to reconcile this field.
+        //     //
+        //     // Must be unique to this field, alphanumeric, and up to 200 characters.
+        //     key: String::from("deliveryconsent"),
+        //     // ..Default::default()
+        // }]);
+
+        params.custom_text = Some(CreateCheckoutSessionCustomText {
+            shipping_address: Some(CreateCheckoutSessionCustomTextShippingAddress {
+                message: "We make deliveries only within Tasmania Derwent Valley or Hobart area."
+                    .to_string(),
+            }),
+            after_submit: Some(CreateCheckoutSessionCustomTextAfterSubmit {
+                message: "We make deliveries only within Tasmania Derwent Valley or Hobart area."
+                    .to_string(),
+            }),
+            ..Default::default()
+        });
+        params.phone_number_collection =
+            Some(stripe::CreateCheckoutSessionPhoneNumberCollection { enabled: true });
+        params.ui_mode = Some(stripe::CheckoutSessionUiMode::Hosted);
+        params.mode = Some(stripe::CheckoutSessionMode::Payment);
+        params.billing_address_collection =
+            Some(stripe::CheckoutSessionBillingAddressCollection::Required);
+        params.currency = Some(stripe::Currency::AUD);
+        params.metadata = checkout_session_params.metadata.clone();
+        params.expires_at = checkout_session_params.expires_at;
+
+        params.line_items = Some(
+            checkout_session_params
+                .line_items
+                .iter()
+                .map(|item| CreateCheckoutSessionLineItems {
+                    adjustable_quantity: item.adjustable.then_some(
+                        CreateCheckoutSessionLineItemsAdjustableQuantity {
+                            enabled: true,
+                            maximum: Some(20),
+                            minimum: Some(1),
+                        },
+                    ),
+                    quantity: Some(item.quantity),
+                    price: Some(item.price_id.clone()),
+                    ..Default::default()
+                })
+                .collect(),
+        );
+        params.expand = &["line_items", "line_items.data.price.product"];
+
+        let new_session = stripe::CheckoutSession::create(&self.client, params).await?;
+
+        Ok(new_session.into())
+    }
+}
@@ -1,11 +1,77 @@
-use crate::{fetch_local_product_info, stripe_retypes};
+use crate::stripe_retypes;
 use serde::*;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::path::PathBuf;
 
+pub mod push;
+// csv is only a dependency of the server
+#[cfg(feature = "ssr")]
+pub mod spreadsheet;
+pub mod validation;
+
+/// Values of the "category" metadata the shop has pages for
+pub const KNOWN_CATEGORIES: [&str; 3] = ["food", "pet_food", "ready_to_eat"];
+
+/// Stripe metadata key with the parent name of a variant, its options are in "variant_<axis>" keys
+pub const VARIANT_PARENT_KEY: &str = "variant_of";
+const VARIANT_OPTION_PREFIX: &str = "variant_";
+
+/// Page slug of a product name, like "large-whole-quails" of "Large Whole Quails"
+pub fn slug(name: &str) -> String {
+    name.to_lowercase().replace(" ", "-")
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct CfgProducts(pub Vec<CfgProduct>);
 
+impl CfgProducts {
+    /// The product of a page, the first variant of a parent for a parent's page
+    pub fn find_by_slug(&self, slug: &str) -> Option<&CfgProduct> {
+        self.0
+            .iter()
+            .find(|product| product.slug() == slug)
+            .or_else(|| self.0.iter().find(|product| product.page_slug() == slug))
+    }
+
+    /// All variants with the same parent as `product`, just the product if it isn't a variant
+    pub fn variants_of(&self, product: &CfgProduct) -> Vec<CfgProduct> {
+        match &product.variant {
+            Some(variant) => self
+                .0
+                .iter()
+                .filter(|other| {
+                    other
+                        .variant
+                        .as_ref()
+                        .is_some_and(|other_variant| other_variant.parent == variant.parent)
+                })
+                .cloned()
+                .collect(),
+            None => vec![product.clone()],
+        }
+    }
+}
+
+/// Axes of a group of variants with their values, in the order the variants list them
+pub fn variant_axes(variants: &[CfgProduct]) -> Vec<(String, Vec<String>)> {
+    let mut axes: Vec<(String, Vec<String>)> = Vec::new();
+    for (axis, value) in variants
+        .iter()
+        .filter_map(|product| product.variant.as_ref())
+        .flat_map(|variant| variant.options.iter())
+    {
+        match axes.iter_mut().find(|(known_axis, _)| known_axis == axis) {
+            Some((_, values)) => {
+                if !values.contains(value) {
+                    values.push(value.clone());
+                }
+            }
+            None => axes.push((axis.clone(), vec![value.clone()])),
+        }
+    }
+    axes
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct CfgProduct {
     pub stripe_id: String,
@@ -16,4 +82,1328 @@ pub struct CfgProduct {
     pub images: Option<Vec<String>>, // urls of images from stripe (usually just one is available)
     pub local_images: Option<Vec<PathBuf>>, // paths to local image files
     pub metadata: Option<HashMap<String, String>>,
+    // Some(false) once the product is archived or deleted in Stripe, the shop doesn't show it then
+    #[serde(default)]
+    pub active: Option<bool>,
+    // dimensions and smaller variants of local_images, made by the image pipeline (images.rs)
+    #[serde(default)]
+    pub local_image_sizes: Option<Vec<LocalImage>>,
+    // order, alt text and roles of local_images, edited by hand. local_images follow its order
+    #[serde(default)]
+    pub image_manifest: Option<Vec<CfgImage>>,
+    // Some when the product is one variant of a parent product, like the size and cut of quails
+    #[serde(default)]
+    pub variant: Option<CfgVariant>,
+    // unit the price is for, like "pack" or "dozen", Stripe shows it at checkout too
+    #[serde(default)]
+    pub unit_label: Option<String>,
+    // weight, storage, allergens and nutrition shown in the spec table of the product page
+    #[serde(default)]
+    pub attributes: Option<CfgAttributes>,
+}
+
+/// How a product has to be kept
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Storage {
+    Frozen,
+    Chilled,
+    Ambient,
+}
+
+impl Storage {
+    fn from_metadata_value(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "frozen" => Some(Storage::Frozen),
+            "chilled" => Some(Storage::Chilled),
+            "ambient" => Some(Storage::Ambient),
+            _ => None,
+        }
+    }
+
+    fn metadata_value(&self) -> &'static str {
+        match self {
+            Storage::Frozen => "frozen",
+            Storage::Chilled => "chilled",
+            Storage::Ambient => "ambient",
+        }
+    }
+}
+
+impl std::fmt::Display for Storage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Storage::Frozen => write!(f, "Frozen, -18°C or below"),
+            Storage::Chilled => write!(f, "Chilled, 0°C to 4°C"),
+            Storage::Ambient => write!(f, "Cool and dry, below 25°C"),
+        }
+    }
+}
+
+/// A row of the nutrition table, like "Protein" with "22.5 g"
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct NutritionRow {
+    pub nutrient: String,
+    pub per_100g: String,
+}
+
+/// Facts about a product shown in its spec table. In Stripe they are metadata keys:
+/// "net_weight_g", "pieces_per_pack", "storage" (frozen, chilled or ambient), "shelf_life_days",
+/// "allergens" separated by commas and "nutrition" like "Energy: 520 kJ; Protein: 22.5 g".
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+#[serde(default)]
+pub struct CfgAttributes {
+    pub net_weight_g: Option<u32>,
+    pub pieces_per_pack: Option<u32>,
+    pub storage: Option<Storage>,
+    pub shelf_life_days: Option<u32>,
+    pub allergens: Vec<String>,
+    pub nutrition: Vec<NutritionRow>, // per 100 g, in the order of the label
+}
+
+const ATTRIBUTE_KEYS: [&str; 6] = [
+    "net_weight_g",
+    "pieces_per_pack",
+    "storage",
+    "shelf_life_days",
+    "allergens",
+    "nutrition",
+];
+
+impl CfgAttributes {
+    /// Reads the attribute keys of Stripe metadata, None when it has none of them
+    pub fn from_metadata(metadata: &HashMap<String, String>) -> Option<Self> {
+        let value = |key: &str| {
+            metadata
+                .get(key)
+                .map(|value| value.trim())
+                .filter(|value| !value.is_empty())
+        };
+        let number = |key: &str| value(key).and_then(|value| value.parse().ok());
+        let attributes = CfgAttributes {
+            net_weight_g: number("net_weight_g"),
+            pieces_per_pack: number("pieces_per_pack"),
+            storage: value("storage").and_then(Storage::from_metadata_value),
+            shelf_life_days: number("shelf_life_days"),
+            allergens: value("allergens")
+                .map(|allergens| {
+                    allergens
+                        .split(',')
+                        .map(|allergen| allergen.trim().to_string())
+                        .filter(|allergen| !allergen.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            nutrition: value("nutrition")
+                .map(|nutrition| {
+                    nutrition
+                        .split(';')
+                        .filter_map(|row| {
+                            let (nutrient, per_100g) = row.split_once(':')?;
+                            Some(NutritionRow {
+                                nutrient: nutrient.trim().to_string(),
+                                per_100g: per_100g.trim().to_string(),
+                            })
+                        })
+                        .collect()
+                })
+                .unwrap_or_default(),
+        };
+        (attributes != CfgAttributes::default()).then_some(attributes)
+    }
+
+    /// Writes the attributes into Stripe metadata the way `from_metadata` reads them, unset ones are removed
+    pub fn write_metadata(&self, metadata: &mut HashMap<String, String>) {
+        for key in ATTRIBUTE_KEYS {
+            metadata.remove(key);
+        }
+        let mut insert = |key: &str, value: Option<String>| {
+            if let Some(value) = value.filter(|value| !value.is_empty()) {
+                metadata.insert(key.to_string(), value);
+            }
+        };
+        insert(
+            "net_weight_g",
+            self.net_weight_g.map(|grams| grams.to_string()),
+        );
+        insert(
+            "pieces_per_pack",
+            self.pieces_per_pack.map(|pieces| pieces.to_string()),
+        );
+        insert(
+            "storage",
+            self.storage
+                .map(|storage| storage.metadata_value().to_string()),
+        );
+        insert(
+            "shelf_life_days",
+            self.shelf_life_days.map(|days| days.to_string()),
+        );
+        insert("allergens", Some(self.allergens.join(", ")));
+        insert(
+            "nutrition",
+            Some(
+                self.nutrition
+                    .iter()
+                    .map(|row| format!("{}: {}", row.nutrient, row.per_100g))
+                    .collect::<Vec<_>>()
+                    .join("; "),
+            ),
+        );
+    }
+}
+
+/// A weight like "500 g" or "1.5 kg"
+pub fn format_weight(grams: u32) -> String {
+    match grams {
+        grams if grams >= 1000 => format!("{} kg", grams as f64 / 1000.0),
+        grams => format!("{} g", grams),
+    }
+}
+
+/// What a unit price is per, weighed products under a kilo are priced per 100 g like on shop shelves
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnitPriceBasis {
+    Per100g,
+    PerKg,
+    PerPiece,
+}
+
+/// Price of a product per unit, for comparing packs of different sizes
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct UnitPrice {
+    pub cents: f64,
+    pub basis: UnitPriceBasis,
+}
+
+impl UnitPrice {
+    /// Sorts weighed products by price per kg before counted ones by price per piece,
+    /// the two can't be compared with each other
+    pub fn sort_key(&self) -> (u8, f64) {
+        match self.basis {
+            UnitPriceBasis::Per100g => (0, self.cents * 10.0),
+            UnitPriceBasis::PerKg => (0, self.cents),
+            UnitPriceBasis::PerPiece => (1, self.cents),
+        }
+    }
+}
+
+impl std::fmt::Display for UnitPrice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let dollars = self.cents / 100.0;
+        match self.basis {
+            UnitPriceBasis::Per100g => write!(f, "${:.2} per 100 g", dollars),
+            UnitPriceBasis::PerKg => write!(f, "${:.2} per kg", dollars),
+            UnitPriceBasis::PerPiece => write!(f, "${:.2} each", dollars),
+        }
+    }
+}
+
+/// A variant groups products under one parent, each variant is still its own product in Stripe
+/// with its own price and stock. Variants with the same parent share one page with a selector.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CfgVariant {
+    pub parent: String, // name of the parent product, like "Quails"
+    pub options: BTreeMap<String, String>, // axis to value, like "size": "Jumbo", "cut": "Butterfly"
+}
+
+impl CfgVariant {
+    /// Reads "variant_of" and the "variant_<axis>" keys of Stripe metadata
+    pub fn from_metadata(metadata: &HashMap<String, String>) -> Option<Self> {
+        let parent = metadata.get(VARIANT_PARENT_KEY)?.trim();
+        if parent.is_empty() {
+            return None;
+        }
+        let options = metadata
+            .iter()
+            .filter(|(key, _)| key.as_str() != VARIANT_PARENT_KEY)
+            .filter_map(|(key, value)| {
+                let axis = key.strip_prefix(VARIANT_OPTION_PREFIX)?;
+                Some((axis.to_string(), value.clone()))
+            })
+            .collect();
+        Some(CfgVariant {
+            parent: parent.to_string(),
+            options,
+        })
+    }
+
+    /// Writes the variant into Stripe metadata the way `from_metadata` reads it
+    pub fn write_metadata(&self, metadata: &mut HashMap<String, String>) {
+        metadata.insert(VARIANT_PARENT_KEY.to_string(), self.parent.clone());
+        for (axis, value) in &self.options {
+            metadata.insert(format!("{}{}", VARIANT_OPTION_PREFIX, axis), value.clone());
+        }
+    }
+
+    /// The option values, like "Jumbo, Butterfly"
+    pub fn label(&self) -> String {
+        self.options.values().cloned().collect::<Vec<_>>().join(", ")
+    }
+}
+
+impl CfgProduct {
+    pub fn is_active(&self) -> bool {
+        self.active != Some(false)
+    }
+
+    pub fn slug(&self) -> String {
+        slug(&self.name)
+    }
+
+    /// Slug of the page showing the product, the page of its parent for variants
+    pub fn page_slug(&self) -> String {
+        match &self.variant {
+            Some(variant) => slug(&variant.parent),
+            None => self.slug(),
+        }
+    }
+
+    /// Price per 100 g or kg from net_weight_g, or else per piece from pieces_per_pack
+    pub fn unit_price(&self) -> Option<UnitPrice> {
+        let unit_amount = self.price.as_ref()?.unit_amount? as f64;
+        let attributes = self.attributes.as_ref()?;
+        match (attributes.net_weight_g, attributes.pieces_per_pack) {
+            (Some(grams), _) if grams > 0 => Some(match grams {
+                grams if grams >= 1000 => UnitPrice {
+                    cents: unit_amount * 1000.0 / grams as f64,
+                    basis: UnitPriceBasis::PerKg,
+                },
+                grams => UnitPrice {
+                    cents: unit_amount * 100.0 / grams as f64,
+                    basis: UnitPriceBasis::Per100g,
+                },
+            }),
+            (_, Some(pieces)) if pieces > 1 => Some(UnitPrice {
+                cents: unit_amount / pieces as f64,
+                basis: UnitPriceBasis::PerPiece,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Name with the options of a variant, like "Quails (Jumbo, Butterfly)"
+    pub fn display_name(&self) -> String {
+        match &self.variant {
+            Some(variant) if !variant.options.is_empty() => {
+                format!("{} ({})", variant.parent, variant.label())
+            }
+            _ => self.name.clone(),
+        }
+    }
+
+    /// The local image with the thumbnail role in image_manifest, or thumbnail.webp of local_images,
+    /// or the first local image if there is neither
+    pub fn thumbnail(&self) -> Option<&PathBuf> {
+        let local_images = self.local_images.as_ref()?;
+        self.local_image_with_role(ImageRole::Thumbnail)
+            .or_else(|| {
+                local_images
+                    .iter()
+                    .find(|path| path.file_name().is_some_and(|name| name == "thumbnail.webp"))
+            })
+            .or(local_images.first())
+    }
+
+    /// Entry of image_manifest for a path of local_images
+    pub fn image_entry(&self, path: &std::path::Path) -> Option<&CfgImage> {
+        let file_name = path.file_name()?;
+        self.image_manifest
+            .as_ref()?
+            .iter()
+            .find(|image| std::ffi::OsStr::new(&image.file) == file_name)
+    }
+
+    pub fn local_image_with_role(&self, role: ImageRole) -> Option<&PathBuf> {
+        self.local_images
+            .as_ref()?
+            .iter()
+            .find(|path| self.image_entry(path).is_some_and(|image| image.role == Some(role)))
+    }
+
+    /// local_images in the order of the product page, the hero image first
+    pub fn gallery_images(&self) -> Vec<PathBuf> {
+        let mut images = self.local_images.clone().unwrap_or_default();
+        if let Some(hero) = self.local_image_with_role(ImageRole::Hero) {
+            let hero = hero.clone();
+            images.retain(|path| *path != hero);
+            images.insert(0, hero);
+        }
+        images
+    }
+
+    /// Alt text of a local image from image_manifest, or the product name
+    pub fn image_alt(&self, path: &std::path::Path) -> String {
+        self.image_entry(path)
+            .and_then(|image| image.alt.clone())
+            .unwrap_or_else(|| self.name.clone())
+    }
+
+    pub fn local_image_size(&self, path: &std::path::Path) -> Option<&LocalImage> {
+        self.local_image_sizes
+            .as_ref()?
+            .iter()
+            .find(|local_image| local_image.path == path)
+    }
+}
+
+/// What an image of a product shows
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageRole {
+    /// Shown in product lists
+    Thumbnail,
+    /// Shown first on the product page
+    Hero,
+    Label,
+    NutritionPanel,
+}
+
+impl std::fmt::Display for ImageRole {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImageRole::Thumbnail => write!(f, "thumbnail"),
+            ImageRole::Hero => write!(f, "hero"),
+            ImageRole::Label => write!(f, "label"),
+            ImageRole::NutritionPanel => write!(f, "nutrition_panel"),
+        }
+    }
+}
+
+/// An entry of image_manifest, like {"file": "front.webp", "alt": "Jar of honey", "role": "hero"}
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CfgImage {
+    pub file: String, // file name in products_assets/<item_number>/ready_assets/
+    #[serde(default)]
+    pub alt: Option<String>,
+    #[serde(default)]
+    pub role: Option<ImageRole>,
+}
+
+/// A webp of local_images with its size and smaller copies of it, so pages can use srcset and reserve its space
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LocalImage {
+    pub path: PathBuf,
+    pub width: u32,
+    pub height: u32,
+    pub variants: Vec<ImageVariant>, // narrowest first
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ImageVariant {
+    pub path: PathBuf,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl LocalImage {
+    /// Value of the srcset attribute, like "/a-320w.webp 320w, /a.webp 1600w"
+    pub fn srcset(&self) -> String {
+        self.variants
+            .iter()
+            .map(|variant| (&variant.path, variant.width))
+            .chain([(&self.path, self.width)])
+            .map(|(path, width)| format!("{} {}w", path.display(), width))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    /// The new value replaced the old one
+    Changed,
+    /// Both sides changed the field since the last refresh, the local value was kept (merges only)
+    Conflict,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FieldChange {
+    pub field: String,
+    pub old: String, // the local value when merging with Stripe
+    pub new: String, // Stripe's value when merging
+    pub kind: ChangeKind,
+    /// New unit_amount minus the old one in cents, price changes only
+    #[serde(default)]
+    pub price_delta: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum ProductChange {
+    Added {
+        stripe_id: String,
+        name: String,
+    },
+    /// No longer active in Stripe, kept in the config with `active: Some(false)`
+    Deactivated {
+        stripe_id: String,
+        name: String,
+    },
+    /// Dropped from the config, when rewriting it from Stripe or between revisions
+    Removed {
+        stripe_id: String,
+        name: String,
+    },
+    Updated {
+        stripe_id: String,
+        name: String,
+        fields: Vec<FieldChange>,
+    },
+}
+
+impl ProductChange {
+    pub fn stripe_id(&self) -> &str {
+        match self {
+            ProductChange::Added { stripe_id, .. }
+            | ProductChange::Deactivated { stripe_id, .. }
+            | ProductChange::Removed { stripe_id, .. }
+            | ProductChange::Updated { stripe_id, .. } => stripe_id,
+        }
+    }
+}
+
+/// Local images found in products_assets/<item_number>/ready_assets/ of a product
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProductImages {
+    pub stripe_id: String,
+    pub name: String,
+    pub item_number: Option<i64>,
+    /// None when the product has no item_number or its images dir doesn't exist
+    pub found: Option<Vec<PathBuf>>,
+    /// Found images image_manifest doesn't list, they are shown after the listed ones
+    #[serde(default)]
+    pub unreferenced: Vec<PathBuf>,
+    /// Files image_manifest lists that weren't found
+    #[serde(default)]
+    pub missing: Vec<String>,
+}
+
+impl ProductImages {
+    pub fn is_missing(&self) -> bool {
+        self.found.as_ref().is_none_or(|found| found.is_empty())
+    }
+}
+
+/// What a refresh from Stripe changes in products_config.json, and the local images it found.
+/// Also the changes between two revisions of it, without images.
+/// Display writes it as a report for humans.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ProductsConfigDiff {
+    pub changes: Vec<ProductChange>,
+    #[serde(default)]
+    pub images: Vec<ProductImages>,
+}
+
+impl ProductsConfigDiff {
+    /// True when the config stays the same, images aren't considered
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    pub fn conflicts(&self) -> usize {
+        self.changes
+            .iter()
+            .map(|change| match change {
+                ProductChange::Updated { fields, .. } => fields
+                    .iter()
+                    .filter(|field| field.kind == ChangeKind::Conflict)
+                    .count(),
+                _ => 0,
+            })
+            .sum()
+    }
+
+    /// One line count of the changes, like "2 added, 1 updated, 1 conflict"
+    pub fn summary(&self) -> String {
+        let count = |f: fn(&ProductChange) -> bool| self.changes.iter().filter(|c| f(c)).count();
+        let counts = [
+            (count(|c| matches!(c, ProductChange::Added { .. })), "added"),
+            (
+                count(|c| matches!(c, ProductChange::Updated { .. })),
+                "updated",
+            ),
+            (
+                count(|c| matches!(c, ProductChange::Deactivated { .. })),
+                "deactivated",
+            ),
+            (
+                count(|c| matches!(c, ProductChange::Removed { .. })),
+                "removed",
+            ),
+            (self.conflicts(), "conflicts"),
+            (
+                self.images
+                    .iter()
+                    .filter(|images| images.is_missing())
+                    .count(),
+                "without local images",
+            ),
+        ];
+
+        let summary: Vec<String> = counts
+            .into_iter()
+            .filter(|(count, _)| *count > 0)
+            .map(|(count, label)| format!("{} {}", count, label))
+            .collect();
+        match summary.is_empty() {
+            true => "No changes".to_string(),
+            false => summary.join(", "),
+        }
+    }
+}
+
+/// Writes one change of a diff as a report line, fields indented below it
+fn write_change(f: &mut std::fmt::Formatter<'_>, change: &ProductChange) -> std::fmt::Result {
+    match change {
+        ProductChange::Added { stripe_id, name } => writeln!(f, "+ {} ({}): added", name, stripe_id)?,
+        ProductChange::Deactivated { stripe_id, name } => writeln!(
+            f,
+            "- {} ({}): no longer active in Stripe, deactivated",
+            name, stripe_id
+        )?,
+        ProductChange::Removed { stripe_id, name } => writeln!(
+            f,
+            "- {} ({}): no longer active in Stripe, removed",
+            name, stripe_id
+        )?,
+        ProductChange::Updated {
+            stripe_id,
+            name,
+            fields,
+        } => {
+            writeln!(f, "~ {} ({}):", name, stripe_id)?;
+            for field in fields {
+                match field.kind {
+                    ChangeKind::Changed => match field.price_delta {
+                        Some(price_delta) => writeln!(
+                            f,
+                            "    {}: {} -> {} ({}{:.2})",
+                            field.field,
+                            field.old,
+                            field.new,
+                            if price_delta < 0 { "-$" } else { "+$" },
+                            price_delta.abs() as f64 / 100.0
+                        )?,
+                        None => {
+                            writeln!(f, "    {}: {} -> {}", field.field, field.old, field.new)?
+                        }
+                    },
+                    ChangeKind::Conflict => writeln!(
+                        f,
+                        "    {}: kept local {}, Stripe has {} (changed on both sides)",
+                        field.field, field.old, field.new
+                    )?,
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+impl std::fmt::Display for ProductsConfigDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for change in &self.changes {
+            write_change(f, change)?;
+        }
+
+        if !self.images.is_empty() {
+            writeln!(f, "Local images:")?;
+        }
+        for images in &self.images {
+            let item_number = images
+                .item_number
+                .map_or("-".to_string(), |item_number| item_number.to_string());
+            match &images.found {
+                Some(found) if !found.is_empty() => writeln!(
+                    f,
+                    "    #{} {}: {} found",
+                    item_number,
+                    images.name,
+                    found.len()
+                )?,
+                _ => writeln!(f, "    #{} {}: missing", item_number, images.name)?,
+            }
+            for path in &images.unreferenced {
+                writeln!(f, "        {}: not in image_manifest", path.display())?;
+            }
+            for file in &images.missing {
+                writeln!(f, "        {}: in image_manifest, not found", file)?;
+            }
+        }
+        writeln!(f, "{}.", self.summary())
+    }
+}
+
+fn same<T: Serialize>(a: &T, b: &T) -> bool {
+    serde_json::to_value(a).ok() == serde_json::to_value(b).ok()
+}
+
+fn describe<T: Serialize>(value: &T) -> String {
+    serde_json::to_string(value).unwrap_or_default()
+}
+
+fn describe_price(price: &Option<stripe_retypes::DbPrice>) -> String {
+    match price {
+        Some(price) => match price.unit_amount {
+            Some(unit_amount) => format!("${:.2} ({})", unit_amount as f64 / 100.0, price.id),
+            None => price.id.clone(),
+        },
+        None => "none".to_string(),
+    }
+}
+
+fn field_change<T>(
+    field: &str,
+    old: &T,
+    new: &T,
+    describe: fn(&T) -> String,
+    kind: ChangeKind,
+) -> FieldChange {
+    FieldChange {
+        field: field.to_string(),
+        old: describe(old),
+        new: describe(new),
+        kind,
+        price_delta: None,
+    }
+}
+
+/// Adds a change of the field when the values differ, returns whether they do
+fn compare_field<T: Serialize>(
+    field: &str,
+    old: &T,
+    new: &T,
+    describe: fn(&T) -> String,
+    changes: &mut Vec<FieldChange>,
+) -> bool {
+    let is_changed = !same(old, new);
+    if is_changed {
+        changes.push(field_change(field, old, new, describe, ChangeKind::Changed));
+    }
+    is_changed
+}
+
+/// Like compare_field, with the change of unit_amount as price_delta
+fn compare_price(
+    old: &Option<stripe_retypes::DbPrice>,
+    new: &Option<stripe_retypes::DbPrice>,
+    changes: &mut Vec<FieldChange>,
+) -> bool {
+    let is_changed = compare_field("price", old, new, describe_price, changes);
+    let unit_amount = |price: &Option<stripe_retypes::DbPrice>| price.as_ref()?.unit_amount;
+    if let (true, Some(change), Some(old), Some(new)) = (
+        is_changed,
+        changes.last_mut(),
+        unit_amount(old),
+        unit_amount(new),
+    ) {
+        change.price_delta = Some(new - old);
+    }
+    is_changed
+}
+
+/// Shared fields take Stripe's value unless they were edited locally since `base`.
+/// Without a base there is no telling an edit apart, so differing values are kept as conflicts.
+fn merge_edited<T: Serialize + Clone>(
+    field: &str,
+    local: &mut T,
+    base: Option<&T>,
+    stripe: &T,
+    changes: &mut Vec<FieldChange>,
+) {
+    if same(local, stripe) {
+        return;
+    }
+    let is_edited_locally = base.is_none_or(|base| !same(local, base));
+    let is_changed_in_stripe = base.is_none_or(|base| !same(stripe, base));
+
+    let kind = match (is_edited_locally, is_changed_in_stripe) {
+        (false, _) => ChangeKind::Changed,
+        (true, false) => return, // a local edit Stripe didn't touch
+        (true, true) => ChangeKind::Conflict,
+    };
+    changes.push(field_change(field, local, stripe, describe, kind));
+    if kind == ChangeKind::Changed {
+        *local = stripe.clone();
+    }
+}
+
+fn merge_product(
+    local: &CfgProduct,
+    base: Option<&CfgProduct>,
+    stripe: &CfgProduct,
+) -> (CfgProduct, Vec<FieldChange>) {
+    let mut merged = local.clone();
+    let mut changes = Vec::new();
+
+    // Stripe is authoritative for these, they always take Stripe's value
+    if compare_field("name", &local.name, &stripe.name, describe, &mut changes) {
+        merged.name = stripe.name.clone();
+    }
+    if compare_price(&local.price, &stripe.price, &mut changes) {
+        merged.price = stripe.price.clone();
+    }
+    if compare_field("active", &local.is_active(), &true, describe, &mut changes) {
+        merged.active = Some(true);
+    }
+
+    merge_edited(
+        "item_number",
+        &mut merged.item_number,
+        base.map(|base| &base.item_number),
+        &stripe.item_number,
+        &mut changes,
+    );
+    merge_edited(
+        "description",
+        &mut merged.description,
+        base.map(|base| &base.description),
+        &stripe.description,
+        &mut changes,
+    );
+    merge_edited(
+        "images",
+        &mut merged.images,
+        base.map(|base| &base.images),
+        &stripe.images,
+        &mut changes,
+    );
+    merge_edited(
+        "variant",
+        &mut merged.variant,
+        base.map(|base| &base.variant),
+        &stripe.variant,
+        &mut changes,
+    );
+    merge_edited(
+        "unit_label",
+        &mut merged.unit_label,
+        base.map(|base| &base.unit_label),
+        &stripe.unit_label,
+        &mut changes,
+    );
+    merge_edited(
+        "attributes",
+        &mut merged.attributes,
+        base.map(|base| &base.attributes),
+        &stripe.attributes,
+        &mut changes,
+    );
+    merge_edited(
+        "metadata",
+        &mut merged.metadata,
+        base.map(|base| &base.metadata),
+        &stripe.metadata,
+        &mut changes,
+    );
+    // local_images, local_image_sizes and image_manifest only exist locally, so they are always kept
+
+    (merged, changes)
+}
+
+fn find_product<'a>(products: &'a CfgProducts, stripe_id: &str) -> Option<&'a CfgProduct> {
+    products
+        .0
+        .iter()
+        .find(|product| product.stripe_id == stripe_id)
+}
+
+/// Three-way merge of the local products config with the products in Stripe.
+///
+/// `base` is what Stripe had at the last refresh, it tells local edits apart from changes made in Stripe.
+/// Stripe wins for name, price and the active flag, and local_images and image_manifest are always kept.
+/// Other fields take Stripe's value unless they were edited locally, edits on both sides keep the local value and are reported as conflicts.
+/// Products that are no longer in Stripe are kept but deactivated.
+pub fn merge_products_config(
+    local: &CfgProducts,
+    base: &CfgProducts,
+    stripe: &CfgProducts,
+) -> (CfgProducts, ProductsConfigDiff) {
+    let find = |products: &CfgProducts, stripe_id: &str| find_product(products, stripe_id).cloned();
+
+    let mut merged = Vec::new();
+    let mut diff = ProductsConfigDiff::default();
+
+    for local_product in &local.0 {
+        let base_product = find(base, &local_product.stripe_id);
+
+        match find(stripe, &local_product.stripe_id) {
+            Some(stripe_product) => {
+                let (product, fields) =
+                    merge_product(local_product, base_product.as_ref(), &stripe_product);
+                if !fields.is_empty() {
+                    diff.changes.push(ProductChange::Updated {
+                        stripe_id: product.stripe_id.clone(),
+                        name: product.name.clone(),
+                        fields,
+                    });
+                }
+                merged.push(product);
+            }
+            None => {
+                let mut product = local_product.clone();
+                if product.is_active() {
+                    product.active = Some(false);
+                    diff.changes.push(ProductChange::Deactivated {
+                        stripe_id: product.stripe_id.clone(),
+                        name: product.name.clone(),
+                    });
+                }
+                merged.push(product);
+            }
+        }
+    }
+
+    for stripe_product in &stripe.0 {
+        if find(local, &stripe_product.stripe_id).is_none() {
+            diff.changes.push(ProductChange::Added {
+                stripe_id: stripe_product.stripe_id.clone(),
+                name: stripe_product.name.clone(),
+            });
+            merged.push(stripe_product.clone());
+        }
+    }
+
+    (CfgProducts(merged), diff)
+}
+
+/// Replacing the local config with the products in Stripe, reported like a merge where Stripe wins everything.
+/// Only image_manifest is kept from the local config.
+pub fn rewrite_products_config(
+    local: &CfgProducts,
+    stripe: &CfgProducts,
+) -> (CfgProducts, ProductsConfigDiff) {
+    let (_, diff) = merge_products_config(local, local, stripe);
+    // Deactivated products are dropped instead, the ones deactivated earlier too
+    let mut changes: Vec<ProductChange> = diff
+        .changes
+        .into_iter()
+        .filter(|change| !matches!(change, ProductChange::Deactivated { .. }))
+        .collect();
+    for product in &local.0 {
+        if find_product(stripe, &product.stripe_id).is_none() {
+            changes.push(ProductChange::Removed {
+                stripe_id: product.stripe_id.clone(),
+                name: product.name.clone(),
+            });
+        }
+    }
+
+    // image_manifest is written by hand and can't come from Stripe, so it's carried over
+    let mut rewritten = stripe.clone();
+    for product in &mut rewritten.0 {
+        if let Some(local_product) = find_product(local, &product.stripe_id) {
+            product.image_manifest = local_product.image_manifest.clone();
+        }
+    }
+
+    (
+        rewritten,
+        ProductsConfigDiff {
+            changes,
+            images: Vec::new(),
+        },
+    )
+}
+
+/// Changes from one version of products_config.json to another, like between two revisions.
+/// Unlike a merge every field is compared, local_images included.
+pub fn diff_products_configs(old: &CfgProducts, new: &CfgProducts) -> ProductsConfigDiff {
+    let mut diff = ProductsConfigDiff::default();
+
+    for old_product in &old.0 {
+        let Some(new_product) = find_product(new, &old_product.stripe_id) else {
+            diff.changes.push(ProductChange::Removed {
+                stripe_id: old_product.stripe_id.clone(),
+                name: old_product.name.clone(),
+            });
+            continue;
+        };
+
+        let mut fields = Vec::new();
+        let (old, new) = (old_product, new_product);
+        compare_field("name", &old.name, &new.name, describe, &mut fields);
+        compare_price(&old.price, &new.price, &mut fields);
+        compare_field(
+            "active",
+            &old.is_active(),
+            &new.is_active(),
+            describe,
+            &mut fields,
+        );
+        compare_field(
+            "item_number",
+            &old.item_number,
+            &new.item_number,
+            describe,
+            &mut fields,
+        );
+        compare_field(
+            "description",
+            &old.description,
+            &new.description,
+            describe,
+            &mut fields,
+        );
+        compare_field("images", &old.images, &new.images, describe, &mut fields);
+        compare_field(
+            "local_images",
+            &old.local_images,
+            &new.local_images,
+            describe,
+            &mut fields,
+        );
+        compare_field(
+            "local_image_sizes",
+            &old.local_image_sizes,
+            &new.local_image_sizes,
+            |local_image_sizes| {
+                format!(
+                    "{} images",
+                    local_image_sizes.as_ref().map_or(0, |sizes| sizes.len())
+                )
+            },
+            &mut fields,
+        );
+        compare_field(
+            "variant",
+            &old.variant,
+            &new.variant,
+            describe,
+            &mut fields,
+        );
+        compare_field(
+            "unit_label",
+            &old.unit_label,
+            &new.unit_label,
+            describe,
+            &mut fields,
+        );
+        compare_field(
+            "attributes",
+            &old.attributes,
+            &new.attributes,
+            describe,
+            &mut fields,
+        );
+        compare_field(
+            "image_manifest",
+            &old.image_manifest,
+            &new.image_manifest,
+            describe,
+            &mut fields,
+        );
+        compare_field(
+            "metadata",
+            &old.metadata,
+            &new.metadata,
+            describe,
+            &mut fields,
+        );
+
+        if !fields.is_empty() {
+            diff.changes.push(ProductChange::Updated {
+                stripe_id: new_product.stripe_id.clone(),
+                name: new_product.name.clone(),
+                fields,
+            });
+        }
+    }
+
+    for new_product in &new.0 {
+        if find_product(old, &new_product.stripe_id).is_none() {
+            diff.changes.push(ProductChange::Added {
+                stripe_id: new_product.stripe_id.clone(),
+                name: new_product.name.clone(),
+            });
+        }
+    }
+
+    diff
+}
+
+/// A stored version of products_config.json, every write of it is kept as one
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProductsConfigRevision {
+    pub revision: i64,
+    pub created_at: i64,
+    pub actor: String,
+    pub note: String, // what wrote it, like "refresh" or "rollback to #3"
+    pub products_count: i64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An active product with a price, the way a sync from Stripe derives it
+    pub(super) fn product(stripe_id: &str, name: &str, unit_amount: i64) -> CfgProduct {
+        CfgProduct {
+            stripe_id: stripe_id.to_string(),
+            item_number: Some(1),
+            name: name.to_string(),
+            description: None,
+            price: Some(stripe_retypes::DbPrice {
+                id: format!("price_{}", stripe_id),
+                active: true,
+                billing_scheme: None,
+                created: None,
+                custom_unit_amount: None,
+                livemode: false,
+                lookup_key: None,
+                metadata: None,
+                nickname: None,
+                product: Some(stripe_id.to_string()),
+                recurring: None,
+                type_: None,
+                unit_amount: Some(unit_amount),
+                unit_amount_decimal: Some(unit_amount.to_string()),
+            }),
+            images: None,
+            local_images: None,
+            metadata: Some(HashMap::from([(
+                "category".to_string(),
+                "food".to_string(),
+            )])),
+            active: Some(true),
+            local_image_sizes: None,
+            image_manifest: None,
+            variant: None,
+            unit_label: None,
+            attributes: None,
+        }
+    }
+
+    fn metadata(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn fields_of<'a>(diff: &'a ProductsConfigDiff, stripe_id: &str) -> &'a [FieldChange] {
+        diff.changes
+            .iter()
+            .find_map(|change| match change {
+                ProductChange::Updated {
+                    stripe_id: change_stripe_id,
+                    fields,
+                    ..
+                } if change_stripe_id == stripe_id => Some(fields.as_slice()),
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn merge_takes_stripe_changes_and_keeps_local_edits() {
+        let base = product("prod_1", "Eggs", 900);
+        let mut local = base.clone();
+        local.description = Some("Free range, edited locally".to_string());
+        local.local_images = Some(vec![PathBuf::from("/products_assets/1/ready_assets/a.webp")]);
+        let mut stripe = base.clone();
+        stripe.name = "Free Range Eggs".to_string();
+        stripe.images = Some(vec!["https://files.stripe.com/eggs.png".to_string()]);
+
+        let (merged, diff) = merge_products_config(
+            &CfgProducts(vec![local.clone()]),
+            &CfgProducts(vec![base]),
+            &CfgProducts(vec![stripe.clone()]),
+        );
+
+        let merged = &merged.0[0];
+        assert_eq!(merged.name, "Free Range Eggs");
+        assert_eq!(merged.images, stripe.images);
+        assert_eq!(merged.description, local.description);
+        assert_eq!(merged.local_images, local.local_images);
+        let fields: Vec<&str> = fields_of(&diff, "prod_1")
+            .iter()
+            .map(|field| field.field.as_str())
+            .collect();
+        assert_eq!(fields, ["name", "images"]);
+        assert_eq!(diff.conflicts(), 0);
+    }
+
+    #[test]
+    fn merge_keeps_local_value_of_conflicts() {
+        let base = product("prod_1", "Eggs", 900);
+        let mut local = base.clone();
+        local.description = Some("Local".to_string());
+        let mut stripe = base.clone();
+        stripe.description = Some("Stripe".to_string());
+
+        let (merged, diff) = merge_products_config(
+            &CfgProducts(vec![local]),
+            &CfgProducts(vec![base]),
+            &CfgProducts(vec![stripe]),
+        );
+
+        assert_eq!(merged.0[0].description.as_deref(), Some("Local"));
+        assert_eq!(diff.conflicts(), 1);
+    }
+
+    #[test]
+    fn merge_deactivates_missing_and_adds_new_products() {
+        let gone = product("prod_gone", "Duck Eggs", 1200);
+        let new = product("prod_new", "Quail Eggs", 700);
+        let stripe = CfgProducts(vec![new]);
+
+        let (merged, diff) = merge_products_config(
+            &CfgProducts(vec![gone.clone()]),
+            &CfgProducts(vec![gone]),
+            &stripe,
+        );
+
+        assert_eq!(merged.0.len(), 2);
+        assert!(!merged.0[0].is_active());
+        assert!(merged.0[1].is_active());
+        assert!(matches!(
+            diff.changes.as_slice(),
+            [
+                ProductChange::Deactivated { .. },
+                ProductChange::Added { .. }
+            ]
+        ));
+
+        // Refreshing again changes nothing
+        let (remerged, diff) = merge_products_config(&merged, &stripe, &stripe);
+        assert!(diff.is_empty());
+        assert_eq!(remerged.0.len(), 2);
+    }
+
+    #[test]
+    fn variant_is_read_from_metadata() {
+        let variant = CfgVariant::from_metadata(&metadata(&[
+            ("variant_of", " Quails "),
+            ("variant_size", "Jumbo"),
+            ("variant_cut", "Butterfly"),
+            ("category", "food"),
+        ]))
+        .unwrap();
+        assert_eq!(variant.parent, "Quails");
+        assert_eq!(
+            variant.options,
+            BTreeMap::from([
+                ("cut".to_string(), "Butterfly".to_string()),
+                ("size".to_string(), "Jumbo".to_string()),
+            ])
+        );
+        assert_eq!(variant.label(), "Butterfly, Jumbo");
+
+        let mut written = HashMap::new();
+        variant.write_metadata(&mut written);
+        assert_eq!(CfgVariant::from_metadata(&written), Some(variant));
+
+        assert_eq!(CfgVariant::from_metadata(&metadata(&[("variant_size", "Jumbo")])), None);
+        assert_eq!(CfgVariant::from_metadata(&metadata(&[("variant_of", "  ")])), None);
+    }
+
+    #[test]
+    fn attributes_are_read_from_metadata() {
+        let attributes = CfgAttributes::from_metadata(&metadata(&[
+            ("net_weight_g", "500"),
+            ("pieces_per_pack", "not a number"),
+            ("storage", "Frozen"),
+            ("shelf_life_days", "365"),
+            ("allergens", "Egg, , Milk"),
+            ("nutrition", "Energy: 520 kJ; Protein: 22.5 g; broken"),
+        ]))
+        .unwrap();
+        assert_eq!(attributes.net_weight_g, Some(500));
+        assert_eq!(attributes.pieces_per_pack, None);
+        assert_eq!(attributes.storage, Some(Storage::Frozen));
+        assert_eq!(attributes.shelf_life_days, Some(365));
+        assert_eq!(attributes.allergens, ["Egg", "Milk"]);
+        assert_eq!(
+            attributes.nutrition,
+            [
+                NutritionRow {
+                    nutrient: "Energy".to_string(),
+                    per_100g: "520 kJ".to_string(),
+                },
+                NutritionRow {
+                    nutrient: "Protein".to_string(),
+                    per_100g: "22.5 g".to_string(),
+                },
+            ]
+        );
+
+        assert_eq!(
+            CfgAttributes::from_metadata(&metadata(&[("storage", "warm"), ("category", "food")])),
+            None
+        );
+    }
+
+    #[test]
+    fn attributes_written_to_metadata_read_back_the_same() {
+        let attributes = CfgAttributes {
+            pieces_per_pack: Some(18),
+            storage: Some(Storage::Chilled),
+            ..Default::default()
+        };
+        // Keys of attributes that are no longer set are removed
+        let mut written = metadata(&[("net_weight_g", "700"), ("category", "food")]);
+        attributes.write_metadata(&mut written);
+
+        assert_eq!(written.get("net_weight_g"), None);
+        assert_eq!(written.get("category").map(String::as_str), Some("food"));
+        assert_eq!(CfgAttributes::from_metadata(&written), Some(attributes));
+    }
+
+    #[test]
+    fn unit_price_follows_weight_or_pieces() {
+        let with_attributes = |unit_amount: i64, attributes: CfgAttributes| CfgProduct {
+            attributes: Some(attributes),
+            ..product("prod_1", "Eggs", unit_amount)
+        };
+
+        let mince = with_attributes(
+            1500,
+            CfgAttributes {
+                net_weight_g: Some(1000),
+                ..Default::default()
+            },
+        )
+        .unit_price()
+        .unwrap();
+        assert_eq!(mince.basis, UnitPriceBasis::PerKg);
+        assert_eq!(mince.to_string(), "$15.00 per kg");
+
+        let honey = with_attributes(
+            1000,
+            CfgAttributes {
+                net_weight_g: Some(250),
+                pieces_per_pack: Some(1),
+                ..Default::default()
+            },
+        )
+        .unit_price()
+        .unwrap();
+        assert_eq!(honey.to_string(), "$4.00 per 100 g");
+
+        let eggs = with_attributes(
+            900,
+            CfgAttributes {
+                pieces_per_pack: Some(18),
+                ..Default::default()
+            },
+        )
+        .unit_price()
+        .unwrap();
+        assert_eq!(eggs.to_string(), "$0.50 each");
+
+        // Weighed products sort before counted ones, by price per kg
+        assert!(honey.sort_key() > mince.sort_key());
+        assert!(eggs.sort_key() > honey.sort_key());
+
+        let single = with_attributes(
+            900,
+            CfgAttributes {
+                pieces_per_pack: Some(1),
+                ..Default::default()
+            },
+        );
+        assert_eq!(single.unit_price(), None);
+        assert_eq!(product("prod_1", "Eggs", 900).unit_price(), None);
+    }
 }
@@ -0,0 +1,196 @@
+//! Pushing local edits of products_config.json back to Stripe, the reverse of a refresh.
+//! Name, description, images, metadata, unit_label and the active flag are updated in place. A price can't
+//! change its amount in Stripe, so a changed unit_amount becomes a new default price and the old one is archived.
+
+use super::{compare_field, compare_price, describe, CfgProduct, FieldChange, ProductChange};
+use serde::*;
+use std::collections::HashMap;
+
+/// What a push changes in Stripe, per product the fields with Stripe's value as old and the local one as new
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct PushPlan {
+    pub changes: Vec<ProductChange>,
+    /// Products that couldn't be fetched from Stripe, they aren't pushed
+    pub skipped: Vec<PushFailure>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PushFailure {
+    pub stripe_id: String,
+    pub name: String,
+    pub error: String,
+}
+
+/// A changed unit_amount pushed as a new default price
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NewPrice {
+    pub stripe_id: String,
+    pub name: String,
+    pub archived_price_id: Option<String>,
+    pub price_id: String,
+    pub unit_amount: i64,
+}
+
+/// Outcome of a push, what was planned and what Stripe accepted
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct PushReport {
+    pub plan: PushPlan,
+    pub pushed: usize,
+    pub new_prices: Vec<NewPrice>,
+    pub failed: Vec<PushFailure>,
+    /// Products that were pushed but left something behind in Stripe, like an old price that is still active
+    pub warnings: Vec<PushFailure>,
+}
+
+/// Metadata as Stripe should have it, with item_number, the variant and attributes written into it like Stripe keeps them
+pub fn pushed_metadata(local: &CfgProduct) -> HashMap<String, String> {
+    let mut metadata = local.metadata.clone().unwrap_or_default();
+    if let Some(item_number) = local.item_number {
+        metadata.insert("item_number".to_string(), item_number.to_string());
+    }
+    if let Some(variant) = &local.variant {
+        variant.write_metadata(&mut metadata);
+    }
+    if let Some(attributes) = &local.attributes {
+        attributes.write_metadata(&mut metadata);
+    }
+    metadata
+}
+
+/// Fields where the local product differs from the one in Stripe, old being Stripe's value.
+/// Descriptions, images and metadata missing locally aren't pushed, they would clear Stripe's.
+pub fn plan_product_push(local: &CfgProduct, stripe: &CfgProduct) -> Vec<FieldChange> {
+    let mut fields = Vec::new();
+
+    compare_field("name", &stripe.name, &local.name, describe, &mut fields);
+    if local.description.is_some() {
+        compare_field(
+            "description",
+            &stripe.description,
+            &local.description,
+            describe,
+            &mut fields,
+        );
+    }
+    if let Some(images) = &local.images {
+        compare_field(
+            "images",
+            &stripe.images.clone().unwrap_or_default(),
+            images,
+            describe,
+            &mut fields,
+        );
+    }
+    if local.metadata.is_some()
+        || local.item_number.is_some()
+        || local.variant.is_some()
+        || local.attributes.is_some()
+    {
+        compare_field(
+            "metadata",
+            &stripe.metadata.clone().unwrap_or_default(),
+            &pushed_metadata(local),
+            describe,
+            &mut fields,
+        );
+    }
+    if local.unit_label.is_some() {
+        compare_field(
+            "unit_label",
+            &stripe.unit_label,
+            &local.unit_label,
+            describe,
+            &mut fields,
+        );
+    }
+    compare_field(
+        "active",
+        &stripe.is_active(),
+        &local.is_active(),
+        describe,
+        &mut fields,
+    );
+
+    // Only the amount is pushed, the local price id is replaced by the new one afterwards
+    let unit_amount = |product: &CfgProduct| product.price.as_ref()?.unit_amount;
+    if unit_amount(local).is_some() && unit_amount(local) != unit_amount(stripe) {
+        compare_price(&stripe.price, &local.price, &mut fields);
+    }
+
+    fields
+}
+
+fn write_failures(
+    f: &mut std::fmt::Formatter<'_>,
+    label: &str,
+    failures: &[PushFailure],
+) -> std::fmt::Result {
+    for failure in failures {
+        writeln!(
+            f,
+            "! {} ({}): {}, {}",
+            failure.name, failure.stripe_id, label, failure.error
+        )?;
+    }
+    Ok(())
+}
+
+impl PushPlan {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// The fields the plan changes of a product
+    pub fn fields_of(&self, stripe_id: &str) -> Option<&[FieldChange]> {
+        self.changes.iter().find_map(|change| match change {
+            ProductChange::Updated {
+                stripe_id: change_stripe_id,
+                fields,
+                ..
+            } if change_stripe_id == stripe_id => Some(fields.as_slice()),
+            _ => None,
+        })
+    }
+}
+
+impl std::fmt::Display for PushPlan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for change in &self.changes {
+            super::write_change(f, change)?;
+        }
+        write_failures(f, "skipped", &self.skipped)?;
+        writeln!(
+            f,
+            "{} products to push, {} skipped.",
+            self.changes.len(),
+            self.skipped.len()
+        )
+    }
+}
+
+impl std::fmt::Display for PushReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.plan)?;
+        for new_price in &self.new_prices {
+            writeln!(
+                f,
+                "$ {} ({}): new price {} of ${:.2}, archived {}",
+                new_price.name,
+                new_price.stripe_id,
+                new_price.price_id,
+                new_price.unit_amount as f64 / 100.0,
+                new_price.archived_price_id.as_deref().unwrap_or("none")
+            )?;
+        }
+        write_failures(f, "failed", &self.failed)?;
+        write_failures(f, "warning", &self.warnings)?;
+        writeln!(
+            f,
+            "{} pushed, {} new prices, {} failed, {} warnings.",
+            self.pushed,
+            self.new_prices.len(),
+            self.failed.len(),
+            self.warnings.len()
+        )
+    }
+}
@@ -0,0 +1,418 @@
+//! CSV export and import of products_config.json, for editing names and descriptions in a spreadsheet.
+//! One row per product, matched back to the config by stripe_id. image_count is only informational,
+//! images are still found in products_assets. Prices aren't imported: the shop shows the price of the
+//! config while checkout charges Stripe's, so a changed price has to go through `push`.
+
+use super::validation::{validate_products_config, ValidationReport};
+use super::{diff_products_configs, CfgProducts, ProductsConfigDiff};
+use serde::*;
+
+const HEADERS: [&str; 7] = [
+    "stripe_id",
+    "item_number",
+    "name",
+    "category",
+    "price",
+    "description",
+    "image_count",
+];
+
+/// A row as the spreadsheet has it, every cell is text until it's checked
+#[derive(Deserialize, Debug)]
+struct ProductRow {
+    stripe_id: String,
+    item_number: String,
+    name: String,
+    category: String,
+    price: String,
+    description: String,
+}
+
+/// A row that couldn't be imported, `line` being its line in the file
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RowError {
+    pub line: u64,
+    pub stripe_id: String,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ImportReport {
+    pub rows: usize,
+    pub errors: Vec<RowError>,
+    /// Changes of the imported rows to the current config
+    pub diff: ProductsConfigDiff,
+    /// Checks of the config with the imported rows
+    pub validation: ValidationReport,
+}
+
+impl ImportReport {
+    /// Whether the imported config can be saved: no row has errors and the products the rows changed have none.
+    /// Errors of products the CSV didn't change are there either way, they don't stop an import.
+    pub fn is_importable(&self) -> bool {
+        self.errors.is_empty()
+            && !self.validation.errors().any(|issue| {
+                self.diff
+                    .changes
+                    .iter()
+                    .any(|change| change.stripe_id() == issue.stripe_id)
+            })
+    }
+}
+
+impl std::fmt::Display for ImportReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for error in &self.errors {
+            writeln!(
+                f,
+                "line {} ({}): {}",
+                error.line, error.stripe_id, error.message
+            )?;
+        }
+        write!(f, "{}", self.diff)?;
+        write!(f, "{}", self.validation)?;
+        writeln!(f, "{} rows, {} with errors.", self.rows, self.errors.len())
+    }
+}
+
+/// Dollars with cents, like "25.00", the way prices are written in the spreadsheet
+fn format_price(unit_amount: i64) -> String {
+    format!("{:.2}", unit_amount as f64 / 100.0)
+}
+
+/// Cents of a price like "25", "25.5" or "$25.00"
+fn parse_price(value: &str) -> Option<i64> {
+    let value = value.trim().trim_start_matches('$').trim();
+    let (dollars, cents) = value.split_once('.').unwrap_or((value, ""));
+    if dollars.is_empty() || cents.len() > 2 || !cents.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let dollars: i64 = dollars.parse().ok().filter(|dollars| *dollars >= 0)?;
+    let cents: i64 = format!("{:0<2}", cents).parse().ok()?;
+    Some(dollars * 100 + cents)
+}
+
+/// Writes one row per product, in the order of the config
+pub fn export_products_csv<W: std::io::Write>(
+    products_config: &CfgProducts,
+    writer: W,
+) -> Result<(), csv::Error> {
+    let mut csv = csv::Writer::from_writer(writer);
+    csv.write_record(HEADERS)?;
+    for product in &products_config.0 {
+        csv.write_record([
+            product.stripe_id.clone(),
+            product
+                .item_number
+                .map_or(String::new(), |item_number| item_number.to_string()),
+            product.name.clone(),
+            product
+                .metadata
+                .as_ref()
+                .and_then(|metadata| metadata.get("category"))
+                .cloned()
+                .unwrap_or_default(),
+            product
+                .price
+                .as_ref()
+                .and_then(|price| price.unit_amount)
+                .map_or(String::new(), format_price),
+            product.description.clone().unwrap_or_default(),
+            product
+                .local_images
+                .as_ref()
+                .map_or(0, |local_images| local_images.len())
+                .to_string(),
+        ])?;
+    }
+    csv.flush()?;
+    Ok(())
+}
+
+/// Applies the rows of a CSV made by `export_products_csv` to the config.
+/// Rows with errors are listed in the report and make it not importable, so a CSV is imported whole or not at all.
+/// Products without a row stay as they are. Fails only when the CSV itself can't be read.
+pub fn import_products_csv<R: std::io::Read>(
+    products_config: &CfgProducts,
+    reader: R,
+) -> Result<(CfgProducts, ImportReport), csv::Error> {
+    // Rows with missing or extra cells are reported like other row errors
+    let mut csv = csv::ReaderBuilder::new().flexible(true).from_reader(reader);
+    let headers = csv.headers()?.clone();
+
+    let mut imported = products_config.clone();
+    let mut report = ImportReport::default();
+    let mut seen_stripe_ids = std::collections::HashSet::new();
+
+    for record in csv.records() {
+        let record = record?;
+        report.rows += 1;
+        let line = record.position().map_or(0, |position| position.line());
+        let mut row_error = |stripe_id: &str, message: String| {
+            report.errors.push(RowError {
+                line,
+                stripe_id: stripe_id.to_string(),
+                message,
+            })
+        };
+
+        let row: ProductRow = match record.deserialize(Some(&headers)) {
+            Ok(row) => row,
+            Err(err) => {
+                row_error(record.get(0).unwrap_or_default(), err.to_string());
+                continue;
+            }
+        };
+        let stripe_id = row.stripe_id.trim();
+        if !seen_stripe_ids.insert(stripe_id.to_string()) {
+            row_error(stripe_id, "stripe_id is in another row too".to_string());
+            continue;
+        }
+        let Some(product) = imported
+            .0
+            .iter_mut()
+            .find(|product| product.stripe_id == stripe_id)
+        else {
+            row_error(
+                stripe_id,
+                "no product with this stripe_id, new products are made in Stripe".to_string(),
+            );
+            continue;
+        };
+
+        let item_number = match row.item_number.trim() {
+            "" => None,
+            item_number => match item_number.parse::<i64>() {
+                Ok(item_number) => Some(item_number),
+                Err(_) => {
+                    row_error(
+                        stripe_id,
+                        format!("item_number {:?} isn't a number", item_number),
+                    );
+                    continue;
+                }
+            },
+        };
+        let name = row.name.trim();
+        if name.is_empty() {
+            row_error(stripe_id, "name is empty".to_string());
+            continue;
+        }
+        // Products without a price are exported with an empty cell, it leaves the price as it is
+        if !row.price.trim().is_empty() {
+            let Some(unit_amount) = parse_price(&row.price) else {
+                row_error(
+                    stripe_id,
+                    format!("price {:?} isn't an amount like 12.50", row.price),
+                );
+                continue;
+            };
+            let current_amount = product.price.as_ref().and_then(|price| price.unit_amount);
+            if current_amount != Some(unit_amount) {
+                row_error(
+                    stripe_id,
+                    format!(
+                        "price {} isn't the current {}, prices aren't imported: \
+                        change price.unit_amount in products_config.json and `push` it",
+                        format_price(unit_amount),
+                        current_amount.map_or("none".to_string(), format_price)
+                    ),
+                );
+                continue;
+            }
+        }
+        product.item_number = item_number;
+        product.name = name.to_string();
+        let description = row.description.trim();
+        product.description = (!description.is_empty()).then(|| description.to_string());
+        match row.category.trim() {
+            "" => {
+                if let Some(metadata) = product.metadata.as_mut() {
+                    metadata.remove("category");
+                }
+            }
+            category => {
+                product
+                    .metadata
+                    .get_or_insert_with(Default::default)
+                    .insert("category".to_string(), category.to_string());
+            }
+        }
+    }
+
+    report.diff = diff_products_configs(products_config, &imported);
+    report.validation = validate_products_config(&imported, None);
+    Ok((imported, report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::product;
+    use super::super::CfgProduct;
+    use super::*;
+
+    const HEADER: &str = "stripe_id,item_number,name,category,price,description,image_count\n";
+
+    fn import(products_config: &CfgProducts, rows: &str) -> (CfgProducts, ImportReport) {
+        import_products_csv(products_config, format!("{}{}", HEADER, rows).as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn prices_are_read_as_cents() {
+        assert_eq!(parse_price("25"), Some(2500));
+        assert_eq!(parse_price("25.5"), Some(2550));
+        assert_eq!(parse_price(" $25.05 "), Some(2505));
+        assert_eq!(parse_price("0.99"), Some(99));
+        assert_eq!(parse_price(&format_price(1234)), Some(1234));
+
+        for invalid in ["", "$", ".50", "25.505", "-25", "25,50", "25.5a", "twelve"] {
+            assert_eq!(parse_price(invalid), None, "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn exported_csv_imports_without_changes() {
+        let products_config = CfgProducts(vec![
+            product("prod_1", "Eggs", 900),
+            CfgProduct {
+                item_number: Some(2),
+                description: Some("Raw, \"local\", honey".to_string()),
+                ..product("prod_2", "Honey", 1250)
+            },
+            CfgProduct {
+                item_number: Some(3),
+                price: None,
+                ..product("prod_3", "Milk", 0)
+            },
+        ]);
+        let mut csv = Vec::new();
+        export_products_csv(&products_config, &mut csv).unwrap();
+
+        let (imported, report) = import_products_csv(&products_config, csv.as_slice()).unwrap();
+
+        assert_eq!(report.rows, 3);
+        assert!(report.errors.is_empty());
+        assert!(report.diff.is_empty());
+        assert_eq!(
+            serde_json::to_value(&imported).unwrap(),
+            serde_json::to_value(&products_config).unwrap()
+        );
+    }
+
+    #[test]
+    fn rows_change_their_products() {
+        let products_config = CfgProducts(vec![product("prod_1", "Eggs", 900)]);
+
+        let (imported, report) = import(
+            &products_config,
+            "prod_1,7,Free Range Eggs,ready_to_eat,$9.00,Dozen,3\n",
+        );
+
+        assert!(report.is_importable(), "{}", report);
+        let eggs = &imported.0[0];
+        assert_eq!(eggs.item_number, Some(7));
+        assert_eq!(eggs.name, "Free Range Eggs");
+        assert_eq!(eggs.description.as_deref(), Some("Dozen"));
+        assert_eq!(
+            eggs.metadata
+                .as_ref()
+                .unwrap()
+                .get("category")
+                .map(String::as_str),
+            Some("ready_to_eat")
+        );
+        assert_eq!(
+            serde_json::to_value(&eggs.price).unwrap(),
+            serde_json::to_value(&products_config.0[0].price).unwrap()
+        );
+    }
+
+    #[test]
+    fn changed_prices_are_not_imported() {
+        let products_config = CfgProducts(vec![
+            product("prod_1", "Eggs", 900),
+            CfgProduct {
+                item_number: Some(2),
+                price: None,
+                ..product("prod_2", "Honey", 0)
+            },
+        ]);
+
+        let (imported, report) = import(
+            &products_config,
+            "prod_1,1,Eggs,food,9.50,,0\n\
+             prod_2,2,Honey,food,12.50,,0\n",
+        );
+
+        let lines: Vec<(u64, &str)> = report
+            .errors
+            .iter()
+            .map(|error| (error.line, error.stripe_id.as_str()))
+            .collect();
+        assert_eq!(lines, [(2, "prod_1"), (3, "prod_2")]);
+        assert!(!report.is_importable());
+        let unit_amount = |products_config: &CfgProducts| {
+            products_config.0[0]
+                .price
+                .as_ref()
+                .and_then(|price| price.unit_amount)
+        };
+        assert_eq!(unit_amount(&imported), Some(900));
+
+        // An empty price leaves it as it is
+        let (imported, report) = import(&products_config, "prod_1,1,Eggs Dozen,food,,,0\n");
+        assert!(report.is_importable(), "{}", report);
+        assert_eq!(imported.0[0].name, "Eggs Dozen");
+        assert_eq!(unit_amount(&imported), Some(900));
+    }
+
+    #[test]
+    fn row_errors_make_the_import_fail() {
+        let products_config = CfgProducts(vec![
+            product("prod_1", "Eggs", 900),
+            CfgProduct {
+                item_number: Some(2),
+                ..product("prod_2", "Honey", 1250)
+            },
+        ]);
+
+        let (_, report) = import(
+            &products_config,
+            "prod_1,1,Eggs,food,9.00,,0\n\
+             prod_1,1,Eggs,food,9.00,,0\n\
+             prod_2,2,Honey,food,12.5.0,,0\n\
+             prod_new,3,Milk,food,3.00,,0\n\
+             prod_2,2\n",
+        );
+
+        let lines: Vec<(u64, &str)> = report
+            .errors
+            .iter()
+            .map(|error| (error.line, error.stripe_id.as_str()))
+            .collect();
+        assert_eq!(
+            lines,
+            [(3, "prod_1"), (4, "prod_2"), (5, "prod_new"), (6, "prod_2")]
+        );
+        assert_eq!(report.rows, 5);
+        assert!(!report.is_importable());
+    }
+
+    #[test]
+    fn only_errors_of_changed_products_block_the_import() {
+        let broken = CfgProduct {
+            item_number: Some(2),
+            price: None,
+            ..product("prod_2", "Honey", 0)
+        };
+        let products_config = CfgProducts(vec![broken, product("prod_1", "Eggs", 900)]);
+
+        let (_, report) = import(&products_config, "prod_1,1,Eggs,food,9.00,Dozen,0\n");
+        assert!(report.validation.has_errors());
+        assert!(report.is_importable(), "{}", report);
+
+        // Giving the changed product the item_number of the broken one blocks it
+        let (_, report) = import(&products_config, "prod_1,2,Eggs,food,9.00,,0\n");
+        assert!(!report.is_importable(), "{}", report);
+    }
+}
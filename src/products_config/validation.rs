@@ -0,0 +1,443 @@
+//! Checks of products_config.json, so one malformed entry doesn't break the shop.
+//! Errors are entries the shop can't sell, warnings are entries shown incomplete.
+
+use super::{variant_axes, CfgProducts, ImageRole, KNOWN_CATEGORIES};
+use serde::*;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum IssueKind {
+    MissingStripeId,
+    MissingName,
+    /// Another product earlier in the config has the same stripe_id
+    DuplicateStripeId,
+    MissingItemNumber,
+    /// Another product earlier in the config has the same item_number, so they'd share images
+    DuplicateItemNumber(i64),
+    MissingPrice,
+    MissingCategory,
+    UnknownCategory(String),
+    MissingLocalImages,
+    MissingThumbnail,
+    MissingImageFile(PathBuf),
+    /// A local image image_manifest doesn't list
+    UnreferencedImage(PathBuf),
+    /// A file image_manifest lists that isn't in local_images
+    MissingManifestImage(String),
+    /// More than one image of image_manifest has a role that's shown once
+    DuplicateImageRole(ImageRole),
+    /// Another variant of the same parent has the same options, the selector can't pick this one
+    DuplicateVariantOptions,
+    /// Other variants of the parent have this option axis, this one doesn't
+    MissingVariantOption(String),
+}
+
+impl IssueKind {
+    pub fn severity(&self) -> Severity {
+        match self {
+            IssueKind::MissingStripeId
+            | IssueKind::MissingName
+            | IssueKind::DuplicateStripeId
+            | IssueKind::DuplicateItemNumber(_)
+            | IssueKind::MissingPrice => Severity::Error,
+            IssueKind::MissingItemNumber
+            | IssueKind::MissingCategory
+            | IssueKind::UnknownCategory(_)
+            | IssueKind::MissingLocalImages
+            | IssueKind::MissingThumbnail
+            | IssueKind::MissingImageFile(_)
+            | IssueKind::UnreferencedImage(_)
+            | IssueKind::MissingManifestImage(_)
+            | IssueKind::DuplicateImageRole(_)
+            | IssueKind::DuplicateVariantOptions
+            | IssueKind::MissingVariantOption(_) => Severity::Warning,
+        }
+    }
+}
+
+impl std::fmt::Display for IssueKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IssueKind::MissingStripeId => write!(f, "stripe_id is empty"),
+            IssueKind::MissingName => write!(f, "name is empty"),
+            IssueKind::DuplicateStripeId => write!(f, "stripe_id is used by another product"),
+            IssueKind::MissingItemNumber => {
+                write!(f, "no item_number, local images can't be found")
+            }
+            IssueKind::DuplicateItemNumber(item_number) => {
+                write!(f, "item_number {} is used by another product", item_number)
+            }
+            IssueKind::MissingPrice => write!(f, "no price with a unit_amount"),
+            IssueKind::MissingCategory => write!(f, "no \"category\" in metadata"),
+            IssueKind::UnknownCategory(category) => write!(
+                f,
+                "category {:?} isn't one of {}",
+                category,
+                KNOWN_CATEGORIES.join(", ")
+            ),
+            IssueKind::MissingLocalImages => write!(f, "no local_images"),
+            IssueKind::MissingThumbnail => write!(
+                f,
+                "no thumbnail.webp or thumbnail role in local_images, the first image is shown instead"
+            ),
+            IssueKind::MissingImageFile(path) => {
+                write!(f, "local image {} doesn't exist", path.display())
+            }
+            IssueKind::UnreferencedImage(path) => write!(
+                f,
+                "local image {} isn't in image_manifest, it's shown last",
+                path.display()
+            ),
+            IssueKind::MissingManifestImage(file) => {
+                write!(f, "image_manifest lists {:?}, it isn't in local_images", file)
+            }
+            IssueKind::DuplicateImageRole(role) => {
+                write!(f, "image_manifest has more than one {} image", role)
+            }
+            IssueKind::DuplicateVariantOptions => {
+                write!(f, "another variant of the parent has the same options")
+            }
+            IssueKind::MissingVariantOption(axis) => {
+                write!(f, "variant has no {:?} option, other variants of the parent have", axis)
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Issue {
+    pub index: usize, // position of the product in the config, stripe_id may be missing or duplicated
+    pub stripe_id: String,
+    pub name: String,
+    pub kind: IssueKind,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ValidationReport(pub Vec<Issue>);
+
+impl ValidationReport {
+    pub fn errors(&self) -> impl Iterator<Item = &Issue> {
+        self.0
+            .iter()
+            .filter(|issue| issue.kind.severity() == Severity::Error)
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &Issue> {
+        self.0
+            .iter()
+            .filter(|issue| issue.kind.severity() == Severity::Warning)
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.errors().next().is_some()
+    }
+
+    /// Whether the product at `index` of the config can be sold
+    pub fn is_servable(&self, index: usize) -> bool {
+        !self.errors().any(|issue| issue.index == index)
+    }
+}
+
+impl std::fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for issue in &self.0 {
+            let severity = match issue.kind.severity() {
+                Severity::Error => "error",
+                Severity::Warning => "warning",
+            };
+            writeln!(
+                f,
+                "{}: #{} {} ({}): {}",
+                severity, issue.index, issue.name, issue.stripe_id, issue.kind
+            )?;
+        }
+        writeln!(
+            f,
+            "{} errors, {} warnings.",
+            self.errors().count(),
+            self.warnings().count()
+        )
+    }
+}
+
+/// Validates products_config.json.
+/// With `site_root` the local_images are checked to exist in it, otherwise only the entries themselves are checked.
+/// Products deactivated in Stripe aren't sold, so only their ids are checked.
+pub fn validate_products_config(
+    products_config: &CfgProducts,
+    site_root: Option<&Path>,
+) -> ValidationReport {
+    let mut report = ValidationReport::default();
+    let mut stripe_ids: HashSet<&str> = HashSet::new();
+    let mut item_numbers: HashSet<i64> = HashSet::new();
+    let mut variant_options = HashSet::new();
+
+    for (index, product) in products_config.0.iter().enumerate() {
+        let mut issue = |kind: IssueKind| {
+            report.0.push(Issue {
+                index,
+                stripe_id: product.stripe_id.clone(),
+                name: product.name.clone(),
+                kind,
+            })
+        };
+
+        if product.stripe_id.trim().is_empty() {
+            issue(IssueKind::MissingStripeId);
+        } else if !stripe_ids.insert(&product.stripe_id) {
+            issue(IssueKind::DuplicateStripeId);
+        }
+        if !product.is_active() {
+            continue;
+        }
+
+        if product.name.trim().is_empty() {
+            issue(IssueKind::MissingName);
+        }
+        match product.item_number {
+            Some(item_number) => {
+                if !item_numbers.insert(item_number) {
+                    issue(IssueKind::DuplicateItemNumber(item_number));
+                }
+            }
+            None => issue(IssueKind::MissingItemNumber),
+        }
+        if product
+            .price
+            .as_ref()
+            .and_then(|price| price.unit_amount)
+            .is_none()
+        {
+            issue(IssueKind::MissingPrice);
+        }
+
+        match product
+            .metadata
+            .as_ref()
+            .and_then(|metadata| metadata.get("category"))
+        {
+            Some(category) if !KNOWN_CATEGORIES.contains(&category.as_str()) => {
+                issue(IssueKind::UnknownCategory(category.clone()))
+            }
+            Some(_) => {}
+            None => issue(IssueKind::MissingCategory),
+        }
+
+        if let Some(variant) = &product.variant {
+            if !variant_options.insert((&variant.parent, &variant.options)) {
+                issue(IssueKind::DuplicateVariantOptions);
+            }
+            let variants = products_config.variants_of(product);
+            for (axis, _) in variant_axes(&variants) {
+                if !variant.options.contains_key(&axis) {
+                    issue(IssueKind::MissingVariantOption(axis));
+                }
+            }
+        }
+
+        let local_images = product.local_images.as_deref().unwrap_or_default();
+        if local_images.is_empty() {
+            issue(IssueKind::MissingLocalImages);
+            continue;
+        }
+        if product
+            .local_image_with_role(ImageRole::Thumbnail)
+            .is_none()
+            && !local_images.iter().any(|path| {
+                path.file_name()
+                    .is_some_and(|name| name == "thumbnail.webp")
+            })
+        {
+            issue(IssueKind::MissingThumbnail);
+        }
+        if let Some(image_manifest) = &product.image_manifest {
+            for path in local_images {
+                if product.image_entry(path).is_none() {
+                    issue(IssueKind::UnreferencedImage(path.clone()));
+                }
+            }
+            for image in image_manifest {
+                if !local_images.iter().any(|path| {
+                    path.file_name()
+                        .is_some_and(|name| name == std::ffi::OsStr::new(&image.file))
+                }) {
+                    issue(IssueKind::MissingManifestImage(image.file.clone()));
+                }
+            }
+            for role in [ImageRole::Thumbnail, ImageRole::Hero] {
+                let count = image_manifest
+                    .iter()
+                    .filter(|image| image.role == Some(role))
+                    .count();
+                if count > 1 {
+                    issue(IssueKind::DuplicateImageRole(role));
+                }
+            }
+        }
+        if let Some(site_root) = site_root {
+            for path in local_images {
+                // local_images are absolute paths of the site, like /products_assets/1/ready_assets/thumbnail.webp
+                let file_path = site_root.join(path.strip_prefix("/").unwrap_or(path));
+                if !file_path.is_file() {
+                    issue(IssueKind::MissingImageFile(path.clone()));
+                }
+            }
+        }
+    }
+
+    report
+}
+
+/// The products of the config that can be sold, without the ones with errors
+pub fn servable_products(products_config: &CfgProducts) -> CfgProducts {
+    let report = validate_products_config(products_config, None);
+    CfgProducts(
+        products_config
+            .0
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| report.is_servable(*index))
+            .map(|(_, product)| product.clone())
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::product;
+    use super::super::{CfgProduct, CfgVariant};
+    use super::*;
+    use std::collections::{BTreeMap, HashMap};
+
+    fn kinds(report: &ValidationReport, index: usize) -> Vec<IssueKind> {
+        report
+            .0
+            .iter()
+            .filter(|issue| issue.index == index)
+            .map(|issue| issue.kind.clone())
+            .collect()
+    }
+
+    fn with_images(product: CfgProduct) -> CfgProduct {
+        CfgProduct {
+            local_images: Some(vec![PathBuf::from(format!(
+                "/products_assets/{}/ready_assets/thumbnail.webp",
+                product.item_number.unwrap_or_default()
+            ))]),
+            ..product
+        }
+    }
+
+    fn variant(parent: &str, options: &[(&str, &str)]) -> Option<CfgVariant> {
+        Some(CfgVariant {
+            parent: parent.to_string(),
+            options: options
+                .iter()
+                .map(|(axis, value)| (axis.to_string(), value.to_string()))
+                .collect::<BTreeMap<_, _>>(),
+        })
+    }
+
+    #[test]
+    fn complete_products_have_no_issues() {
+        let report = validate_products_config(
+            &CfgProducts(vec![with_images(product("prod_1", "Eggs", 900))]),
+            None,
+        );
+        assert!(report.0.is_empty(), "{}", report);
+    }
+
+    #[test]
+    fn duplicates_and_missing_prices_are_errors() {
+        let first = with_images(product("prod_1", "Eggs", 900));
+        let duplicate = first.clone();
+        let no_price = CfgProduct {
+            item_number: Some(2),
+            price: None,
+            ..with_images(product("prod_2", "Honey", 0))
+        };
+
+        let report = validate_products_config(&CfgProducts(vec![first, duplicate, no_price]), None);
+
+        assert!(report.is_servable(0));
+        assert_eq!(
+            kinds(&report, 1),
+            [
+                IssueKind::DuplicateStripeId,
+                IssueKind::DuplicateItemNumber(1)
+            ]
+        );
+        assert_eq!(kinds(&report, 2), [IssueKind::MissingPrice]);
+        assert!(!report.is_servable(1) && !report.is_servable(2));
+        assert_eq!(report.errors().count(), 3);
+    }
+
+    #[test]
+    fn deactivated_products_only_have_their_ids_checked() {
+        let active = with_images(product("prod_1", "Eggs", 900));
+        let deactivated = CfgProduct {
+            name: String::new(),
+            price: None,
+            metadata: None,
+            active: Some(false),
+            ..product("prod_1", "Eggs", 900)
+        };
+
+        let report = validate_products_config(&CfgProducts(vec![active, deactivated]), None);
+
+        assert_eq!(kinds(&report, 1), [IssueKind::DuplicateStripeId]);
+    }
+
+    #[test]
+    fn incomplete_products_get_warnings() {
+        let uncategorized = CfgProduct {
+            metadata: Some(HashMap::from([(
+                "category".to_string(),
+                "toys".to_string(),
+            )])),
+            ..product("prod_1", "Eggs", 900)
+        };
+
+        let report = validate_products_config(&CfgProducts(vec![uncategorized]), None);
+
+        assert!(!report.has_errors());
+        assert_eq!(
+            kinds(&report, 0),
+            [
+                IssueKind::UnknownCategory("toys".to_string()),
+                IssueKind::MissingLocalImages
+            ]
+        );
+    }
+
+    #[test]
+    fn variants_need_distinct_and_complete_options() {
+        let quails = |stripe_id: &str, item_number: i64, options: &[(&str, &str)]| CfgProduct {
+            item_number: Some(item_number),
+            variant: variant("Quails", options),
+            ..with_images(product(stripe_id, "Quails", 1500))
+        };
+        let products = CfgProducts(vec![
+            quails("prod_1", 1, &[("size", "Jumbo"), ("cut", "Whole")]),
+            quails("prod_2", 2, &[("size", "Jumbo"), ("cut", "Whole")]),
+            quails("prod_3", 3, &[("size", "Small")]),
+        ]);
+
+        let report = validate_products_config(&products, None);
+
+        assert!(kinds(&report, 0).is_empty());
+        assert_eq!(kinds(&report, 1), [IssueKind::DuplicateVariantOptions]);
+        assert_eq!(
+            kinds(&report, 2),
+            [IssueKind::MissingVariantOption("cut".to_string())]
+        );
+        assert_eq!(servable_products(&products).0.len(), 3);
+    }
+}
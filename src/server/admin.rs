@@ -0,0 +1,93 @@
+use crate::orders::{FulfilmentStatus, FulfilmentTransition, FulfilmentUpdate, Order};
+use crate::stripe_retypes::{DbCheckoutSession, DbCustomer};
+use crate::SyncStatus;
+use leptos::ServerFnError;
+use log::*;
+
+/// Sets the units on hand of a product, None stops tracking its stock
+#[leptos::server(name = SetProductStock, endpoint = "admin/set_product_stock")]
+pub async fn set_product_stock(
+    product_id: String,
+    stock: Option<i64>,
+) -> Result<(), ServerFnError> {
+    let admin = crate::auth::require_admin().await?;
+
+    crate::db::inventory::set_stock(&crate::db::use_db()?, &product_id, stock).await?;
+    info!(
+        "Admin {} set stock of {} to {:?}.",
+        admin.username, product_id, stock
+    );
+    Ok(())
+}
+
+/// Moves an order to another fulfilment status, recorded with the admin as actor
+#[leptos::server(name = UpdateOrderFulfilment, endpoint = "admin/update_order_fulfilment")]
+pub async fn update_order_fulfilment(
+    order_number: String,
+    to: FulfilmentStatus,
+    note: Option<String>,
+    carrier: Option<String>,
+    tracking_number: Option<String>,
+) -> Result<Order, ServerFnError> {
+    let admin = crate::auth::require_admin().await?;
+
+    let (order, _) = crate::db::orders::update_order_fulfilment(
+        &crate::db::use_db()?,
+        &order_number,
+        FulfilmentUpdate {
+            to,
+            actor: admin.username,
+            note,
+            carrier,
+            tracking_number,
+        },
+    )
+    .await?;
+    Ok(order)
+}
+
+/// All orders, newest first
+#[leptos::server(name = AdminOrders, endpoint = "admin/orders")]
+pub async fn admin_orders() -> Result<Vec<Order>, ServerFnError> {
+    crate::auth::require_admin().await?;
+
+    Ok(crate::db::orders::get_orders(&crate::db::use_db()?).await?)
+}
+
+#[leptos::server(name = AdminOrderTransitions, endpoint = "admin/order_transitions")]
+pub async fn admin_order_transitions(
+    order_number: String,
+) -> Result<Vec<FulfilmentTransition>, ServerFnError> {
+    crate::auth::require_admin().await?;
+
+    Ok(crate::db::orders::get_order_transitions(&crate::db::use_db()?, &order_number).await?)
+}
+
+#[leptos::server(name = AdminCustomers, endpoint = "admin/customers")]
+pub async fn admin_customers() -> Result<Vec<DbCustomer>, ServerFnError> {
+    crate::auth::require_admin().await?;
+
+    Ok(crate::use_stripe_data()?.customers)
+}
+
+/// Checkout sessions, newest first
+#[leptos::server(name = AdminCheckoutSessions, endpoint = "admin/checkout_sessions")]
+pub async fn admin_checkout_sessions() -> Result<Vec<DbCheckoutSession>, ServerFnError> {
+    crate::auth::require_admin().await?;
+
+    let mut checkout_sessions = crate::use_stripe_data()?.checkout_sessions;
+    checkout_sessions.sort_by_key(|checkout_session| std::cmp::Reverse(checkout_session.created));
+    Ok(checkout_sessions)
+}
+
+/// Whether Stripe is reachable and how the latest syncs went
+#[leptos::server(name = AdminSyncStatus, endpoint = "admin/sync_status")]
+pub async fn admin_sync_status() -> Result<SyncStatus, ServerFnError> {
+    crate::auth::require_admin().await?;
+
+    let appstate = crate::use_shared_app_state()?.snapshot();
+    Ok(SyncStatus {
+        stripe_status: appstate.stripe_status,
+        sync_reports: appstate.sync_reports,
+    })
+}
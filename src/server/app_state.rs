@@ -1,4 +1,4 @@
-use crate::{AppState, StripeData};
+use crate::{AppState, Catalog, StripeData};
 
 #[leptos::server(
     name = GetStripeKey
@@ -7,69 +7,58 @@ pub async fn get_stripe_key() -> Result<String, leptos::ServerFnError> {
     unimplemented!();
 }
 
+/// Gets the SharedAppState provided to the current request by main.rs
+#[cfg(feature = "ssr")]
+pub fn use_shared_app_state() -> Result<crate::SharedAppState, leptos::ServerFnError> {
+    match leptos::use_context::<crate::SharedAppState>() {
+        Some(ok) => Ok(ok),
+        None => {
+            // leptos::logging::log!("No context SharedAppState");
+            Err(leptos::ServerFnError::ServerError(
+                "SharedAppState not found in context".into(),
+            ))
+        }
+    }
+}
+
+/// Gets the StripeData of the current AppState, for server functions that work with customers or checkout sessions
+#[cfg(feature = "ssr")]
+pub fn use_stripe_data() -> Result<StripeData, leptos::ServerFnError> {
+    match use_shared_app_state()?.snapshot().stripe_data {
+        Some(ok) => Ok(ok),
+        None => Err(leptos::ServerFnError::ServerError(
+            "StripeData not found".into(),
+        )),
+    }
+}
+
+/// Products, prices, categories and images for the storefront, without any customer or checkout session data
+#[leptos::server(
+      name = FetchCatalog,
+)]
+pub async fn fetch_catalog() -> Result<Catalog, leptos::ServerFnError> {
+    // Stock changes with every checkout, so it's read fresh instead of kept in SharedAppState
+    let available_stock = crate::db::inventory::get_available_stock(&crate::db::use_db()?).await?;
+
+    Ok(use_shared_app_state()?.snapshot().catalog(available_stock))
+}
+
+/// All of StripeData, including customers and checkout sessions
 #[leptos::server(
       name = StripeStater,
 )]
 pub async fn stripe_stater() -> Result<StripeData, leptos::ServerFnError> {
-    let state = match leptos::use_context::<Option<crate::AppState>>() {
-        Some(ok) => {
-            // leptos::logging::log!("GOT context AppState");
-            ok
-        }
-        None => {
-            // leptos::logging::log!("No context AppState");
-            None
-        }
-    };
-    let axum::extract::State(appstate): axum::extract::State<crate::AppState> =
-        leptos_axum::extract_with_state(match &state {
-            Some(x) => x,
-            None => &AppState {
-                stripe_data: None,
-                products_config: None,
-            },
-        })
-        .await?;
+    crate::auth::require_admin().await?;
 
-    // log::info!("Server data: {:#?}", appstate.stripe_data.clone());
-    match appstate.stripe_data {
-        Some(ok) => {
-            // info!("StripeData Loaded...");
-            Ok(ok)
-        }
-        None => {
-            // error!("No StripeData!");
-            return Err(leptos::ServerFnError::ServerError(
-                "StripeData not found".into(),
-            ));
-        }
-    }
+    use_stripe_data()
 }
 
+/// All of AppState, including customers and checkout sessions
 #[leptos::server(
       name = AppStateStater,
 )]
 pub async fn appstate_stater() -> Result<AppState, leptos::ServerFnError> {
-    let state = match leptos::use_context::<Option<crate::AppState>>() {
-        Some(ok) => {
-            // leptos::logging::log!("GOT context AppState");
-            ok
-        }
-        None => {
-            // leptos::logging::log!("No context AppState");
-            None
-        }
-    };
-
-    let axum::extract::State(appstate): axum::extract::State<crate::AppState> =
-        leptos_axum::extract_with_state(match &state {
-            Some(x) => x,
-            None => &AppState {
-                stripe_data: None,
-                products_config: None,
-            },
-        })
-        .await?;
+    crate::auth::require_admin().await?;
 
-    Ok(appstate)
+    Ok(use_shared_app_state()?.snapshot())
 }
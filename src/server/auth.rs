@@ -0,0 +1,41 @@
+use leptos::ServerFnError;
+
+/// Logs in as admin, the session is kept in an HttpOnly cookie.
+/// Returns false on wrong username or password.
+#[leptos::server(name = AdminLogin, endpoint = "admin_login")]
+pub async fn admin_login(username: String, password: String) -> Result<bool, ServerFnError> {
+    use axum::http::{header::SET_COOKIE, HeaderValue};
+
+    let pool = crate::db::use_db()?;
+    match crate::auth::login(&pool, &username, &password).await? {
+        Some((_, cookie)) => {
+            leptos::expect_context::<leptos_axum::ResponseOptions>()
+                .append_header(SET_COOKIE, HeaderValue::from_str(&cookie)?);
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+#[leptos::server(name = AdminLogout, endpoint = "admin_logout")]
+pub async fn admin_logout() -> Result<(), ServerFnError> {
+    use axum::http::{header::SET_COOKIE, HeaderValue};
+    use axum_extra::extract::CookieJar;
+
+    let jar = leptos_axum::extract::<CookieJar>().await?;
+    if let Some(token) = crate::auth::session_token(&jar) {
+        let cookie = crate::auth::logout(&crate::db::use_db()?, &token).await?;
+        leptos::expect_context::<leptos_axum::ResponseOptions>()
+            .append_header(SET_COOKIE, HeaderValue::from_str(&cookie)?);
+    }
+    Ok(())
+}
+
+/// Username of the logged in admin, None when not logged in
+#[leptos::server(name = AdminWhoami, endpoint = "admin_whoami")]
+pub async fn admin_whoami() -> Result<Option<String>, ServerFnError> {
+    Ok(leptos_axum::extract::<crate::auth::AdminSession>()
+        .await
+        .ok()
+        .map(|admin| admin.username))
+}
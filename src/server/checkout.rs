@@ -1,5 +1,5 @@
 use crate::stripe_retypes::{DbCheckoutSession, DbCheckoutSessionStatus};
-use crate::{stripe_stater, stripe_sync, ShoppingCart, StripeData};
+use crate::{ShoppingCart, StripeData};
 use leptos::*;
 use leptos::ServerFnError;
 use log::*;
@@ -10,16 +10,7 @@ use std::collections::HashMap;
 pub async fn find_checkout_session_matches(
     checkout_sessionid: String,
 ) -> Result<bool, ServerFnError> {
-    use stripe::*;
-    let client = Client::new(match std::env::var("STRIPE_KEY") {
-        Ok(ok) => ok,
-        Err(err) => {
-            log::error!("{:#?}", err);
-            return Err(ServerFnError::ServerError(err.to_string()));
-        }
-    });
-
-    let stripe_data: StripeData = stripe_stater().await?;
+    let stripe_data: StripeData = crate::use_stripe_data()?;
 
     Ok(stripe_data.checkout_sessions.iter().any(|session| {
         session.id == checkout_sessionid
@@ -30,6 +21,11 @@ pub async fn find_checkout_session_matches(
     }))
 }
 
+/// Checkout sessions expire after this many seconds, which frees the stock they reserved.
+/// Stripe wants expires_at at least 30 minutes after it makes the session by its own clock,
+/// the extra minutes cover the request and clock skew.
+pub const CHECKOUT_SESSION_LIFETIME: i64 = 35 * 60;
+
 /// Creates new checkout session via stripe API using shopping cart items from client
 #[leptos::server(name = NewCheckoutSession)]
 pub async fn new_checkout_session(
@@ -40,15 +36,20 @@ pub async fn new_checkout_session(
     cart.0 = shopping_cart;
     let shopping_cart = cart;
 
-    use stripe::*;
-    let client = Client::new(match std::env::var("STRIPE_KEY") {
-        Ok(ok) => ok,
-        Err(err) => {
-            log::error!("{:#?}", err);
-            return Err(ServerFnError::ServerError(err.to_string()));
-        }
-    });
-    let stripe_data: StripeData = stripe_stater().await?;
+    if !crate::use_shared_app_state()?
+        .snapshot()
+        .is_checkout_available()
+    {
+        error!("Checkout is unavailable while Stripe can't be reached.");
+        return Err(leptos::ServerFnError::ServerError(
+            "Checkout is temporarily unavailable".into(),
+        ));
+    }
+
+    use crate::payments::{CheckoutLineItem, CheckoutSessionParams};
+
+    let payment_backend = crate::payments::payment_backend()?;
+    let stripe_data: StripeData = crate::use_stripe_data()?;
 
     let base_url = match std::env::var("DEVPORT") {
         Ok(port) => "http://localhost:4444",
@@ -56,118 +57,22 @@ pub async fn new_checkout_session(
     };
 
     let cancel_url = format!("{:#}/shop/cart", base_url);
-    let success_url = format!("{:#}/success", base_url);
-
-    let mut params = stripe::CreateCheckoutSession::new();
-    params.cancel_url = Some(&cancel_url);
-    params.success_url = Some(&success_url);
-    params.customer = None;
-    params.customer_creation = Some(stripe::CheckoutSessionCustomerCreation::IfRequired);
-    params.shipping_address_collection =
-        Some(stripe::CreateCheckoutSessionShippingAddressCollection {
-            allowed_countries: vec![
-                stripe::CreateCheckoutSessionShippingAddressCollectionAllowedCountries::Au,
-            ],
-        });
+    // Stripe fills in the session id, so the success page can show the order
+    let success_url = format!("{:#}/success?session_id={{CHECKOUT_SESSION_ID}}", base_url);
 
     let total_price: i64 = shopping_cart.calculate_total_price(&stripe_data.products);
 
     let is_cart_under: bool = total_price < 30000;
 
-    params.shipping_options = if is_cart_under {
-        Some(vec![CreateCheckoutSessionShippingOptions {
-            /// The ID of the Shipping Rate to use for this shipping option.
-            shipping_rate: Some(stripe_data.default_shipping_rate_id),
-
-            /// Parameters to be passed to Shipping Rate creation for this shipping option.
-            shipping_rate_data: None,
-        }])
+    let shipping_rate_id = if is_cart_under {
+        stripe_data.default_shipping_rate_id
     } else {
-        Some(vec![CreateCheckoutSessionShippingOptions {
-            /// The ID of the Shipping Rate to use for this shipping option.
-            shipping_rate: Some(stripe_data.free_shipping_rate_id),
-
-            /// Parameters to be passed to Shipping Rate creation for this shipping option.
-            shipping_rate_data: None,
-        }])
+        stripe_data.free_shipping_rate_id
     };
-    params.consent_collection = Some(CreateCheckoutSessionConsentCollection {
-        payment_method_reuse_agreement: Some(CreateCheckoutSessionConsentCollectionPaymentMethodReuseAgreement {
-            position: CreateCheckoutSessionConsentCollectionPaymentMethodReuseAgreementPosition::Hidden,
-        }),
-        ..Default::default()
-    });
 
-    // Collect additional information from your customer using custom fields.
-    //
-    // Up to 3 fields are supported.
-    // params.custom_fields = Some(vec![CreateCheckoutSessionCustomFields {
-    //     // Configuration for `type=dropdown` fields.
-    //     dropdown: Some(CreateCheckoutSessionCustomFieldsDropdown {
-    //         options: vec![CreateCheckoutSessionCustomFieldsDropdownOptions {
-    //             // The label for the option, displayed to the customer.
-    //             //
-    //             // Up to 100 characters.
-    //             label: String::from("I understand that I live within the delivery route "),
-
-    //             // The value for this option, not displayed to the customer,
-    //             // used by your integration to reconcile the option selected by the customer.
-    //             //
-    //             // Must be unique to this option, alphanumeric, and up to 100 characters.
-    //             value: String::from("deliverycollection"),
-    //         }],
-    //     }),
-    //     // dropdown: None,
-
-    //     // The label for the field, displayed to the customer.
-    //     label: CreateCheckoutSessionCustomFieldsLabel {
-    //         custom: String::from("How to collect your order?"),
-    //         type_: CreateCheckoutSessionCustomFieldsLabelType::Custom,
-    //     },
-
-    //     // Configuration for `type=numeric` fields.
-    //     // numeric: Some(CreateCheckoutSessionCustomFieldsNumeric {
-    //     //     ..Default::default()
-    //     // }),
-    //     numeric: None,
-
-    //     // Whether the customer is required to complete the field before completing the Checkout Session.
-    //     //
-    //     // Defaults to `false`.
-    //     optional: Some(false),
-
-    //     // Configuration for `type=text` fields.
-    //     // text: Some(CreateCheckoutSessionCustomFieldsText {}),
-    //     text: None,
-
-    //     // The type of the field.
-    //     type_: CreateCheckoutSessionCustomFieldsType::Dropdown,
-
-    //     // String of your choice that your integration can use to reconcile this field.
-    //     //
-    //     // Must be unique to this field, alphanumeric, and up to 200 characters.
-    //     key: String::from("deliveryconsent"),
-    //     // ..Default::default()
-    // }]);
-
-    params.custom_text = Some(CreateCheckoutSessionCustomText {
-        shipping_address: Some(CreateCheckoutSessionCustomTextShippingAddress {
-            message: "We make deliveries only within Tasmania Derwent Valley or Hobart area."
-                .to_string(),
-        }),
-        after_submit: Some(CreateCheckoutSessionCustomTextAfterSubmit {
-            message: "We make deliveries only within Tasmania Derwent Valley or Hobart area."
-                .to_string(),
-        }),
-        ..Default::default()
-    });
-    params.phone_number_collection =
-        Some(stripe::CreateCheckoutSessionPhoneNumberCollection { enabled: true });
-    params.ui_mode = Some(stripe::CheckoutSessionUiMode::Hosted);
-    params.mode = Some(stripe::CheckoutSessionMode::Payment);
-    params.billing_address_collection =
-        Some(stripe::CheckoutSessionBillingAddressCollection::Required);
-    params.currency = Some(stripe::Currency::AUD);
+    let db = crate::db::use_db()?;
+    // Only the reserved quantity of tracked products can be paid for, so their quantity is fixed at checkout
+    let tracked_stock = crate::db::inventory::get_available_stock(&db).await?;
 
     let mut line_items_vec = Vec::new();
 
@@ -179,29 +84,92 @@ pub async fn new_checkout_session(
     }
 
     for (product_id, quantity) in &shopping_cart.0 {
-        if let Some(product) = stripe_data.products.iter().find(|p| p.id == *product_id) {
-            let line_item = CreateCheckoutSessionLineItems {
-                adjustable_quantity: Some(CreateCheckoutSessionLineItemsAdjustableQuantity {
-                    enabled: true,
-                    maximum: Some(20),
-                    minimum: Some(1),
-                }),
-                quantity: Some((*quantity).into()),
-                price: Some(product.default_price.clone().expect("NO PRICE!").id),
-                ..Default::default()
-            };
-            line_items_vec.push(line_item);
-        } else {
-            error!("NO products in StripeData. Couldn't create line_items");
-            return Err(leptos::ServerFnError::ServerError(
-                "NO products in StripeData. Couldn't create line_items".into(),
-            ));
+        match stripe_data
+            .products
+            .iter()
+            .find(|p| p.id == *product_id)
+            .and_then(|product| product.default_price.as_ref())
+        {
+            Some(price) => line_items_vec.push(CheckoutLineItem {
+                price_id: price.id.clone(),
+                quantity: (*quantity).into(),
+                adjustable: !tracked_stock.contains_key(product_id),
+            }),
+            None => {
+                error!("NO products in StripeData. Couldn't create line_items");
+                return Err(leptos::ServerFnError::ServerError(
+                    "NO products in StripeData. Couldn't create line_items".into(),
+                ));
+            }
         }
     }
-    params.line_items = Some(line_items_vec);
-    params.expand = &["line_items", "line_items.data.price.product"];
 
-    let new_session = stripe::CheckoutSession::create(&client, params).await?;
+    // The chosen options of variants in the cart, so the session says which quails were ordered.
+    // Keys are "variant_<product id>", Stripe allows 50 metadata keys per session.
+    let variants_metadata: std::collections::HashMap<String, String> = shopping_cart
+        .0
+        .keys()
+        .filter_map(|product_id| {
+            let product = stripe_data.products.iter().find(|p| p.id == *product_id)?;
+            let variant = product
+                .metadata
+                .as_ref()
+                .and_then(crate::products_config::CfgVariant::from_metadata)?;
+            Some((
+                format!("variant_{}", product_id),
+                format!("{} ({})", variant.parent, variant.label()),
+            ))
+        })
+        .take(50)
+        .collect();
+
+    // Hold the stock before the session exists, so two customers can't pay for the same last unit
+    let reservation_key = crate::db::inventory::new_reservation_key();
+    let reserved_items: Vec<(String, u64)> = shopping_cart
+        .0
+        .iter()
+        .map(|(product_id, quantity)| (product_id.clone(), (*quantity).into()))
+        .collect();
+    if let Err(err) =
+        crate::db::inventory::reserve_stock(&db, &reservation_key, &reserved_items).await
+    {
+        error!("Couldn't reserve stock for checkout: {}", err);
+        return Err(leptos::ServerFnError::ServerError(match err {
+            crate::db::inventory::InventoryError::OutOfStock { product_id, .. } => {
+                let product_name = stripe_data
+                    .products
+                    .iter()
+                    .find(|p| p.id == product_id)
+                    .map_or(product_id, |p| p.name.clone());
+                format!("Not enough {} in stock", product_name)
+            }
+            crate::db::inventory::InventoryError::Db(_) => "Couldn't reserve stock".into(),
+        }));
+    }
+
+    let expires_at = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs() as i64)
+        + CHECKOUT_SESSION_LIFETIME;
+
+    let new_session = match payment_backend
+        .create_checkout_session(CheckoutSessionParams {
+            line_items: line_items_vec,
+            shipping_rate_id,
+            success_url,
+            cancel_url,
+            metadata: (!variants_metadata.is_empty()).then_some(variants_metadata),
+            expires_at: Some(expires_at),
+        })
+        .await
+    {
+        Ok(ok) => ok,
+        Err(err) => {
+            crate::db::inventory::release_reservation(&db, &reservation_key).await?;
+            return Err(err);
+        }
+    };
+    crate::db::inventory::attach_checkout_session(&db, &reservation_key, &new_session.id).await?;
 
     info!(
         "Created NEW checkout session: {:#?}, for {:#?} $AUD. (Created: {:#?} / Expires at: {:#?} )",
@@ -211,14 +179,12 @@ pub async fn new_checkout_session(
         &new_session.expires_at
     );
 
-    stripe_sync();
-
     leptos_axum::redirect(match &new_session.url.clone() {
         Some(url) => url,
         None => "/cancel",
     });
 
-    Ok(new_session.into())
+    Ok(new_session)
 }
 
 #[server (
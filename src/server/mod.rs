@@ -1,9 +1,15 @@
+mod admin;
 mod app_state;
+mod auth;
 mod checkout;
+mod orders;
 mod products;
 mod stripe;
 
+pub use admin::*;
 pub use app_state::*;
+pub use auth::*;
 pub use checkout::*;
+pub use orders::*;
 pub use products::*;
 pub use stripe::*;
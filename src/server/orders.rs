@@ -0,0 +1,49 @@
+use crate::orders::{Order, OrderSummary};
+use leptos::ServerFnError;
+use log::*;
+
+/// Gets the summary of the order made from a checkout session, for the page Stripe redirects to after paying.
+/// If the webhook hasn't arrived yet the session is fetched from the payment backend, so the order is made right away.
+/// Customer details stay out of it, they are only served by the admin endpoints.
+#[leptos::server(name = CheckoutOrder)]
+pub async fn checkout_order(
+    checkout_session_id: String,
+) -> Result<Option<OrderSummary>, ServerFnError> {
+    use crate::db;
+
+    let pool = db::use_db()?;
+
+    if let Some(order) =
+        db::orders::get_order_by_checkout_session(&pool, &checkout_session_id).await?
+    {
+        return Ok(Some(OrderSummary::from(&order)));
+    }
+    // Only ids shaped like checkout sessions are looked up in Stripe
+    if !checkout_session_id.starts_with("cs_") {
+        return Ok(None);
+    }
+
+    let checkout_session = crate::payments::payment_backend()?
+        .retrieve_checkout_session(&checkout_session_id)
+        .await?;
+
+    if !Order::is_order_ready(&checkout_session) {
+        info!(
+            "Checkout session {} isn't paid yet ({:?}, {:?}).",
+            checkout_session.id, checkout_session.status, checkout_session.payment_status
+        );
+        return Ok(None);
+    }
+
+    db::stripe::upsert_checkout_session(&pool, &checkout_session).await?;
+    crate::use_shared_app_state()?.update_stripe_data(|stripe_data| {
+        stripe_data.upsert_checkout_session(checkout_session.clone())
+    });
+
+    Ok(
+        db::orders::get_order_by_checkout_session(&pool, &checkout_session_id)
+            .await?
+            .as_ref()
+            .map(OrderSummary::from),
+    )
+}
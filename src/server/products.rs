@@ -2,97 +2,522 @@ use crate::{CfgProduct, CfgProducts, StripeData};
 use leptos::ServerFnError;
 use log::*;
 
+/// Admin endpoint of `refresh_products_config`
 #[leptos::server(
     name = RefreshLocalProductInfo,
-    endpoint = "refresh_local_products_info", // WORKING BUT TODO IMPLEMENT AUTHENTIFICATION
+    endpoint = "refresh_local_products_info",
 )]
 pub async fn refresh_local_product_info(rewrite: bool) -> Result<String, leptos::ServerFnError> {
-    use std::fs::File;
-    use std::io::Read;
-    use std::io::Write;
+    let admin = crate::auth::require_admin().await?;
+    info!("Admin {} is refreshing local CfgProducts.", admin.username);
+
+    let shared_appstate = crate::use_shared_app_state()?;
+    let started_at = crate::db::unix_now();
+    let result = refresh_products_config(&crate::db::use_db()?, &admin.username, rewrite).await;
+    shared_appstate.record_sync(crate::SyncReport::new(
+        crate::SyncKind::ProductsRefresh,
+        &admin.username,
+        started_at,
+        result
+            .as_ref()
+            .map(|diff| format!("{}, rewrite: {}", diff.summary(), rewrite)),
+    ));
+    result.map(|diff| diff.to_string())
+}
+
+/// Admin endpoint of `preview_products_config`
+#[leptos::server(
+    name = PreviewLocalProductInfo,
+    endpoint = "preview_local_products_info",
+)]
+pub async fn preview_local_product_info(
+    rewrite: bool,
+) -> Result<crate::products_config::ProductsConfigDiff, leptos::ServerFnError> {
+    let admin = crate::auth::require_admin().await?;
+    info!(
+        "Admin {} is previewing a refresh of local CfgProducts.",
+        admin.username
+    );
+
+    let (_, _, diff) = preview_products_config(&crate::db::use_db()?, rewrite).await?;
+    Ok(diff)
+}
+
+/// Works out what `refresh_products_config` would write without touching disk or the database.
+/// Returns the refreshed config, the products in Stripe it was merged with and what changes.
+#[cfg(feature = "ssr")]
+pub async fn preview_products_config(
+    db: &crate::db::DbPool,
+    rewrite: bool,
+) -> Result<
+    (
+        CfgProducts,
+        CfgProducts,
+        crate::products_config::ProductsConfigDiff,
+    ),
+    leptos::ServerFnError,
+> {
+    use crate::products_config::{merge_products_config, rewrite_products_config};
     use std::path::Path;
-    use stripe::*;
 
+    // Retrieve the LEPTOS_SITE_ROOT environment variable for path of the data file
+    let site_root = std::env::var("LEPTOS_SITE_ROOT").unwrap_or_else(|_| "site".to_string());
+    let products_config_file_path = Path::new(&site_root).join("products_config.json");
+
+    let stripe_data = StripeData::new_fetch().await?;
+    let stripe_products_config: CfgProducts = StripeData::derive_products_config(stripe_data);
+
+    let (merged_products_config, mut diff) = if products_config_file_path.exists() && !rewrite {
+        let local_products_config: CfgProducts = read_products_config().await?;
+        let base_products_config = crate::db::products_config::get_products_config_base(db).await?;
+        merge_products_config(
+            &local_products_config,
+            &base_products_config,
+            &stripe_products_config,
+        )
+    } else {
+        // read_products_config would write a missing file, so it's only read when it exists
+        let local_products_config = match products_config_file_path.exists() {
+            true => read_products_config().await?,
+            false => CfgProducts(Vec::new()),
+        };
+        rewrite_products_config(&local_products_config, &stripe_products_config)
+    };
+
+    // MAKE SCAN OF ASSET IMAGES IN DIR
+    // Attach them to the local_images in CfgProduct
+    let (updated_products_config, images) =
+        add_images_to_products_config(merged_products_config).await?;
+    diff.images = images;
+
+    Ok((updated_products_config, stripe_products_config, diff))
+}
+
+/// Refreshes products_config.json with the products in Stripe and the images in assets.
+/// `rewrite` starts over from Stripe, otherwise Stripe's changes are merged into the local config
+/// (see `merge_products_config`). The result is saved as a new revision, unless nothing changed. Returns what changed.
+#[cfg(feature = "ssr")]
+pub async fn refresh_products_config(
+    db: &crate::db::DbPool,
+    actor: &str,
+    rewrite: bool,
+) -> Result<crate::products_config::ProductsConfigDiff, leptos::ServerFnError> {
     tracing::info!("");
     tracing::info!("Refreshing Local CfgProducts...");
 
-    // Retrieve the LEPTOS_SITE_ROOT environment variable for path of the data file
-    let site_root = std::env::var("LEPTOS_SITE_ROOT").unwrap_or_else(|_| "site".to_string());
-    let assets_dir = std::env::var("LEPTOS_ASSETS_DIR").unwrap_or_else(|_| "public".to_string());
+    // Convert new source images first, so the refreshed config lists them
+    let images_report = process_product_images().await?;
+    info!("Processed product images: {}", images_report);
+
+    let (updated_products_config, stripe_products_config, diff) =
+        preview_products_config(db, rewrite).await?;
+
+    // Refreshes without changes, like the one at every startup, don't add a revision
+    if diff.is_empty()
+        && is_products_config_file_unchanged(&updated_products_config)
+        && crate::db::products_config::get_latest_revision(db)
+            .await?
+            .is_some()
+    {
+        info!("products_config.json is up to date, no new revision saved.");
+        if let Some(shared_appstate) = leptos::use_context::<crate::SharedAppState>() {
+            shared_appstate.set_products_config(updated_products_config);
+        }
+    } else {
+        let note = match rewrite {
+            true => format!("rewrite from Stripe: {}", diff.summary()),
+            false => format!("refresh from Stripe: {}", diff.summary()),
+        };
+        save_products_config(db, updated_products_config, actor, &note).await?;
+    }
+    crate::db::products_config::set_products_config_base(db, &stripe_products_config).await?;
 
-    // Config file paths
-    let products_config_file_path = Path::new(&site_root).join("products_config.json");
-    let products_config_public_file_path = Path::new(&assets_dir).join("products_config.json");
+    info!("Refreshed products_config.json:\n{}", diff);
+    Ok(diff)
+}
+
+/// Admin endpoint of `preview_push_products_config`
+#[leptos::server(
+    name = PreviewPushProducts,
+    endpoint = "preview_push_products",
+)]
+pub async fn preview_push_products(
+) -> Result<crate::products_config::push::PushPlan, leptos::ServerFnError> {
+    let admin = crate::auth::require_admin().await?;
+    info!(
+        "Admin {} is previewing a push of local CfgProducts to Stripe.",
+        admin.username
+    );
+
+    preview_push_products_config().await
+}
+
+/// Admin endpoint of `push_products_config`
+#[leptos::server(
+    name = PushProducts,
+    endpoint = "push_products",
+)]
+pub async fn push_products(
+    plan: crate::products_config::push::PushPlan,
+) -> Result<String, leptos::ServerFnError> {
+    let admin = crate::auth::require_admin().await?;
+    info!("Admin {} is pushing local CfgProducts to Stripe.", admin.username);
+
+    let shared_appstate = crate::use_shared_app_state()?;
+    let started_at = crate::db::unix_now();
+    let result = push_products_config(&crate::db::use_db()?, &admin.username, &plan).await;
+    shared_appstate.record_sync(crate::SyncReport::new(
+        crate::SyncKind::ProductsPush,
+        &admin.username,
+        started_at,
+        result.as_ref().map(|report| {
+            format!(
+                "{} pushed, {} new prices, {} failed, {} warnings",
+                report.pushed,
+                report.new_prices.len(),
+                report.failed.len(),
+                report.warnings.len()
+            )
+        }),
+    ));
+    result.map(|report| report.to_string())
+}
 
-    if products_config_file_path.exists() {
-        if rewrite {
-            // If the file exists delete it, then recreate new one by serializing StripeData::new_fetch()
-            let stripe_data = StripeData::new_fetch().await?;
-            let stripe_products_config = StripeData::derive_products_config(stripe_data);
-
-            // MAKE SCAN OF ASSET IMAGES IN DIR
-            // Attach them to the local_images in CfgProduct
-            let updated_stripe_products_config: CfgProducts =
-                add_images_to_products_config(stripe_products_config.clone()).await?;
-
-            write_products_config(updated_stripe_products_config, true).await
-        } else {
-            // Refresh file with new products from stripe api
-            let stripe_data = StripeData::new_fetch().await?;
-            let stripe_products_config: CfgProducts =
-                StripeData::derive_products_config(stripe_data);
-
-            let local_products_config: CfgProducts = fetch_local_product_info().await?;
-
-            let mut h: Vec<CfgProduct> = Vec::new();
-            // Add all local products to the vector
-            for p in &local_products_config.0 {
-                h.push(p.clone()); // Push existing products
+/// Compares each local product with its product in Stripe.
+/// Returns the plan and the products in Stripe by stripe_id.
+#[cfg(feature = "ssr")]
+async fn plan_push(
+    backend: &dyn crate::payments::PaymentBackend,
+    products_config: &CfgProducts,
+) -> (
+    crate::products_config::push::PushPlan,
+    std::collections::HashMap<String, CfgProduct>,
+) {
+    use crate::products_config::push::{plan_product_push, PushFailure, PushPlan};
+    use crate::products_config::ProductChange;
+
+    let mut plan = PushPlan::default();
+    let mut stripe_products = std::collections::HashMap::new();
+    for product in &products_config.0 {
+        // Archived products can still be retrieved, only deleted ones are skipped
+        let stripe_product: CfgProduct = match backend.retrieve_product(&product.stripe_id).await {
+            Ok(stripe_product) => stripe_product.into(),
+            Err(err) => {
+                plan.skipped.push(PushFailure {
+                    stripe_id: product.stripe_id.clone(),
+                    name: product.name.clone(),
+                    error: err.to_string(),
+                });
+                continue;
             }
+        };
+
+        let fields = plan_product_push(product, &stripe_product);
+        if !fields.is_empty() {
+            plan.changes.push(ProductChange::Updated {
+                stripe_id: product.stripe_id.clone(),
+                name: product.name.clone(),
+                fields,
+            });
+        }
+        stripe_products.insert(product.stripe_id.clone(), stripe_product);
+    }
+    (plan, stripe_products)
+}
+
+/// Works out what `push_products_config` would change in Stripe without changing anything
+#[cfg(feature = "ssr")]
+pub async fn preview_push_products_config(
+) -> Result<crate::products_config::push::PushPlan, leptos::ServerFnError> {
+    let backend = crate::payments::payment_backend()?;
+    let products_config = read_products_config().await?;
+    let (plan, _) = plan_push(backend.as_ref(), &products_config).await;
+    Ok(plan)
+}
+
+/// Pushes the local edits of products_config.json to Stripe, see products_config/push.rs.
+/// Only the changes of `confirmed`, the plan of `preview_push_products_config` the operator agreed to,
+/// are pushed. Products whose changes differ from it by now fail, the push has to be previewed again.
+/// Products that were pushed get their new price id locally and in the merge base, so the next
+/// refresh doesn't see the push as changes made in Stripe. The result is saved as a new revision.
+#[cfg(feature = "ssr")]
+pub async fn push_products_config(
+    db: &crate::db::DbPool,
+    actor: &str,
+    confirmed: &crate::products_config::push::PushPlan,
+) -> Result<crate::products_config::push::PushReport, leptos::ServerFnError> {
+    use crate::products_config::push::{PushFailure, PushReport};
+    use crate::products_config::ProductChange;
 
-            // Add missing Stripe products to the vector
-            for s in &stripe_products_config.0 {
-                if !local_products_config
+    tracing::info!("");
+    tracing::info!("Pushing Local CfgProducts to Stripe...");
+
+    let backend = crate::payments::payment_backend()?;
+    let mut products_config = read_products_config().await?;
+    let mut base_products_config = crate::db::products_config::get_products_config_base(db).await?;
+    let (current_plan, stripe_products) = plan_push(backend.as_ref(), &products_config).await;
+
+    let mut report = PushReport {
+        plan: confirmed.clone(),
+        ..Default::default()
+    };
+    for change in confirmed.changes.iter().cloned() {
+        let ProductChange::Updated {
+            stripe_id,
+            name,
+            fields,
+        } = change
+        else {
+            continue;
+        };
+        let (Some(product), Some(stripe_product)) = (
+            products_config
+                .0
+                .iter_mut()
+                .find(|product| product.stripe_id == stripe_id),
+            stripe_products.get(&stripe_id),
+        ) else {
+            continue;
+        };
+
+        if current_plan.fields_of(&stripe_id) != Some(fields.as_slice()) {
+            report.failed.push(PushFailure {
+                stripe_id,
+                name,
+                error: "changed locally or in Stripe since the preview, preview the push again"
+                    .to_string(),
+            });
+            continue;
+        }
+
+        let fields: Vec<&str> = fields.iter().map(|field| field.field.as_str()).collect();
+        match push_product(
+            backend.as_ref(),
+            product,
+            stripe_product,
+            &fields,
+            &mut report.warnings,
+        )
+        .await
+        {
+            Ok((pushed_product, new_price)) => {
+                report.pushed += 1;
+                report.new_prices.extend(new_price);
+                // Stripe has the local values now, the price id and metadata are taken from it
+                product.price = pushed_product.price.clone();
+                product.metadata = pushed_product.metadata.clone();
+                match base_products_config
                     .0
-                    .iter()
-                    .any(|p| p.stripe_id == s.stripe_id)
+                    .iter_mut()
+                    .find(|base_product| base_product.stripe_id == stripe_id)
                 {
-                    h.push(s.clone()); // Push only missing Stripe products
+                    Some(base_product) => *base_product = pushed_product,
+                    None => base_products_config.0.push(pushed_product),
                 }
             }
+            Err(err) => {
+                error!("Couldn't push product {} to Stripe: {}", stripe_id, err);
+                report.failed.push(PushFailure {
+                    stripe_id,
+                    name,
+                    error: err.to_string(),
+                })
+            }
+        }
+    }
+
+    if report.pushed > 0 {
+        let note = format!("push to Stripe: {} products", report.pushed);
+        save_products_config(db, products_config, actor, &note).await?;
+        crate::db::products_config::set_products_config_base(db, &base_products_config).await?;
+    }
 
-            // Create new products config by adding missing products from stripedata
-            // to existing config if the local config is missing the products by id
-            // MAKE SCAN OF ASSET IMAGES IN DIR
-            // Attach them to the local_images in CfgProduct
-            let updated_products_config: CfgProducts =
-                add_images_to_products_config(CfgProducts(h)).await?;
+    info!("Pushed products_config.json to Stripe:\n{}", report);
+    Ok(report)
+}
 
-            // Write serialized local data updated with new products from stripe api
-            write_products_config(updated_products_config, true).await
+/// Updates the `fields` of one product in Stripe to the local values.
+/// A changed price is created first and made the default price, then the old one is archived.
+/// When the product can't be updated the new price is archived again, so it isn't left active.
+/// The product is pushed once it is updated, an old price that can't be archived is added to `warnings`.
+/// Returns the product as Stripe has it afterwards.
+#[cfg(feature = "ssr")]
+async fn push_product(
+    backend: &dyn crate::payments::PaymentBackend,
+    local: &CfgProduct,
+    stripe: &CfgProduct,
+    fields: &[&str],
+    warnings: &mut Vec<crate::products_config::push::PushFailure>,
+) -> Result<(CfgProduct, Option<crate::products_config::push::NewPrice>), leptos::ServerFnError> {
+    use crate::products_config::push::{pushed_metadata, NewPrice, PushFailure};
+
+    let has = |field: &str| fields.contains(&field);
+
+    let mut new_price = match local.price.as_ref().and_then(|price| price.unit_amount) {
+        Some(unit_amount) if has("price") => {
+            let price = backend.create_price(&local.stripe_id, unit_amount).await?;
+            Some(NewPrice {
+                stripe_id: local.stripe_id.clone(),
+                name: local.name.clone(),
+                archived_price_id: stripe.price.as_ref().map(|price| price.id.clone()),
+                price_id: price.id,
+                unit_amount,
+            })
         }
-    } else {
-        // If the file doesn't exist, just create new one by serializing StripeData::new_fetch()
-        let stripe_data = StripeData::new_fetch().await?;
-        let stripe_products_config: CfgProducts = StripeData::derive_products_config(stripe_data);
+        _ => None,
+    };
 
-        // MAKE SCAN OF ASSET IMAGES IN DIR
-        // Attach them to the local_images in CfgProduct
-        let updated_stripe_products_config: CfgProducts =
-            add_images_to_products_config(stripe_products_config.clone()).await?;
+    let params = crate::payments::ProductUpdateParams {
+        name: has("name").then(|| local.name.clone()),
+        description: has("description").then(|| local.description.clone().unwrap_or_default()),
+        images: has("images").then(|| local.images.clone().unwrap_or_default()),
+        metadata: has("metadata").then(|| {
+            let mut metadata = pushed_metadata(local);
+            // Stripe keeps keys that aren't sent, an empty value removes them
+            for key in stripe.metadata.iter().flatten().map(|(key, _)| key) {
+                metadata.entry(key.clone()).or_default();
+            }
+            metadata
+        }),
+        active: has("active").then(|| local.is_active()),
+        unit_label: has("unit_label").then(|| local.unit_label.clone().unwrap_or_default()),
+        default_price: new_price.as_ref().map(|new_price| new_price.price_id.clone()),
+    };
+    if let Err(err) = backend.update_product(&local.stripe_id, params).await {
+        if let Some(new_price) = &new_price {
+            if let Err(archive_err) = backend.archive_price(&new_price.price_id).await {
+                error!(
+                    "Couldn't archive price {} of the failed push of {}: {}",
+                    new_price.price_id, local.stripe_id, archive_err
+                );
+            }
+        }
+        return Err(err);
+    }
 
-        write_products_config(updated_stripe_products_config.clone(), true).await
+    if let Some(new_price) = new_price.as_mut() {
+        if let Some(archived_price_id) = new_price.archived_price_id.clone() {
+            // The new price is the default already, the old one is only left active
+            if let Err(err) = backend.archive_price(&archived_price_id).await {
+                warn!(
+                    "Couldn't archive old price {} of {}: {}",
+                    archived_price_id, local.stripe_id, err
+                );
+                warnings.push(PushFailure {
+                    stripe_id: local.stripe_id.clone(),
+                    name: local.name.clone(),
+                    error: format!(
+                        "old price {} is still active, archive it in Stripe: {}",
+                        archived_price_id, err
+                    ),
+                });
+                new_price.archived_price_id = None;
+            }
+        }
     }
+
+    let pushed_product = backend.retrieve_product(&local.stripe_id).await?.into();
+    Ok((pushed_product, new_price))
 }
 
-/// Fetches the Product Info from local automatically deserialized json file
-/// If file doesn't exist it serializes a new file from products data inside StripeData
-/// Returns Vec of Products parameters like name and price and their images
+/// Admin endpoint of `read_products_config`
 #[leptos::server(
     name = FetchLocalProductInfo,
     endpoint = "fetch_products_config"
 )]
 pub async fn fetch_local_product_info() -> Result<CfgProducts, leptos::ServerFnError> {
+    crate::auth::require_admin().await?;
+
+    read_products_config().await
+}
+
+/// Revisions of products_config.json, newest first
+#[leptos::server(
+    name = ProductsConfigRevisions,
+    endpoint = "products_config_revisions"
+)]
+pub async fn products_config_revisions(
+) -> Result<Vec<crate::products_config::ProductsConfigRevision>, leptos::ServerFnError> {
+    crate::auth::require_admin().await?;
+
+    Ok(crate::db::products_config::get_revisions(&crate::db::use_db()?).await?)
+}
+
+/// Admin endpoint of `diff_revisions`
+#[leptos::server(
+    name = DiffProductsConfigRevisions,
+    endpoint = "diff_products_config_revisions"
+)]
+pub async fn diff_products_config_revisions(
+    from: i64,
+    to: i64,
+) -> Result<crate::products_config::ProductsConfigDiff, leptos::ServerFnError> {
+    crate::auth::require_admin().await?;
+
+    diff_revisions(&crate::db::use_db()?, from, to).await
+}
+
+/// Admin endpoint of `rollback_to_revision`, the shop serves the revision right away
+#[leptos::server(
+    name = RollbackProductsConfig,
+    endpoint = "rollback_products_config"
+)]
+pub async fn rollback_products_config(revision: i64) -> Result<i64, leptos::ServerFnError> {
+    let admin = crate::auth::require_admin().await?;
+
+    rollback_to_revision(&crate::db::use_db()?, revision, &admin.username).await
+}
+
+#[cfg(feature = "ssr")]
+async fn get_revision(
+    db: &crate::db::DbPool,
+    revision: i64,
+) -> Result<CfgProducts, leptos::ServerFnError> {
+    match crate::db::products_config::get_revision(db, revision).await? {
+        Some(products_config) => Ok(products_config),
+        None => Err(ServerFnError::ServerError(format!(
+            "No products config revision #{}",
+            revision
+        ))),
+    }
+}
+
+/// Changes from revision `from` to revision `to` of products_config.json
+#[cfg(feature = "ssr")]
+pub async fn diff_revisions(
+    db: &crate::db::DbPool,
+    from: i64,
+    to: i64,
+) -> Result<crate::products_config::ProductsConfigDiff, leptos::ServerFnError> {
+    Ok(crate::products_config::diff_products_configs(
+        &get_revision(db, from).await?,
+        &get_revision(db, to).await?,
+    ))
+}
+
+/// Writes an earlier revision of products_config.json back as the latest one.
+/// Returns the number of the new revision.
+#[cfg(feature = "ssr")]
+pub async fn rollback_to_revision(
+    db: &crate::db::DbPool,
+    revision: i64,
+    actor: &str,
+) -> Result<i64, leptos::ServerFnError> {
+    let products_config = get_revision(db, revision).await?;
+    save_products_config(
+        db,
+        products_config,
+        actor,
+        &format!("rollback to #{}", revision),
+    )
+    .await
+}
+
+/// Fetches the Product Info from local automatically deserialized json file
+/// If file doesn't exist it serializes a new file from products data inside StripeData
+/// Returns Vec of Products parameters like name and price and their images
+#[cfg(feature = "ssr")]
+pub async fn read_products_config() -> Result<CfgProducts, leptos::ServerFnError> {
     use std::fs::File;
     use std::io::Read;
     use std::io::Write;
@@ -120,7 +545,34 @@ pub async fn fetch_local_product_info() -> Result<CfgProducts, leptos::ServerFnE
                 "CONFIG: #{:?} {:?} - #{:?} AUD ",
                 p.item_number.unwrap_or(-1),
                 p.name,
-                p.price.clone().unwrap().unit_amount.unwrap() as f64 / 100.0
+                p.price
+                    .as_ref()
+                    .and_then(|price| price.unit_amount)
+                    .unwrap_or_default() as f64
+                    / 100.0
+            );
+        }
+
+        let report = crate::products_config::validation::validate_products_config(
+            &products_config,
+            Some(Path::new(&site_root)),
+        );
+        for issue in report.errors() {
+            tracing::error!(
+                "CONFIG ERROR: #{} {:?} ({}): {}, it isn't sold",
+                issue.index,
+                issue.name,
+                issue.stripe_id,
+                issue.kind
+            );
+        }
+        for issue in report.warnings() {
+            tracing::warn!(
+                "CONFIG WARNING: #{} {:?} ({}): {}",
+                issue.index,
+                issue.name,
+                issue.stripe_id,
+                issue.kind
             );
         }
 
@@ -130,7 +582,7 @@ pub async fn fetch_local_product_info() -> Result<CfgProducts, leptos::ServerFnE
         let stripe_data = StripeData::new_fetch().await?;
         let stripe_products_config: CfgProducts = StripeData::derive_products_config(stripe_data);
 
-        write_products_config(stripe_products_config.clone(), true).await?;
+        write_products_config(stripe_products_config.clone()).await?;
 
         stripe_products_config
     };
@@ -140,13 +592,21 @@ pub async fn fetch_local_product_info() -> Result<CfgProducts, leptos::ServerFnE
     Ok(final_products_config)
 }
 
-/// Adds images to CfgProducts from assets
-#[leptos::server(
-    name = AddImagesToProductsConfig
-)]
-async fn add_images_to_products_config(
+/// Runs the image pipeline over the products_assets of LEPTOS_SITE_ROOT, see images.rs
+#[cfg(feature = "ssr")]
+pub async fn process_product_images() -> Result<crate::images::ImagesReport, ServerFnError> {
+    let site_root = std::env::var("LEPTOS_SITE_ROOT").unwrap_or_else(|_| "site".to_string());
+    Ok(tokio::task::spawn_blocking(move || {
+        crate::images::process_product_images(std::path::Path::new(&site_root))
+    })
+    .await?)
+}
+
+/// Adds images to CfgProducts from assets, and reports the images found for each product
+#[cfg(feature = "ssr")]
+pub async fn add_images_to_products_config(
     products_config: CfgProducts,
-) -> Result<CfgProducts, ServerFnError> {
+) -> Result<(CfgProducts, Vec<crate::products_config::ProductImages>), ServerFnError> {
     use std::fs::File;
     use std::io::Read;
     use std::io::Write;
@@ -161,9 +621,13 @@ async fn add_images_to_products_config(
     let products_assets_dir = Path::new(&site_root);
 
     let mut updated_products_config = products_config.clone();
+    let mut images = Vec::new();
 
     for mut product in &mut updated_products_config.0 {
         // assert_eq!(product.images.is_some(), true);
+        let mut found = None;
+        let mut unreferenced = Vec::new();
+        let mut missing = Vec::new();
 
         if let Some(item_number) = product.item_number {
             // Build the path for the product's assets directory
@@ -180,16 +644,47 @@ async fn add_images_to_products_config(
                     product_images_dir_path.display()
                 );
 
-                let webp_files: Vec<PathBuf> = match std::fs::read_dir(product_images_dir_path) {
+                let mut webp_files: Vec<PathBuf> = match std::fs::read_dir(product_images_dir_path)
+                {
                     Ok(entries) => entries
                         .filter_map(|entry| entry.ok()) // Filter out errors
                         .map(|entry| entry.path()) // Get the path
                         .filter(|path| {
                             path.is_file() && path.extension().map_or(false, |ext| ext == "webp")
                         })
+                        // narrower copies made by the image pipeline are listed in local_image_sizes instead
+                        .filter(|path| crate::images::variant_width(path).is_none())
                         .collect(),
                     Err(_) => vec![], // Return empty Vec<PathBuf> if read_dir fails
                 };
+                webp_files.sort();
+                // read_dir order is arbitrary, image_manifest declares it. Unlisted images go after the listed ones
+                if let Some(image_manifest) = &product.image_manifest {
+                    let is_file = |path: &PathBuf, file: &str| {
+                        path.file_name()
+                            .is_some_and(|name| name == std::ffi::OsStr::new(file))
+                    };
+                    let position = |path: &PathBuf| {
+                        image_manifest
+                            .iter()
+                            .position(|image| is_file(path, &image.file))
+                    };
+                    webp_files.sort_by_key(|path| position(path).unwrap_or(usize::MAX));
+                    for image in image_manifest {
+                        if !webp_files.iter().any(|path| is_file(path, &image.file)) {
+                            warn!(
+                                "IMAGE {:?} OF PRODUCT {:?} IS IN image_manifest BUT NOT FOUND",
+                                image.file, item_number
+                            );
+                            missing.push(image.file.clone());
+                        }
+                    }
+                    unreferenced = webp_files
+                        .iter()
+                        .filter(|path| position(path).is_none())
+                        .cloned()
+                        .collect();
+                }
                 let webp_files_local: Vec<PathBuf> = webp_files
                     .into_iter()
                     .filter_map(|path| {
@@ -201,6 +696,15 @@ async fn add_images_to_products_config(
                     .collect();
 
                 // Set images
+                found = Some(webp_files_local.clone());
+                product.local_image_sizes = Some(
+                    webp_files_local
+                        .iter()
+                        .filter_map(|path| {
+                            crate::images::describe_local_image(Path::new(&site_root), path)
+                        })
+                        .collect(),
+                );
                 product.local_images = Some(webp_files_local);
                 // info!(
                 //     "LOCAL WEBP FILES for PRODUCT {:?}: {:#?}",
@@ -214,30 +718,81 @@ async fn add_images_to_products_config(
                 );
             }
         }
+
+        images.push(crate::products_config::ProductImages {
+            stripe_id: product.stripe_id.clone(),
+            name: product.name.clone(),
+            item_number: product.item_number,
+            found,
+            unreferenced: unreferenced
+                .into_iter()
+                .filter_map(|path| {
+                    path.strip_prefix(&site_root)
+                        .ok()
+                        .map(|path| PathBuf::from(format!("/{}", path.display())))
+                })
+                .collect(),
+            missing,
+        });
     }
 
     for product in &updated_products_config.0 {
         info!(
             "FOUND LOCAL IMAGES OF PRODUCT {:?}: {:#?}",
-            product.item_number.unwrap(),
+            product.item_number,
             product.local_images.clone()
         );
     }
 
-    Ok(updated_products_config)
+    Ok((updated_products_config, images))
 }
 
-/// Writes the config file of CfgProducts
-#[leptos::server(
-    name = WriteProductsConfig
-)]
+/// Stores products_config.json as a new revision, writes it and serves it from now on.
+/// Returns the number of the revision.
+#[cfg(feature = "ssr")]
+pub async fn save_products_config(
+    db: &crate::db::DbPool,
+    products_config: CfgProducts,
+    actor: &str,
+    note: &str,
+) -> Result<i64, leptos::ServerFnError> {
+    let revision =
+        crate::db::products_config::insert_revision(db, &products_config, actor, note).await?;
+    write_products_config(products_config.clone()).await?;
+
+    // No context when called from main before startup or from the CLI
+    if let Some(shared_appstate) = leptos::use_context::<crate::SharedAppState>() {
+        shared_appstate.set_products_config(products_config);
+    }
+
+    info!(
+        "Saved products config revision #{} by {}: {}",
+        revision, actor, note
+    );
+    Ok(revision)
+}
+
+/// Whether both config files already have exactly what `write_products_config` would write
+#[cfg(feature = "ssr")]
+fn is_products_config_file_unchanged(products_config: &CfgProducts) -> bool {
+    let site_root = std::env::var("LEPTOS_SITE_ROOT").unwrap_or_else(|_| "site".to_string());
+    let assets_dir = std::env::var("LEPTOS_ASSETS_DIR").unwrap_or_else(|_| "public".to_string());
+    let Ok(json_data) = serde_json::to_string_pretty(products_config) else {
+        return false;
+    };
+    [site_root, assets_dir].iter().all(|dir| {
+        std::fs::read_to_string(std::path::Path::new(dir).join("products_config.json"))
+            .is_ok_and(|contents| contents == json_data)
+    })
+}
+
+/// Writes the config file of CfgProducts to site root and assets.
+/// Each file is written to a temporary file first and renamed over the old one,
+/// so readers never see a half written config.
+#[cfg(feature = "ssr")]
 pub async fn write_products_config(
     products_config: CfgProducts,
-    rewrite: bool,
 ) -> Result<String, leptos::ServerFnError> {
-    use std::fs::File;
-    use std::io::Read;
-    use std::io::Write;
     use std::path::Path;
 
     // Retrieve the LEPTOS_SITE_ROOT environment variable for path of the data file
@@ -248,26 +803,22 @@ pub async fn write_products_config(
     let products_config_file_path = Path::new(&site_root).join("products_config.json");
     let products_config_public_file_path = Path::new(&assets_dir).join("products_config.json");
 
-    std::fs::create_dir_all(&site_root); // safe measure if dir doesn't exist, create it
-    std::fs::create_dir_all(&assets_dir);
-
-    if rewrite {
-        // remove existing file
-        std::fs::remove_file(products_config_file_path.clone());
-        std::fs::remove_file(products_config_public_file_path.clone());
-        info!("Removed products config file.")
-    }
+    std::fs::create_dir_all(&site_root)?; // safe measure if dir doesn't exist, create it
+    std::fs::create_dir_all(&assets_dir)?;
 
     let json_data = serde_json::to_string_pretty(&products_config)?;
-    std::fs::write(&products_config_file_path, json_data.clone())?; // write to target/site/
-    std::fs::write(&products_config_public_file_path, json_data.clone())?; // write to public/
-    info!(
-        "Written products config file with synced data at: {}",
-        products_config_file_path.display()
-    );
-    info!(
-        "Written products config file with synced data at: {}",
-        products_config_public_file_path.display()
-    );
+    for path in [
+        &products_config_file_path,
+        &products_config_public_file_path,
+    ] {
+        // The temporary file sits next to the config, a rename across filesystems wouldn't be atomic
+        let tmp_path = path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, &json_data)?;
+        std::fs::rename(&tmp_path, path)?;
+        info!(
+            "Written products config file with synced data at: {}",
+            path.display()
+        );
+    }
     Ok("Ok".into())
 }
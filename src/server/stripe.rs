@@ -3,237 +3,49 @@ use leptos::*;
 use leptos::ServerFnError;
 use log::*;
 
+/// Admin endpoint fetching StripeData without storing it
 #[leptos::server(
     name = FetchStripeData,
     // endpoint = "fetch_stripe_data",
 )]
 pub async fn fetch_stripe_data() -> Result<StripeData, leptos::ServerFnError> {
-    info!("New StripeData fetch api call to Stripe...");
+    crate::auth::require_admin().await?;
 
-    use stripe::*;
-    let client = Client::new(match std::env::var("STRIPE_KEY") {
-        Ok(ok) => ok,
-        Err(err) => {
-            log::error!("{:#?}", err);
-            return Err(ServerFnError::ServerError(err.to_string()));
-        }
-    });
-
-    // Products
-    let mut product_list_params = ListProducts::new();
-    product_list_params.active = Some(true);
-    product_list_params.expand = &["data.default_price"];
-    product_list_params.limit = Some(100);
-    let list_of_products_from_stripe_api = match Product::list(&client, &product_list_params).await
-    {
-        Ok(list) => list,
-        Err(err) => {
-            log::error!("{:#?}", err);
-            return Err(ServerFnError::ServerError(err.to_string()));
-        }
-    };
-    // tracing::info!("{:#?}", list_of_products_from_stripe_api); // DEBUG product info
-
-    // Customers
-    let mut customer_list_params = ListCustomers::new();
-    customer_list_params.limit = Some(20); // WARN INFO important to not forget to change at some point
-    let list_of_customers_from_stripe_api =
-        match Customer::list(&client, &customer_list_params).await {
-            Ok(list) => list,
-            Err(err) => {
-                log::error!("{:#?}", err);
-                return Err(ServerFnError::ServerError(err.to_string()));
-            }
-        };
-
-    // Checkout Sessions
-    let mut checkout_session_list_params = ListCheckoutSessions::new();
-    checkout_session_list_params.expand = &["data.line_items"];
-    let list_of_checkout_sessions_from_stripe_api =
-        match CheckoutSession::list(&client, &checkout_session_list_params).await {
-            Ok(list) => list,
-            Err(err) => {
-                log::error!("{:#?}", err);
-                return Err(ServerFnError::ServerError(err.to_string()));
-            }
-        };
-
-    // Shipping Rates
-    let mut shipping_rates_list_params = ListShippingRates::new();
-    shipping_rates_list_params.active = Some(true);
-    let list_of_shipping_rates_from_stripe_api: List<ShippingRate> =
-        match ShippingRate::list(&client, &shipping_rates_list_params).await {
-            Ok(list) => list,
-            Err(err) => {
-                log::error!("{:#?}", err);
-                return Err(ServerFnError::ServerError(err.to_string()));
-            }
-        };
-
-    let default_shipping_rate_id: String = match {
-        list_of_shipping_rates_from_stripe_api
-            .data
-            .iter()
-            .find(|rate| {
-                if let Some(fixed_amount) = &rate.fixed_amount {
-                    if fixed_amount.amount > 0 && fixed_amount.currency == Currency::AUD {
-                        true
-                    } else {
-                        false
-                    }
-                } else {
-                    false
-                }
-            })
-    } {
-        Some(first_shipping_rate) => {
-            // info!(
-            //     "Found Default Shipping Rate ID: {:#?} for {:#?}$",
-            //     first_shipping_rate.id.to_string().clone(),
-            //     first_shipping_rate.fixed_amount.clone().unwrap().amount as f64 / 100.0,
-            // );
-            first_shipping_rate.id.to_string()
-        }
-        None => {
-            let mut create_default_shipping_rate_params = CreateShippingRate {
-                delivery_estimate: Some(CreateShippingRateDeliveryEstimate {
-                    maximum: Some(CreateShippingRateDeliveryEstimateMaximum {
-                        unit: CreateShippingRateDeliveryEstimateMaximumUnit::Day,
-                        value: 4,
-                    }),
-                    minimum: Some(CreateShippingRateDeliveryEstimateMinimum {
-                        unit: CreateShippingRateDeliveryEstimateMinimumUnit::Day,
-                        value: 7,
-                    }),
-                }),
-                display_name: "Default Created Shipping Rate",
-                expand: &[],
-                fixed_amount: Some(CreateShippingRateFixedAmount {
-                    amount: 1000, // 10$AUD
-                    currency: Currency::AUD,
-                    currency_options: None,
-                }),
-                metadata: None,
-                tax_behavior: None,
-                tax_code: None,
-                type_: Some(ShippingRateType::FixedAmount),
-            };
-            info!("Creating New Default Shipping Rate.");
-            let shipping_rate =
-                match ShippingRate::create(&client, create_default_shipping_rate_params).await {
-                    Ok(rate) => rate,
-                    Err(err) => {
-                        log::error!("{:#?}", err);
-                        return Err(ServerFnError::ServerError(err.to_string()));
-                    }
-                };
-            shipping_rate.id.to_string()
-        }
-    };
-
-    let free_shipping_rate_id: String = match {
-        list_of_shipping_rates_from_stripe_api
-            .data
-            .iter()
-            .find(|rate| {
-                if let Some(fixed_amount) = &rate.fixed_amount {
-                    if fixed_amount.amount == 0 && fixed_amount.currency == Currency::AUD {
-                        true
-                    } else {
-                        false
-                    }
-                } else {
-                    false
-                }
-            })
-    } {
-        Some(free_shipping_rate) => {
-            // info!(
-            //     "Found Free Shipping Rate ID: {:#?} for {:#?}$",
-            //     free_shipping_rate.id.to_string().clone(),
-            //     free_shipping_rate.fixed_amount.clone().unwrap().amount as f64 / 100.0
-            // );
-            free_shipping_rate.id.to_string()
-        }
-        None => {
-            let mut create_free_shipping_rate_params = CreateShippingRate {
-                delivery_estimate: Some(CreateShippingRateDeliveryEstimate {
-                    maximum: Some(CreateShippingRateDeliveryEstimateMaximum {
-                        unit: CreateShippingRateDeliveryEstimateMaximumUnit::Day,
-                        value: 7,
-                    }),
-                    minimum: Some(CreateShippingRateDeliveryEstimateMinimum {
-                        unit: CreateShippingRateDeliveryEstimateMinimumUnit::Day,
-                        value: 4,
-                    }),
-                }),
-                display_name: "Free Created Shipping Rate",
-                expand: &[],
-                fixed_amount: Some(CreateShippingRateFixedAmount {
-                    amount: 0,
-                    currency: Currency::AUD,
-                    currency_options: None,
-                }),
-                metadata: None,
-                tax_behavior: None,
-                tax_code: None,
-                type_: Some(ShippingRateType::FixedAmount),
-            };
-            info!("Creating New Free Shipping Rate.");
-            let shipping_rate =
-                match ShippingRate::create(&client, create_free_shipping_rate_params).await {
-                    Ok(rate) => rate,
-                    Err(err) => {
-                        log::error!("{:#?}", err);
-                        return Err(ServerFnError::ServerError(err.to_string()));
-                    }
-                };
-            shipping_rate.id.to_string()
-        }
-    };
-
-    // info!("Default Shipping Rate ID: {:#?}", default_shipping_rate_id);
-    // info!("Free Shipping Rate ID: {:#?}", free_shipping_rate_id);
-    // leptos::logging::log!("\n");
-
-    Ok(StripeData::new(
-        list_of_products_from_stripe_api,
-        list_of_customers_from_stripe_api,
-        list_of_checkout_sessions_from_stripe_api,
-        default_shipping_rate_id,
-        free_shipping_rate_id,
-    ))
+    StripeData::new_fetch().await
 }
 
+/// Admin endpoint of `sync_stripe_data`
 #[server (
     name = StripeSync,
-    endpoint = "sync", // WORKING BUT TODO IMPLEMENT AUTHENTIFICATION
+    endpoint = "sync",
 )]
 pub async fn stripe_sync() -> Result<serde_json::Value, leptos::ServerFnError> {
-    use axum::http::HeaderMap;
-    use log::*;
-    use stripe::*;
-
-    let state = match leptos::use_context::<Option<crate::AppState>>() {
-        Some(ok) => {
-            // leptos::logging::log!("GOT context AppState");
-            ok
-        }
-        None => {
-            // leptos::logging::log!("No context AppState");
-            None
-        }
-    };
-    let axum::extract::State(mut appstate): axum::extract::State<crate::AppState> =
-        leptos_axum::extract_with_state(match &state {
-            Some(x) => x,
-            None => &AppState {
-                stripe_data: None,
-                products_config: None,
-            },
-        })
-        .await?;
+    let admin = crate::auth::require_admin().await?;
+    info!("Admin {} started a sync with Stripe.", admin.username);
+
+    let shared_appstate = crate::use_shared_app_state()?;
+    let started_at = crate::db::unix_now();
+    let result = sync_stripe_data(&shared_appstate, &crate::db::use_db()?).await;
+    shared_appstate.record_sync(crate::SyncReport::new(
+        crate::SyncKind::StripeSync,
+        &admin.username,
+        started_at,
+        result.as_ref().map(|report| {
+            format!(
+                "{} products, {} customers",
+                report["count"]["products"], report["count"]["customers"]
+            )
+        }),
+    ));
+    result
+}
 
+/// Fetches StripeData, serves it from the shared AppState and stores it in the database
+#[cfg(feature = "ssr")]
+pub async fn sync_stripe_data(
+    shared_appstate: &crate::SharedAppState,
+    db: &crate::db::DbPool,
+) -> Result<serde_json::Value, leptos::ServerFnError> {
     info!("v----Starting sync of local StripeData with Stripe API----v");
 
     let new_stripedata: Option<StripeData> = match StripeData::new_fetch().await {
@@ -244,7 +56,7 @@ pub async fn stripe_sync() -> Result<serde_json::Value, leptos::ServerFnError> {
         }
     };
 
-    appstate.stripe_data = match new_stripedata.clone() {
+    let stripe_data = match new_stripedata {
         Some(data) => {
             info!("v----Synced-StripeData----v");
             info!("Synchronized AppState with Stripe API");
@@ -267,7 +79,7 @@ pub async fn stripe_sync() -> Result<serde_json::Value, leptos::ServerFnError> {
                     .collect::<Vec<crate::stripe_retypes::DbCheckoutSession>>()
                     .len()
             );
-            Some(data)
+            data
         }
         None => {
             log::error!("Couldn't update StripeData");
@@ -277,19 +89,31 @@ pub async fn stripe_sync() -> Result<serde_json::Value, leptos::ServerFnError> {
         }
     };
 
+    // Swap the new data into the shared state so next requests are served from it
+    shared_appstate.set_stripe_data(stripe_data.clone());
+
+    // It is served already, but orders and stock only follow what is in the database
+    if let Err(err) = stripe_data.save_to_db(db).await {
+        log::error!("Couldn't store synced StripeData in database: {:#?}", err);
+        return Err(leptos::ServerFnError::ServerError(format!(
+            "Couldn't store synced StripeData in database: {}",
+            err
+        )));
+    }
+    // Expired sessions released their stock while saving, this catches reservations whose session was never stored
+    match crate::db::inventory::release_stale_reservations(db, 2 * crate::CHECKOUT_SESSION_LIFETIME)
+        .await
+    {
+        Ok(0) => {}
+        Ok(released) => log::info!("Released {} stale stock reservations.", released),
+        Err(err) => log::error!("Couldn't release stale stock reservations: {:#?}", err),
+    }
+
     Ok(serde_json::json!({
-        "code": match appstate.stripe_data.clone() {
-            Some(_) => http::StatusCode::NO_CONTENT.to_string(),
-            None => http::StatusCode::INTERNAL_SERVER_ERROR.to_string(),        },
+        "code": http::StatusCode::NO_CONTENT.to_string(),
         "count": {
-            "products": match appstate.stripe_data.clone() {
-                Some(data) => data.products.len(),
-                None => 0,
-            },
-            "customers": match appstate.stripe_data.clone() {
-                Some(data) => data.customers.len(),
-                None => 0
-            },
+            "products": stripe_data.products.len(),
+            "customers": stripe_data.customers.len(),
         },
     }))
 }
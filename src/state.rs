@@ -1,11 +1,149 @@
 use crate::products_config::CfgProducts;
 use crate::stripe_retypes;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct AppState {
     pub stripe_data: Option<StripeData>,
     pub products_config: Option<CfgProducts>,
+    pub stripe_status: StripeStatus,
+    /// Latest syncs with Stripe and refreshes of products_config, newest first
+    #[serde(default)]
+    pub sync_reports: Vec<SyncReport>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StripeStatus {
+    /// StripeData was fetched from Stripe
+    Live,
+    /// Stripe couldn't be reached, StripeData comes from the database. Catalog is read only and checkout is unavailable.
+    Degraded,
+}
+
+impl AppState {
+    pub fn is_checkout_available(&self) -> bool {
+        self.stripe_status == StripeStatus::Live && self.stripe_data.is_some()
+    }
+
+    /// The public part of the AppState.
+    /// Products come from products_config, or straight from StripeData when there is no products config yet.
+    #[cfg(feature = "ssr")]
+    pub fn catalog(&self, available_stock: HashMap<String, i64>) -> Catalog {
+        let products = match (&self.products_config, &self.stripe_data) {
+            (Some(products_config), _) => products_config.clone(),
+            (None, Some(stripe_data)) => stripe_data.clone().derive_products_config(),
+            (None, None) => CfgProducts(Vec::new()),
+        };
+        // Products archived in Stripe stay in products_config.json but aren't sold,
+        // neither are products with errors the pages can't show
+        let products = CfgProducts(
+            crate::products_config::validation::servable_products(&products)
+                .0
+                .into_iter()
+                .filter(|product| product.is_active())
+                .collect(),
+        );
+
+        let mut categories: Vec<String> = products
+            .0
+            .iter()
+            .filter_map(|product| product.metadata.as_ref()?.get("category").cloned())
+            .collect();
+        categories.sort();
+        categories.dedup();
+
+        Catalog {
+            products,
+            categories,
+            checkout_available: self.is_checkout_available(),
+            available_stock,
+        }
+    }
+}
+
+/// What the storefront shows and sells: products with their prices, categories and images, and the stock left.
+/// Unlike AppState it has no customers or checkout sessions, so it is safe to send to every browser.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Catalog {
+    pub products: CfgProducts,
+    pub categories: Vec<String>, // "category" metadata of the products, sorted
+    pub checkout_available: bool, // false while the server runs without Stripe
+    /// Stock left to sell by product id, read from the database on every request.
+    /// Products missing here don't have their stock tracked.
+    #[serde(default)]
+    pub available_stock: HashMap<String, i64>,
+}
+
+impl Catalog {
+    pub fn is_sold_out(&self, product_id: &str) -> bool {
+        self.available_stock
+            .get(product_id)
+            .is_some_and(|available| *available <= 0)
+    }
+
+    /// How many of a product can go in the cart, `max_quantity` or less when there isn't that much stock left
+    pub fn cart_limit(&self, product_id: &str, max_quantity: u8) -> u8 {
+        match self.available_stock.get(product_id) {
+            Some(available) => (*available).clamp(0, max_quantity as i64) as u8,
+            None => max_quantity,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyncKind {
+    StripeSync,
+    ProductsRefresh,
+    /// products_config.json or product images edited on disk, see catalog_watch.rs
+    CatalogReload,
+    /// Local edits of products_config.json pushed to Stripe
+    ProductsPush,
+}
+
+/// Outcome of one sync with Stripe or refresh of products_config, shown on the admin sync page
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SyncReport {
+    pub kind: SyncKind,
+    pub actor: String, // admin username, "startup" or "watcher"
+    pub summary: Option<String>,
+    pub error: Option<String>, // None if the sync succeeded
+    // Measured in seconds since the Unix epoch.
+    pub started_at: i64,
+    pub finished_at: i64,
+}
+
+/// How many SyncReports the AppState keeps
+pub const SYNC_REPORTS_KEPT: usize = 20;
+
+#[cfg(feature = "ssr")]
+impl SyncReport {
+    pub fn new<E: std::fmt::Display>(
+        kind: SyncKind,
+        actor: &str,
+        started_at: i64,
+        result: Result<String, E>,
+    ) -> Self {
+        let (summary, error) = match result {
+            Ok(summary) => (Some(summary), None),
+            Err(err) => (None, Some(err.to_string())),
+        };
+        SyncReport {
+            kind,
+            actor: actor.to_string(),
+            summary,
+            error,
+            started_at,
+            finished_at: crate::db::unix_now(),
+        }
+    }
+}
+
+/// What the admin sync page shows
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SyncStatus {
+    pub stripe_status: StripeStatus,
+    pub sync_reports: Vec<SyncReport>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -16,3 +154,64 @@ pub struct StripeData {
     pub default_shipping_rate_id: String,
     pub free_shipping_rate_id: String,
 }
+
+/// Server side handle to the one AppState that every request reads from.
+/// Sync, refresh and webhook paths swap new data in here, so the next request sees it without a restart.
+#[cfg(feature = "ssr")]
+#[derive(Clone, Debug)]
+pub struct SharedAppState(std::sync::Arc<std::sync::RwLock<AppState>>);
+
+#[cfg(feature = "ssr")]
+impl SharedAppState {
+    pub fn new(appstate: AppState) -> Self {
+        SharedAppState(std::sync::Arc::new(std::sync::RwLock::new(appstate)))
+    }
+
+    /// Returns a copy of the current AppState.
+    pub fn snapshot(&self) -> AppState {
+        self.0
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    /// Applies `f` to the AppState while holding the write lock, so readers never see a half updated state.
+    pub fn update<F: FnOnce(&mut AppState)>(&self, f: F) {
+        let mut appstate = self
+            .0
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        f(&mut appstate);
+    }
+
+    /// Stores freshly fetched StripeData, which also means Stripe is reachable again
+    pub fn set_stripe_data(&self, stripe_data: StripeData) {
+        self.update(|appstate| {
+            appstate.stripe_data = Some(stripe_data);
+            appstate.stripe_status = StripeStatus::Live;
+        });
+    }
+
+    /// Applies `f` to the StripeData in place. Returns false if there is no StripeData yet.
+    pub fn update_stripe_data<F: FnOnce(&mut StripeData)>(&self, f: F) -> bool {
+        let mut updated = false;
+        self.update(|appstate| {
+            if let Some(stripe_data) = appstate.stripe_data.as_mut() {
+                f(stripe_data);
+                updated = true;
+            }
+        });
+        updated
+    }
+
+    pub fn set_products_config(&self, products_config: CfgProducts) {
+        self.update(|appstate| appstate.products_config = Some(products_config));
+    }
+
+    pub fn record_sync(&self, sync_report: SyncReport) {
+        self.update(|appstate| {
+            appstate.sync_reports.insert(0, sync_report);
+            appstate.sync_reports.truncate(SYNC_REPORTS_KEPT);
+        });
+    }
+}
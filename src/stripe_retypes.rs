@@ -20,6 +20,23 @@ pub struct DbCheckoutSession {
     pub status: Option<DbCheckoutSessionStatus>,
     pub success_url: Option<String>,
     pub url: Option<String>,
+    // Filled in by the customer on the checkout page, missing in sessions stored before they were mirrored
+    #[serde(default)]
+    pub currency: Option<String>, // lowercase ISO code like "aud"
+    #[serde(default)]
+    pub customer_details: Option<DbCheckoutSessionCustomerDetails>,
+    #[serde(default)]
+    pub shipping_details: Option<DbShipping>,
+    #[serde(default)]
+    pub amount_shipping: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DbCheckoutSessionCustomerDetails {
+    pub address: Option<DbAddress>,
+    pub email: Option<String>,
+    pub name: Option<String>,
+    pub phone: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -29,7 +46,7 @@ pub enum DbCheckoutSessionMode {
     Subscription,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub enum DbCheckoutSessionPaymentStatus {
     NoPaymentRequired,
     Paid,
@@ -202,3 +219,18 @@ pub struct DbShipping {
     pub phone: Option<String>,
     pub tracking_number: Option<String>,
 }
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DbShippingRate {
+    pub id: String,
+    pub active: bool,
+    pub display_name: Option<String>,
+    pub fixed_amount: Option<DbShippingRateFixedAmount>,
+    pub livemode: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DbShippingRateFixedAmount {
+    pub amount: i64,
+    pub currency: String, // lowercase ISO code like "aud"
+}
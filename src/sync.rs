@@ -14,57 +14,217 @@ use stripe_retypes::*;
 
 impl StripeData {
     pub fn new(
-        products: List<Product>,
-        customers: List<Customer>,
-        checkout_sessions: List<CheckoutSession>,
+        products: Vec<DbProduct>,
+        customers: Vec<DbCustomer>,
+        checkout_sessions: Vec<DbCheckoutSession>,
         default_shipping_rate_id: String,
         free_shipping_rate_id: String,
     ) -> Self {
         StripeData {
-            products: products.data.into_iter().map(|x| x.into()).collect(),
-            customers: customers.data.into_iter().map(|x| x.into()).collect(),
-            checkout_sessions: checkout_sessions
-                .data
-                .into_iter()
-                .map(|x| x.into())
-                .collect(),
+            products,
+            customers,
+            checkout_sessions,
             default_shipping_rate_id,
             free_shipping_rate_id,
         }
     }
+
+    /// Fetches everything StripeData holds from the payment backend.
+    /// Creates the default and free AUD shipping rates if the backend has none yet.
+    pub async fn fetch_from(
+        payment_backend: &dyn crate::payments::PaymentBackend,
+    ) -> Result<Self, ServerFnError> {
+        use crate::payments::ShippingRateParams;
+
+        let products = payment_backend.list_products().await?;
+        let customers = payment_backend.list_customers().await?;
+        let checkout_sessions = payment_backend.list_checkout_sessions().await?;
+        let shipping_rates = payment_backend.list_shipping_rates().await?;
+
+        let find_aud_rate = |is_free: bool| {
+            shipping_rates.iter().find(|rate| {
+                rate.fixed_amount.as_ref().is_some_and(|fixed_amount| {
+                    (fixed_amount.amount == 0) == is_free && fixed_amount.currency == "aud"
+                })
+            })
+        };
+
+        let default_shipping_rate_id: String = match find_aud_rate(false) {
+            Some(first_shipping_rate) => first_shipping_rate.id.clone(),
+            None => {
+                info!("Creating New Default Shipping Rate.");
+                payment_backend
+                    .create_shipping_rate(ShippingRateParams {
+                        display_name: "Default Created Shipping Rate".to_string(),
+                        amount: 1000, // 10$AUD
+                        min_delivery_days: 4,
+                        max_delivery_days: 7,
+                    })
+                    .await?
+                    .id
+            }
+        };
+
+        let free_shipping_rate_id: String = match find_aud_rate(true) {
+            Some(free_shipping_rate) => free_shipping_rate.id.clone(),
+            None => {
+                info!("Creating New Free Shipping Rate.");
+                payment_backend
+                    .create_shipping_rate(ShippingRateParams {
+                        display_name: "Free Created Shipping Rate".to_string(),
+                        amount: 0,
+                        min_delivery_days: 4,
+                        max_delivery_days: 7,
+                    })
+                    .await?
+                    .id
+            }
+        };
+
+        // info!("Default Shipping Rate ID: {:#?}", default_shipping_rate_id);
+        // info!("Free Shipping Rate ID: {:#?}", free_shipping_rate_id);
+
+        Ok(StripeData::new(
+            products,
+            customers,
+            checkout_sessions,
+            default_shipping_rate_id,
+            free_shipping_rate_id,
+        ))
+    }
+
     pub fn derive_products_config(self) -> CfgProducts {
         let mut v = CfgProducts(Vec::new());
         for p in self.products {
-            v.0.push(CfgProduct {
-                item_number: match p.metadata {
-                    Some(ref x) => x.get("item_number").cloned().and_then(|i| i.parse().ok()),
-                    _ => None,
-                },
-                stripe_id: p.id,
-                name: p.name,
-                price: p.default_price,
-                description: p.description,
-                local_images: None,
-                images: p.images,
-                metadata: p.metadata,
-            })
+            v.0.push(p.into())
         }
         v
     }
     pub async fn new_fetch() -> Result<Self, ServerFnError> {
-        fetch_stripe_data().await
+        info!("New StripeData fetch api call to Stripe...");
+        StripeData::fetch_from(crate::payments::payment_backend()?.as_ref()).await
+    }
+
+    /// Inserts or replaces a product by id. Archived products are removed, same as in fetch_stripe_data.
+    pub fn upsert_product(&mut self, product: DbProduct) {
+        if !product.active {
+            self.remove_product(&product.id);
+            return;
+        }
+        match self.products.iter_mut().find(|p| p.id == product.id) {
+            Some(existing) => *existing = product,
+            None => self.products.push(product),
+        }
+    }
+
+    pub fn remove_product(&mut self, product_id: &str) {
+        self.products.retain(|p| p.id != product_id);
+    }
+
+    /// Replaces the price on every product that uses it as default price
+    pub fn upsert_price(&mut self, price: DbPrice) {
+        for product in &mut self.products {
+            if product
+                .default_price
+                .as_ref()
+                .is_some_and(|default_price| default_price.id == price.id)
+            {
+                product.default_price = Some(price.clone());
+            }
+        }
+    }
+
+    /// Clears a deleted price from every product that uses it as default price
+    pub fn remove_price(&mut self, price_id: &str) {
+        for product in &mut self.products {
+            if product
+                .default_price
+                .as_ref()
+                .is_some_and(|default_price| default_price.id == price_id)
+            {
+                product.default_price = None;
+            }
+        }
+    }
+
+    pub fn upsert_customer(&mut self, customer: DbCustomer) {
+        match self.customers.iter_mut().find(|c| c.id == customer.id) {
+            Some(existing) => *existing = customer,
+            None => self.customers.push(customer),
+        }
+    }
+
+    pub fn remove_customer(&mut self, customer_id: &str) {
+        self.customers.retain(|c| c.id != customer_id);
+    }
+
+    pub fn upsert_checkout_session(&mut self, checkout_session: DbCheckoutSession) {
+        match self
+            .checkout_sessions
+            .iter_mut()
+            .find(|c| c.id == checkout_session.id)
+        {
+            Some(existing) => *existing = checkout_session,
+            None => self.checkout_sessions.push(checkout_session),
+        }
+    }
+}
+
+/// Keeps trying to fetch StripeData until Stripe answers, then swaps it into the shared AppState.
+/// Spawned by main.rs when the server had to boot in degraded mode.
+pub async fn retry_stripe_fetch_until_live(
+    shared_appstate: crate::SharedAppState,
+    db: crate::db::DbPool,
+) {
+    let mut delay = std::time::Duration::from_secs(5);
+    let max_delay = std::time::Duration::from_secs(300);
+
+    loop {
+        tokio::time::sleep(delay).await;
+
+        let started_at = crate::db::unix_now();
+        match StripeData::new_fetch().await {
+            Ok(stripe_data) => {
+                if let Err(err) = stripe_data.save_to_db(&db).await {
+                    error!("Failed to store StripeData in database: {}", err);
+                }
+                if shared_appstate.snapshot().products_config.is_none() {
+                    shared_appstate
+                        .set_products_config(stripe_data.clone().derive_products_config());
+                }
+                shared_appstate.record_sync(crate::SyncReport::new::<ServerFnError>(
+                    crate::SyncKind::StripeSync,
+                    "retry",
+                    started_at,
+                    Ok("Stripe is reachable again".to_string()),
+                ));
+                shared_appstate.set_stripe_data(stripe_data);
+                info!("Stripe is reachable again, leaving degraded mode.");
+                return;
+            }
+            Err(err) => {
+                delay = (delay * 2).min(max_delay);
+                warn!("Stripe still unreachable, retrying in {:?}: {}", delay, err);
+            }
+        }
     }
 }
 
 impl CfgProducts {
     pub async fn new_fetch_local() -> Result<Self, ServerFnError> {
-        fetch_local_product_info().await
+        read_products_config().await
     }
-    pub async fn fetch_reset() -> Result<String, ServerFnError> {
-        refresh_local_product_info(true).await
+    pub async fn fetch_reset(
+        db: &crate::db::DbPool,
+        actor: &str,
+    ) -> Result<crate::products_config::ProductsConfigDiff, ServerFnError> {
+        refresh_products_config(db, actor, true).await
     }
-    pub async fn fetch_update() -> Result<String, ServerFnError> {
-        refresh_local_product_info(false).await
+    pub async fn fetch_update(
+        db: &crate::db::DbPool,
+        actor: &str,
+    ) -> Result<crate::products_config::ProductsConfigDiff, ServerFnError> {
+        refresh_products_config(db, actor, false).await
     }
 }
 
@@ -121,6 +281,15 @@ impl From<CheckoutSession> for DbCheckoutSession {
             },
             success_url: value.success_url,
             url: value.url,
+            currency: value.currency.map(|x| x.to_string()),
+            customer_details: value.customer_details.map(|x| DbCheckoutSessionCustomerDetails {
+                address: x.address.map(|x| x.into()),
+                email: x.email,
+                name: x.name,
+                phone: x.phone,
+            }),
+            shipping_details: value.shipping_details.map(|x| x.into()),
+            amount_shipping: value.shipping_cost.map(|x| x.amount_total),
         }
     }
 }
@@ -142,6 +311,38 @@ impl From<CheckoutSessionItem> for DbCheckoutSessionItem {
     }
 }
 
+impl From<DbProduct> for CfgProduct {
+    fn from(p: DbProduct) -> Self {
+        let variant = p
+            .metadata
+            .as_ref()
+            .and_then(products_config::CfgVariant::from_metadata);
+        let attributes = p
+            .metadata
+            .as_ref()
+            .and_then(products_config::CfgAttributes::from_metadata);
+        CfgProduct {
+            item_number: match p.metadata {
+                Some(ref x) => x.get("item_number").cloned().and_then(|i| i.parse().ok()),
+                _ => None,
+            },
+            stripe_id: p.id,
+            name: p.name,
+            price: p.default_price,
+            description: p.description,
+            local_images: None,
+            images: p.images,
+            metadata: p.metadata,
+            active: Some(p.active),
+            local_image_sizes: None,
+            image_manifest: None,
+            variant,
+            unit_label: p.unit_label,
+            attributes,
+        }
+    }
+}
+
 impl From<Product> for DbProduct {
     fn from(value: Product) -> Self {
         DbProduct {
@@ -252,11 +453,7 @@ impl From<Price> for DbPrice {
             lookup_key: value.lookup_key,
             metadata: value.metadata,
             nickname: value.nickname,
-            product: value
-                .product
-                .unwrap_or_default()
-                .into_object()
-                .map(|x| x.id.to_string()),
+            product: value.product.map(|x| x.id().to_string()),
             recurring: value.recurring.map(|x| x.into()),
             // tiers: value.tiers,
             // tiers_mode: value.tiers_mode,
@@ -311,6 +508,21 @@ impl From<Shipping> for DbShipping {
     }
 }
 
+impl From<ShippingRate> for DbShippingRate {
+    fn from(value: ShippingRate) -> Self {
+        DbShippingRate {
+            id: value.id.to_string(),
+            active: value.active,
+            display_name: value.display_name,
+            fixed_amount: value.fixed_amount.map(|x| DbShippingRateFixedAmount {
+                amount: x.amount,
+                currency: x.currency.to_string(),
+            }),
+            livemode: value.livemode,
+        }
+    }
+}
+
 impl From<Customer> for DbCustomer {
     fn from(value: Customer) -> Self {
         DbCustomer {
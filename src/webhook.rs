@@ -0,0 +1,141 @@
+#![cfg(feature = "ssr")]
+
+use crate::db::{self, DbPool};
+use crate::payments::payment_backend;
+use crate::stripe_retypes::*;
+use crate::SharedAppState;
+use axum::{
+    extract::Extension,
+    http::{HeaderMap, StatusCode},
+};
+use leptos::ServerFnError;
+use log::*;
+use stripe::*;
+
+/// Receives Stripe webhook events on `/api/webhooks/stripe`.
+/// The `Stripe-Signature` header is verified against STRIPE_WEBHOOK_SECRET before anything is touched.
+/// Any non 2xx response makes Stripe retry the event later.
+pub async fn stripe_webhook_handler(
+    Extension(shared_appstate): Extension<SharedAppState>,
+    Extension(db): Extension<DbPool>,
+    headers: HeaderMap,
+    payload: String,
+) -> StatusCode {
+    let secret = match std::env::var("STRIPE_WEBHOOK_SECRET") {
+        Ok(ok) => ok,
+        Err(err) => {
+            error!("Missing STRIPE_WEBHOOK_SECRET variable in env: {:#?}", err);
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+    };
+
+    let signature = match headers
+        .get("Stripe-Signature")
+        .and_then(|value| value.to_str().ok())
+    {
+        Some(signature) => signature,
+        None => {
+            warn!("Stripe webhook request without Stripe-Signature header.");
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+
+    let event = match Webhook::construct_event(&payload, signature, &secret) {
+        Ok(event) => event,
+        Err(err) => {
+            warn!("Rejected Stripe webhook event: {:#?}", err);
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+
+    info!("Stripe webhook event: {} ({})", event.type_, event.id);
+
+    match handle_stripe_event(&shared_appstate, &db, event).await {
+        Ok(()) => StatusCode::OK,
+        Err(err) => {
+            error!("Couldn't handle Stripe webhook event: {:#?}", err);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+/// Applies a verified Stripe event to the local database and the shared StripeData
+async fn handle_stripe_event(
+    shared_appstate: &SharedAppState,
+    db: &DbPool,
+    event: Event,
+) -> Result<(), ServerFnError> {
+    let payment_backend = payment_backend()?;
+
+    let updated = match (event.type_, event.data.object) {
+        (
+            EventType::CheckoutSessionCompleted
+            | EventType::CheckoutSessionAsyncPaymentSucceeded
+            | EventType::CheckoutSessionAsyncPaymentFailed
+            | EventType::CheckoutSessionExpired,
+            EventObject::CheckoutSession(session),
+        ) => {
+            // Webhook payloads don't carry line items, so get the full session
+            let session = payment_backend
+                .retrieve_checkout_session(session.id.as_str())
+                .await?;
+            info!(
+                "Checkout session {} is now {:?} ({:?})",
+                session.id, session.status, session.payment_status
+            );
+            // Storing a Complete and Paid session also makes its Order
+            db::stripe::upsert_checkout_session(db, &session).await?;
+            shared_appstate
+                .update_stripe_data(|stripe_data| stripe_data.upsert_checkout_session(session))
+        }
+        (
+            EventType::ProductCreated | EventType::ProductUpdated,
+            EventObject::Product(product),
+        ) => {
+            // Webhook payloads only have the id of default_price, so get it expanded
+            let product = payment_backend
+                .retrieve_product(product.id.as_str())
+                .await?;
+            db::stripe::upsert_product(db, &product).await?;
+            shared_appstate.update_stripe_data(|stripe_data| stripe_data.upsert_product(product))
+        }
+        (EventType::ProductDeleted, EventObject::Product(product)) => {
+            db::stripe::deactivate_product(db, product.id.as_str()).await?;
+            shared_appstate
+                .update_stripe_data(|stripe_data| stripe_data.remove_product(product.id.as_str()))
+        }
+        (EventType::PriceCreated | EventType::PriceUpdated, EventObject::Price(price)) => {
+            let price: DbPrice = price.into();
+            db::stripe::upsert_price(db, &price).await?;
+            shared_appstate.update_stripe_data(|stripe_data| stripe_data.upsert_price(price))
+        }
+        (EventType::PriceDeleted, EventObject::Price(price)) => {
+            db::stripe::delete_price(db, price.id.as_str()).await?;
+            shared_appstate
+                .update_stripe_data(|stripe_data| stripe_data.remove_price(price.id.as_str()))
+        }
+        (
+            EventType::CustomerCreated | EventType::CustomerUpdated,
+            EventObject::Customer(customer),
+        ) => {
+            let customer: DbCustomer = customer.into();
+            db::stripe::upsert_customer(db, &customer).await?;
+            shared_appstate.update_stripe_data(|stripe_data| stripe_data.upsert_customer(customer))
+        }
+        (EventType::CustomerDeleted, EventObject::Customer(customer)) => {
+            db::stripe::delete_customer(db, customer.id.as_str()).await?;
+            shared_appstate
+                .update_stripe_data(|stripe_data| stripe_data.remove_customer(customer.id.as_str()))
+        }
+        (event_type, _) => {
+            info!("Ignoring Stripe webhook event: {}", event_type);
+            return Ok(());
+        }
+    };
+
+    if !updated {
+        warn!("No StripeData in AppState, webhook event was not applied.");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,217 @@
+#![cfg(feature = "ssr")]
+
+//! A checkout against the FakeBackend, delivered back the way the webhook stores it:
+//! the paid session makes its order and takes the reserved stock off the inventory.
+
+use farmtasker_au::db::{self, DbPool};
+use farmtasker_au::payments::{
+    CheckoutLineItem, CheckoutSessionParams, FakeBackend, PaymentBackend,
+};
+use farmtasker_au::products_config::CfgProducts;
+use serde_json::json;
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+use std::future::Future;
+use std::str::FromStr;
+
+fn block_on<F: Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(future)
+}
+
+async fn memory_db() -> DbPool {
+    let options = SqliteConnectOptions::from_str("sqlite::memory:").unwrap();
+    // Every connection to :memory: is a database of its own
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect_with(options)
+        .await
+        .unwrap();
+    sqlx::migrate!("./migrations").run(&pool).await.unwrap();
+    pool
+}
+
+fn backend() -> FakeBackend {
+    let product = |stripe_id: &str, name: &str, unit_amount: i64| {
+        json!({
+            "stripe_id": stripe_id,
+            "name": name,
+            "price": {
+                "id": format!("price_{}", stripe_id),
+                "active": true,
+                "livemode": false,
+                "unit_amount": unit_amount,
+            },
+        })
+    };
+    let products_config: CfgProducts = serde_json::from_value(json!([
+        product("prod_eggs", "Eggs", 900),
+        product("prod_honey", "Honey", 1250),
+    ]))
+    .unwrap();
+    FakeBackend::from_products_config(products_config)
+}
+
+/// Reserves stock and makes the checkout session like the checkout server fn does
+async fn checkout(db: &DbPool, backend: &FakeBackend, items: &[(&str, u64)]) -> String {
+    let reservation_key = db::inventory::new_reservation_key();
+    let reserved: Vec<(String, u64)> = items
+        .iter()
+        .map(|(product_id, quantity)| (product_id.to_string(), *quantity))
+        .collect();
+    db::inventory::reserve_stock(db, &reservation_key, &reserved)
+        .await
+        .unwrap();
+
+    let session = backend
+        .create_checkout_session(CheckoutSessionParams {
+            line_items: items
+                .iter()
+                .map(|(product_id, quantity)| CheckoutLineItem {
+                    price_id: format!("price_{}", product_id),
+                    quantity: *quantity,
+                    adjustable: false,
+                })
+                .collect(),
+            shipping_rate_id: String::new(),
+            success_url: "http://localhost/checkout/success/{CHECKOUT_SESSION_ID}".to_string(),
+            cancel_url: "http://localhost/cart".to_string(),
+            metadata: None,
+            expires_at: None,
+        })
+        .await
+        .unwrap();
+    db::inventory::attach_checkout_session(db, &reservation_key, &session.id)
+        .await
+        .unwrap();
+    session.id
+}
+
+/// Stores the session as the webhook does on a checkout.session event, which carries only its id
+async fn deliver_webhook(db: &DbPool, backend: &FakeBackend, checkout_session_id: &str) {
+    let session = backend
+        .retrieve_checkout_session(checkout_session_id)
+        .await
+        .unwrap();
+    db::stripe::upsert_checkout_session(db, &session)
+        .await
+        .unwrap();
+}
+
+async fn available(db: &DbPool, product_id: &str) -> Option<i64> {
+    db::inventory::get_available_stock(db)
+        .await
+        .unwrap()
+        .get(product_id)
+        .copied()
+}
+
+#[test]
+fn paid_checkout_makes_an_order_and_takes_stock_once() {
+    block_on(async {
+        let db = memory_db().await;
+        let backend = backend();
+        db::inventory::set_stock(&db, "prod_eggs", Some(10))
+            .await
+            .unwrap();
+
+        let checkout_session_id =
+            checkout(&db, &backend, &[("prod_eggs", 3), ("prod_honey", 1)]).await;
+        assert_eq!(available(&db, "prod_eggs").await, Some(7));
+
+        // The open session is stored without an order
+        deliver_webhook(&db, &backend, &checkout_session_id).await;
+        assert!(
+            db::orders::get_order_by_checkout_session(&db, &checkout_session_id)
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        backend
+            .complete_checkout_session(&checkout_session_id)
+            .unwrap();
+        deliver_webhook(&db, &backend, &checkout_session_id).await;
+        // Stripe delivers events at least once
+        deliver_webhook(&db, &backend, &checkout_session_id).await;
+
+        let order = db::orders::get_order_by_checkout_session(&db, &checkout_session_id)
+            .await
+            .unwrap()
+            .unwrap();
+        let items: Vec<(Option<&str>, u64)> = order
+            .items
+            .iter()
+            .map(|item| (item.product_id.as_deref(), item.quantity))
+            .collect();
+        assert_eq!(items, [(Some("prod_eggs"), 3), (Some("prod_honey"), 1)]);
+        assert_eq!(order.amount_total, 3 * 900 + 1250);
+        assert_eq!(db::orders::get_orders(&db).await.unwrap().len(), 1);
+        assert_eq!(available(&db, "prod_eggs").await, Some(7));
+        assert_eq!(available(&db, "prod_honey").await, None);
+    });
+}
+
+#[test]
+fn payment_after_a_stale_release_still_takes_stock() {
+    block_on(async {
+        let db = memory_db().await;
+        let backend = backend();
+        db::inventory::set_stock(&db, "prod_eggs", Some(10))
+            .await
+            .unwrap();
+
+        let checkout_session_id = checkout(&db, &backend, &[("prod_eggs", 2)]).await;
+        // Every reservation is older than a negative max_age
+        assert_eq!(
+            db::inventory::release_stale_reservations(&db, -1)
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(available(&db, "prod_eggs").await, Some(10));
+
+        backend
+            .complete_checkout_session(&checkout_session_id)
+            .unwrap();
+        deliver_webhook(&db, &backend, &checkout_session_id).await;
+        deliver_webhook(&db, &backend, &checkout_session_id).await;
+
+        assert_eq!(available(&db, "prod_eggs").await, Some(8));
+        assert!(
+            db::orders::get_order_by_checkout_session(&db, &checkout_session_id)
+                .await
+                .unwrap()
+                .is_some()
+        );
+    });
+}
+
+#[test]
+fn expired_checkout_gives_its_stock_back() {
+    block_on(async {
+        let db = memory_db().await;
+        let backend = backend();
+        db::inventory::set_stock(&db, "prod_eggs", Some(10))
+            .await
+            .unwrap();
+
+        let checkout_session_id = checkout(&db, &backend, &[("prod_eggs", 4)]).await;
+        assert_eq!(available(&db, "prod_eggs").await, Some(6));
+
+        backend
+            .expire_checkout_session(&checkout_session_id)
+            .unwrap();
+        deliver_webhook(&db, &backend, &checkout_session_id).await;
+
+        assert_eq!(available(&db, "prod_eggs").await, Some(10));
+        assert!(
+            db::orders::get_order_by_checkout_session(&db, &checkout_session_id)
+                .await
+                .unwrap()
+                .is_none()
+        );
+    });
+}